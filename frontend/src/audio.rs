@@ -1,7 +1,27 @@
+mod bundle;
+mod bus;
+mod clock;
 mod ctx;
 mod devices;
 mod effects;
+mod inputs;
+mod metronome;
+mod midi;
+mod options;
+mod recorder;
+mod synth;
 
-pub use ctx::AudioGraph;
-pub use devices::{get_devices, InputDeviceInfo};
+pub use bus::{MonitorBus, SendBus, SendStream};
+pub use clock::{provide_audio_clock, use_audio_clock, AudioClock};
+pub use ctx::{open_device, stop_tracks, AudioGraph, AudioTrack};
+pub use devices::{get_devices, on_device_change, InputDeviceInfo};
 pub use effects::{analyse, gain};
+pub use inputs::{InputChannels, InputSpec};
+pub use metronome::Metronome;
+pub use midi::{
+    list_outputs, listen_to_inputs, midi_access, provide_midi_routing, send_to_output, silence_output,
+    use_midi_routing, MidiClock, MidiRouting,
+};
+pub use options::{AudioSettings, LatencyHint, SAMPLE_RATES};
+pub use recorder::{download, RecordSource, Recording};
+pub use synth::Synth;
@@ -0,0 +1,171 @@
+/// Packs files into a zip archive without compressing them, recorded audio
+/// is compressed already. Sizes and offsets have to fit the plain zip
+/// format, archives that would need zip64 are refused.
+pub fn zip(files: &[(String, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    let too_large = |_| String::from("The recording is too large for a zip archive");
+    let entries =
+        u16::try_from(files.len()).map_err(|_| String::from("Too many files for a zip archive"))?;
+    let mut archive = vec![];
+    let mut directory = vec![];
+
+    for (name, data) in files {
+        let offset = limit(archive.len()).map_err(too_large)?;
+        let header = Header {
+            name: name.as_bytes(),
+            name_len: u16::try_from(name.len())
+                .map_err(|_| format!("File name too long: {name}"))?,
+            crc: crc32(data),
+            size: limit(data.len()).map_err(too_large)?,
+        };
+
+        put_u32(&mut archive, 0x04034b50);
+        header.write(&mut archive);
+        archive.extend_from_slice(header.name);
+        archive.extend_from_slice(data);
+
+        put_u32(&mut directory, 0x02014b50);
+        put_u16(&mut directory, 20);
+        header.write(&mut directory);
+        put_u16(&mut directory, 0); // comment
+        put_u16(&mut directory, 0); // disk
+        put_u16(&mut directory, 0); // internal attributes
+        put_u32(&mut directory, 0); // external attributes
+        put_u32(&mut directory, offset);
+        directory.extend_from_slice(header.name);
+    }
+
+    let directory_offset = limit(archive.len()).map_err(too_large)?;
+    let directory_size = limit(directory.len()).map_err(too_large)?;
+    limit(archive.len() + directory.len()).map_err(too_large)?;
+    archive.extend_from_slice(&directory);
+    put_u32(&mut archive, 0x06054b50);
+    put_u16(&mut archive, 0);
+    put_u16(&mut archive, 0);
+    put_u16(&mut archive, entries);
+    put_u16(&mut archive, entries);
+    put_u32(&mut archive, directory_size);
+    put_u32(&mut archive, directory_offset);
+    put_u16(&mut archive, 0);
+    Ok(archive)
+}
+
+/// All ones marks a zip64 field, so that is out of reach as well
+fn limit(value: usize) -> Result<u32, ()> {
+    u32::try_from(value)
+        .ok()
+        .filter(|v| *v < u32::MAX)
+        .ok_or(())
+}
+
+/// The part the local header and the central directory have in common
+struct Header<'a> {
+    name: &'a [u8],
+    name_len: u16,
+    crc: u32,
+    size: u32,
+}
+
+impl Header<'_> {
+    fn write(&self, out: &mut Vec<u8>) {
+        put_u16(out, 20); // version needed, plain storing
+        put_u16(out, 0x0800); // names are UTF-8
+        put_u16(out, 0); // stored
+        put_u16(out, 0); // time
+        put_u16(out, 0x21); // 1980-01-01
+        put_u32(out, self.crc);
+        put_u32(out, self.size);
+        put_u32(out, self.size);
+        put_u16(out, self.name_len);
+        put_u16(out, 0); // extra
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_matches_the_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b"hello"), 0x3610a686);
+    }
+
+    #[test]
+    fn writes_a_stored_archive() {
+        let archive = zip(&[(String::from("a.txt"), b"hello".to_vec())]).unwrap();
+
+        #[rustfmt::skip]
+        let expected: Vec<u8> = [
+            // Local header
+            &[0x50, 0x4b, 0x03, 0x04, 20, 0, 0x00, 0x08, 0, 0, 0, 0, 0x21, 0][..],
+            &[0x86, 0xa6, 0x10, 0x36, 5, 0, 0, 0, 5, 0, 0, 0, 5, 0, 0, 0],
+            b"a.txt",
+            b"hello",
+            // Central directory
+            &[0x50, 0x4b, 0x01, 0x02, 20, 0, 20, 0, 0x00, 0x08, 0, 0, 0, 0, 0x21, 0],
+            &[0x86, 0xa6, 0x10, 0x36, 5, 0, 0, 0, 5, 0, 0, 0, 5, 0, 0, 0],
+            &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            b"a.txt",
+            // End of central directory, 51 bytes of it at offset 40
+            &[0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0, 1, 0, 1, 0],
+            &[51, 0, 0, 0, 40, 0, 0, 0, 0, 0],
+        ]
+        .concat();
+        assert_eq!(archive, expected);
+    }
+
+    #[test]
+    fn entries_follow_each_other() {
+        let files = [
+            (String::from("one"), vec![1; 10]),
+            (String::from("two"), vec![2; 20]),
+        ];
+        let archive = zip(&files).unwrap();
+
+        let u32_at = |i: usize| u32::from_le_bytes(archive[i..i + 4].try_into().unwrap());
+        let u16_at = |i: usize| u16::from_le_bytes(archive[i..i + 2].try_into().unwrap());
+        let end = archive.len() - 22;
+        assert_eq!(u32_at(end), 0x06054b50);
+        assert_eq!(u16_at(end + 10), 2);
+
+        // The second central directory entry points at the second file
+        let directory = u32_at(end + 16) as usize;
+        let second = directory + 46 + 3;
+        assert_eq!(u32_at(second), 0x02014b50);
+        assert_eq!(u32_at(second + 42), 30 + 3 + 10);
+        assert_eq!(u32_at(second + 16), crc32(&[2; 20]));
+    }
+
+    #[test]
+    fn refuses_what_needs_zip64() {
+        assert!(limit(u32::MAX as usize - 1).is_ok());
+        assert!(limit(u32::MAX as usize).is_err());
+
+        let files = vec![(String::new(), vec![]); usize::from(u16::MAX) + 1];
+        assert!(zip(&files).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use wasm_bindgen::prelude::*;
+use web_sys::{
+    AnalyserNode, AudioContext, AudioNode, DynamicsCompressorNode, GainNode, MediaStream,
+    MediaStreamAudioDestinationNode, MediaStreamTrack,
+};
+
+use super::{analyse, gain};
+
+/// What I hear: remote members, the click and my own input, each with a
+/// level of its own, into the speakers
+#[derive(Clone, Debug)]
+pub struct MonitorBus {
+    /// My own input before it joins the rest of the monitor mix
+    pub input: GainNode,
+    /// The metronome, only I hear it, it isn't sent to the others
+    pub click: GainNode,
+    pub level: GainNode,
+    pub analyser: AnalyserNode,
+}
+
+impl MonitorBus {
+    pub fn new(ctx: &AudioContext) -> Result<MonitorBus, JsValue> {
+        let level = gain(ctx)?;
+        let analyser = analyse(&level, ctx)?;
+        let input = gain(ctx)?;
+        input.connect_with_audio_node(&level)?;
+        let click = gain(ctx)?;
+        click.connect_with_audio_node(&level)?;
+        Ok(MonitorBus {
+            input,
+            click,
+            level,
+            analyser,
+        })
+    }
+
+    /// Where remote members and other sources join the mix
+    pub fn mix(&self) -> &AudioNode {
+        &self.level
+    }
+
+    pub fn connect(&self, destination: &AudioNode) -> Result<(), JsValue> {
+        self.level.connect_with_audio_node(destination)?;
+        Ok(())
+    }
+}
+
+/// One input on its way to the others, sent as a stream of its own so
+/// they can mix it independently
+#[derive(Clone, Debug, PartialEq)]
+pub struct SendStream {
+    pub stream: MediaStream,
+    pub label: String,
+}
+
+#[derive(Clone, Debug)]
+struct SendChannel {
+    id: String,
+    label: String,
+    compressor: DynamicsCompressorNode,
+    level: GainNode,
+    output: MediaStreamAudioDestinationNode,
+}
+
+/// What the others hear: each of my inputs through a compressor that keeps
+/// peaks from clipping the encoder, into the stream it gets sent in
+#[derive(Clone, Debug)]
+pub struct SendBus {
+    ctx: AudioContext,
+    channels: Vec<SendChannel>,
+    /// Sums the channels for metering only
+    meter: GainNode,
+    pub analyser: AnalyserNode,
+}
+
+impl SendBus {
+    pub fn new(ctx: &AudioContext) -> Result<SendBus, JsValue> {
+        let meter = gain(ctx)?;
+        let analyser = analyse(&meter, ctx)?;
+        Ok(SendBus {
+            ctx: ctx.clone(),
+            channels: vec![],
+            meter,
+            analyser,
+        })
+    }
+
+    /// Sends whatever comes out of `from` as a new stream
+    pub fn add(&mut self, id: &str, label: &str, from: &AudioNode) -> Result<(), JsValue> {
+        let compressor = self.ctx.create_dynamics_compressor()?;
+        compressor.threshold().set_value(-12.0);
+        compressor.knee().set_value(6.0);
+        compressor.ratio().set_value(4.0);
+        compressor.attack().set_value(0.003);
+        compressor.release().set_value(0.1);
+
+        let level = gain(&self.ctx)?;
+        let output = self.ctx.create_media_stream_destination()?;
+        from.connect_with_audio_node(&compressor)?
+            .connect_with_audio_node(&level)?
+            .connect_with_audio_node(&output)?;
+        level.connect_with_audio_node(&self.meter)?;
+
+        self.channels.push(SendChannel {
+            id: id.to_string(),
+            label: label.to_string(),
+            compressor,
+            level,
+            output,
+        });
+        Ok(())
+    }
+
+    /// Renames a channel, what it sends stays the same
+    pub fn relabel(&mut self, id: &str, label: &str) {
+        for channel in self.channels.iter_mut().filter(|c| c.id == id) {
+            channel.label = label.to_string();
+        }
+    }
+
+    /// Stops sending a channel, its stream ends for the others
+    pub fn remove(&mut self, id: &str) {
+        for channel in self.channels.iter().filter(|c| c.id == id) {
+            channel.compressor.disconnect().ok();
+            channel.level.disconnect().ok();
+            for track in channel.output.stream().get_tracks().iter() {
+                MediaStreamTrack::from(track).stop();
+            }
+        }
+        self.channels.retain(|c| c.id != id);
+    }
+
+    /// Level controls of every channel, they all follow the bus level
+    pub fn levels(&self) -> impl Iterator<Item = &GainNode> {
+        self.channels.iter().map(|c| &c.level)
+    }
+
+    pub fn streams(&self) -> Vec<SendStream> {
+        self.channels
+            .iter()
+            .map(|c| SendStream {
+                stream: c.output.stream(),
+                label: c.label.clone(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,55 @@
+use leptos::*;
+use wasm_bindgen::prelude::*;
+use web_sys::{AudioContext, AudioContextState};
+
+/// The time base used for measuring latency between members, the audio
+/// context's clock as that is what sound is scheduled against. It stands
+/// still while the context doesn't run, so every state change starts a new
+/// epoch and times from different epochs don't compare.
+#[derive(Clone, Copy)]
+pub struct AudioClock(StoredValue<ClockState>);
+
+#[derive(Default)]
+struct ClockState {
+    ctx: Option<AudioContext>,
+    epoch: u32,
+    on_change: Option<Closure<dyn FnMut()>>,
+}
+
+impl AudioClock {
+    pub fn set_context(&self, ctx: AudioContext) {
+        let state = self.0;
+        let on_change = Closure::wrap(Box::new(move || {
+            state.try_update_value(|s| s.epoch = s.epoch.wrapping_add(1));
+        }) as Box<dyn FnMut()>);
+        ctx.set_onstatechange(Some(on_change.as_ref().unchecked_ref()));
+
+        self.0.update_value(|s| {
+            if let Some(old) = &s.ctx {
+                old.set_onstatechange(None);
+            }
+            s.ctx = Some(ctx);
+            s.epoch = s.epoch.wrapping_add(1);
+            s.on_change = Some(on_change);
+        });
+    }
+
+    /// Milliseconds and the epoch they count in, nothing while the context
+    /// isn't running
+    pub fn now(&self) -> Option<(f64, u32)> {
+        self.0.with_value(|s| {
+            s.ctx
+                .as_ref()
+                .filter(|ctx| ctx.state() == AudioContextState::Running)
+                .map(|ctx| (ctx.current_time() * 1000.0, s.epoch))
+        })
+    }
+}
+
+pub fn provide_audio_clock() {
+    provide_context(AudioClock(store_value(ClockState::default())));
+}
+
+pub fn use_audio_clock() -> AudioClock {
+    expect_context::<AudioClock>()
+}
@@ -1,26 +1,46 @@
 use gloo_console::log;
+use js_sys::{Function, Promise, Reflect};
 use leptos::*;
+use protocol::mixer::{ChannelKey, MixerState, SEND_BUS};
 use serde_json::json;
 use uuid::Uuid;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{
-    window, AnalyserNode, AudioContext, GainNode, MediaStream, MediaStreamAudioSourceNode,
-    MediaStreamTrack,
+    window, AnalyserNode, AudioContext, AudioNode, AudioParam, GainNode, HtmlAudioElement, MediaStream,
+    MediaStreamAudioSourceNode, MediaStreamConstraints, MediaStreamTrack, StereoPannerNode,
 };
 
-use super::{analyse, gain};
+use super::{
+    AudioSettings, InputChannels, InputSpec, Metronome, MonitorBus, RecordSource, Recording,
+    SendBus, SendStream, Synth,
+};
 
+/// A channel strip: source -> gain -> panner -> analyser, with a send taken
+/// from the source so the fader only shapes what I hear
 #[derive(Clone, Debug)]
 pub struct AudioTrack {
+    /// Id of the `MediaStreamTrack`, or of the `InputSpec` for local tracks
+    pub id: String,
     pub owner: Uuid,
     pub label: String,
     stream: MediaStream,
     source: MediaStreamAudioSourceNode,
+    /// The channels the strip takes from its source
+    picked: AudioNode,
+    /// Where the strip starts, before any processing. It stays when the
+    /// source is switched, so whatever listens here carries on.
+    feed: GainNode,
     pub gain: GainNode,
+    pub panner: StereoPannerNode,
     pub analyser: AnalyserNode,
+    /// Level into the send bus, only connected for local tracks
+    pub send: GainNode,
+    /// What a local track was opened from
+    pub input: Option<InputSpec>,
 }
 impl AudioTrack {
+    /// A strip for a track received from another member
     pub fn new(
         ctx: &AudioContext,
         owner: Uuid,
@@ -29,24 +49,130 @@ impl AudioTrack {
         let stream = MediaStream::new()?;
         stream.add_track(track);
         let source = ctx.create_media_stream_source(&stream)?;
+        let input = source.clone();
+        Self::build(ctx, track.id(), owner, track.label(), stream, source, &input)
+    }
+
+    /// A strip for one of my inputs, taking its channels from the device
+    /// stream
+    pub fn from_input(
+        ctx: &AudioContext,
+        owner: Uuid,
+        spec: &InputSpec,
+        stream: MediaStream,
+    ) -> Result<AudioTrack, JsValue> {
+        let (source, input) = Self::open_input(ctx, spec, &stream)?;
+        let mut track =
+            Self::build(ctx, spec.id.to_string(), owner, spec.name.clone(), stream, source, &input)?;
+        track.input = Some(spec.clone());
+        Ok(track)
+    }
+
+    /// Feeds the strip from another device stream. Everything after the
+    /// source stays, so the mix and what the others receive carry on.
+    pub fn switch_input(
+        &mut self,
+        ctx: &AudioContext,
+        spec: &InputSpec,
+        stream: MediaStream,
+    ) -> Result<(), JsValue> {
+        let (source, input) = Self::open_input(ctx, spec, &stream)?;
+        input.connect_with_audio_node(&self.feed)?;
+        self.release();
+        self.picked.disconnect().ok();
+        self.stream = stream;
+        self.source = source;
+        self.picked = input;
+        self.label = spec.name.clone();
+        self.input = Some(spec.clone());
+        Ok(())
+    }
+
+    fn open_input(
+        ctx: &AudioContext,
+        spec: &InputSpec,
+        stream: &MediaStream,
+    ) -> Result<(MediaStreamAudioSourceNode, AudioNode), JsValue> {
+        let source = ctx.create_media_stream_source(stream)?;
+        let input: AudioNode = match spec.channels {
+            InputChannels::All => source.clone().into(),
+            InputChannels::Mono(first) | InputChannels::Stereo(first) => {
+                let count = if matches!(spec.channels, InputChannels::Mono(_)) { 1 } else { 2 };
+                let splitter = ctx.create_channel_splitter_with_number_of_outputs(first + count)?;
+                let merger = ctx.create_channel_merger_with_number_of_inputs(count)?;
+                source.connect_with_audio_node(&splitter)?;
+                for i in 0..count {
+                    splitter.connect_with_audio_node_and_output_and_input(&merger, first + i, i)?;
+                }
+                merger.into()
+            }
+        };
+        Ok((source, input))
+    }
+
+    fn build(
+        ctx: &AudioContext,
+        id: String,
+        owner: Uuid,
+        label: String,
+        stream: MediaStream,
+        source: MediaStreamAudioSourceNode,
+        input: &AudioNode,
+    ) -> Result<AudioTrack, JsValue> {
+        let feed = ctx.create_gain()?;
         let gain = ctx.create_gain()?;
+        let panner = ctx.create_stereo_panner()?;
         let analyser = ctx.create_analyser()?;
+        let send = ctx.create_gain()?;
 
-        source
-            .connect_with_audio_node(&gain)?
+        input.connect_with_audio_node(&feed)?;
+        feed.connect_with_audio_node(&send)?;
+        feed.connect_with_audio_node(&gain)?
+            .connect_with_audio_node(&panner)?
             .connect_with_audio_node(&analyser)?;
 
         let track = AudioTrack {
+            id,
             owner,
-            label: track.label(),
+            label,
             stream,
             source,
+            picked: input.clone(),
+            feed,
             gain,
+            panner,
             analyser,
+            send,
+            input: None,
         };
         Ok(track)
     }
 
+    /// Disconnects the strip and releases what feeds it
+    pub fn close(&self) {
+        self.release();
+        self.send.disconnect().ok();
+        self.panner.disconnect().ok();
+    }
+
+    /// Lets go of the device
+    fn release(&self) {
+        self.source.disconnect().ok();
+        stop_tracks(&self.stream);
+    }
+
+    pub fn key(&self) -> ChannelKey {
+        ChannelKey {
+            owner: self.owner,
+            track: self.id.clone(),
+        }
+    }
+
+    /// Where the strip's processed signal comes out
+    pub fn output(&self) -> &StereoPannerNode {
+        &self.panner
+    }
+
     pub fn get_track(&self) -> Result<MediaStreamTrack, JsValue> {
         let track = self.stream.get_audio_tracks().get(0);
         Ok(MediaStreamTrack::from(track))
@@ -59,30 +185,81 @@ pub struct AudioGraph {
     owner: Uuid,
     pub local_tracks: Vec<AudioTrack>,
     pub remote_tracks: Vec<AudioTrack>,
-    pub gain: GainNode,
-    pub analyser: AnalyserNode,
+    pub monitor: MonitorBus,
+    pub send: SendBus,
+    /// Plays MIDI received from other members
+    pub synth: Synth,
+    pub metronome: Metronome,
+    /// Plays the monitor mix in browsers where the context itself can't
+    /// pick an output device
+    sink: Option<HtmlAudioElement>,
 }
 
 impl AudioGraph {
-    pub fn new() -> Result<AudioGraph, JsValue> {
-        let ctx = AudioContext::new()?;
-        let gain = gain(&ctx)?;
-        let analyser = analyse(&gain, &ctx)?;
+    pub fn new(settings: &AudioSettings) -> Result<AudioGraph, JsValue> {
+        let ctx = AudioContext::new_with_context_options(&settings.context_options())?;
+        let monitor = MonitorBus::new(&ctx)?;
+        let send = SendBus::new(&ctx)?;
+        let synth = Synth::new(&ctx)?;
+        synth.output.connect_with_audio_node(monitor.mix())?;
+        let metronome = Metronome::new(&ctx)?;
+        metronome.output.connect_with_audio_node(&monitor.click)?;
+
+        let sink = if can_set_sink(&ctx) {
+            None
+        } else {
+            let out = ctx.create_media_stream_destination()?;
+            monitor.connect(&out)?;
+            let element = HtmlAudioElement::new()?;
+            element.set_src_object(Some(&out.stream()));
+            Some(element)
+        };
 
         let graph = Self {
             owner: Uuid::new_v4(),
             ctx,
-            gain,
-            analyser,
+            monitor,
+            send,
+            synth,
+            metronome,
+            sink,
             local_tracks: vec![],
             remote_tracks: vec![],
         };
         Ok(graph)
     }
 
+    pub fn context(&self) -> &AudioContext {
+        &self.ctx
+    }
+
+    /// Seconds the context adds on its own and until its output is heard,
+    /// for browsers that report them
+    pub fn latencies(&self) -> (Option<f64>, Option<f64>) {
+        let latency = |key: &str| Reflect::get(&self.ctx, &JsValue::from_str(key)).ok()?.as_f64();
+        (latency("baseLatency"), latency("outputLatency"))
+    }
+
     pub fn connect(&mut self) -> Result<(), JsValue> {
-        let destination = self.ctx.destination();
-        self.gain.connect_with_audio_node(&destination)?;
+        match self.sink {
+            Some(_) => Ok(()),
+            None => self.monitor.connect(&self.ctx.destination()),
+        }
+    }
+
+    /// Plays the monitor mix on another device, an empty id picks the
+    /// system default
+    pub async fn set_output(&self, device: &str) -> Result<(), JsValue> {
+        let target: &JsValue = match &self.sink {
+            Some(element) => element,
+            None => &self.ctx,
+        };
+        let set_sink_id = Reflect::get(target, &JsValue::from_str("setSinkId"))?;
+        if !set_sink_id.is_function() {
+            return Err(JsValue::from_str("This browser can't choose an output device"));
+        }
+        let picked = Function::from(set_sink_id).call1(target, &JsValue::from_str(device))?;
+        JsFuture::from(Promise::from(picked)).await?;
         Ok(())
     }
 
@@ -96,6 +273,10 @@ impl AudioGraph {
     }
 
     pub fn resume(&mut self) -> Result<(), JsValue> {
+        if let Some(element) = &self.sink {
+            // Playing needs a user gesture, which going live is
+            let _ = element.play()?;
+        }
         let fut = JsFuture::from(self.ctx.resume()?);
         spawn_local(async {
             log!("Resume");
@@ -104,54 +285,206 @@ impl AudioGraph {
         Ok(())
     }
 
-    pub async fn add_device(&mut self, device: &str) -> Result<(), JsValue> {
-        let audio: String = json!(
-        {
-            "latencyHint": 0.000001,
-            "latency": 0,
-            "echoCancellation": false,
-            "noiseSuppression": false,
-            "autoGainControl": false,
-            "deviceId":{
-                "exact": device
+    /// Applies what can be changed about an input without opening a
+    /// device, returns whether it still needs one from `open_device`
+    pub fn update_device(&mut self, spec: &InputSpec) -> bool {
+        let id = spec.id.to_string();
+        let Some(track) = self.local_tracks.iter_mut().find(|t| t.id == id) else {
+            return true;
+        };
+        match &track.input {
+            Some(open) if open == spec => false,
+            Some(open) if open.device == spec.device && open.channels == spec.channels => {
+                track.label = spec.name.clone();
+                track.input = Some(spec.clone());
+                self.send.relabel(&id, &spec.name);
+                false
             }
-        })
-        .to_string();
-
-        let mut constraints = web_sys::MediaStreamConstraints::new();
-        constraints.audio(&JsValue::from(&audio));
+            _ => true,
+        }
+    }
 
-        let devices = window().expect("Oh my god").navigator().media_devices()?;
+    /// Starts sending an input from a stream opened by `open_device`. An
+    /// input that is open already moves over to the new stream in place,
+    /// the others keep receiving the same track and nothing needs to be
+    /// renegotiated.
+    pub fn add_device(&mut self, spec: &InputSpec, stream: MediaStream) -> Result<(), JsValue> {
+        if !self.update_device(spec) {
+            // Opened twice while the first one was on its way
+            stop_tracks(&stream);
+            return Ok(());
+        }
+        let id = spec.id.to_string();
+        if let Some(track) = self.local_tracks.iter_mut().find(|t| t.id == id) {
+            log!(format!("Switching {} to {}", spec.name, spec.device));
+            if let Err(e) = track.switch_input(&self.ctx, spec, stream.clone()) {
+                stop_tracks(&stream);
+                return Err(e);
+            }
+            self.send.relabel(&id, &spec.name);
+            return Ok(());
+        }
 
-        let stream = devices.get_user_media_with_constraints(&constraints)?;
-        let stream = JsFuture::from(stream).await?;
-        let stream = MediaStream::from(stream);
+        let track = match AudioTrack::from_input(&self.ctx, self.owner, spec, stream.clone()) {
+            Ok(track) => track,
+            Err(e) => {
+                stop_tracks(&stream);
+                return Err(e);
+            }
+        };
+        track.output().connect_with_audio_node(&self.monitor.input)?;
+        self.send.add(&track.id, &track.label, &track.send)?;
+        self.local_tracks.push(track);
+        Ok(())
+    }
 
-        let tracks: Vec<MediaStreamTrack> = stream
-            .get_audio_tracks()
-            .iter()
-            .map(|j_track| MediaStreamTrack::from(j_track))
-            .collect();
-        for track in &tracks {
-            let track = AudioTrack::new(&self.ctx, self.owner, track)?;
-            track.gain.connect_with_audio_node(&self.gain)?;
-            self.local_tracks.push(track);
+    /// Closes an input, the others stop receiving it
+    pub fn remove_device(&mut self, id: &str) {
+        for track in self.local_tracks.iter().filter(|t| t.id == id) {
+            track.close();
         }
-        Ok(())
+        self.local_tracks.retain(|t| t.id != id);
+        self.send.remove(id);
     }
 
+    /// Adds a remote track, tracks that already have a strip are ignored
     pub fn add_input(&mut self, owner: Uuid, track: MediaStreamTrack) -> Result<(), JsValue> {
+        if self.remote_tracks.iter().any(|t| t.id == track.id()) {
+            return Ok(());
+        }
         let track = AudioTrack::new(&self.ctx, owner, &track)?;
-        track.gain.connect_with_audio_node(&self.gain)?;
+        track.output().connect_with_audio_node(self.monitor.mix())?;
         self.remote_tracks.push(track);
         Ok(())
     }
 
-    pub fn stream(&self) -> Result<MediaStream, JsValue> {
-        let out_stream = self.ctx.create_media_stream_destination().unwrap().stream();
+    /// Drops the strip of a remote track that ended or went away
+    pub fn remove_input(&mut self, id: &str) {
+        for track in self.remote_tracks.iter().filter(|t| t.id == id) {
+            track.close();
+        }
+        self.remote_tracks.retain(|t| t.id != id);
+    }
+
+    fn tracks(&self) -> impl Iterator<Item = &AudioTrack> {
+        self.local_tracks.iter().chain(self.remote_tracks.iter())
+    }
+
+    pub fn is_local(&self, key: &ChannelKey) -> bool {
+        key.owner == self.owner
+    }
+
+    pub fn track(&self, key: &ChannelKey) -> Option<&AudioTrack> {
+        self.tracks().find(|track| track.key() == *key)
+    }
+
+    /// The tracks the mixer needs a channel for, with their labels
+    pub fn channels(&self) -> Vec<(ChannelKey, String)> {
+        self.tracks()
+            .map(|track| (track.key(), track.label.clone()))
+            .collect()
+    }
+
+    /// Makes the nodes follow the mixer, tracks without a channel are left
+    /// as they are. With a `fade` in seconds the parameters glide to their
+    /// new values instead of jumping.
+    pub fn reconcile(&self, mixer: &MixerState, fade: f64) -> Result<(), JsValue> {
+        for track in self.tracks() {
+            let key = track.key();
+            let Some(channel) = mixer.channel(&key) else {
+                continue;
+            };
+            self.ramp(&track.gain.gain(), mixer.effective_gain(&key), fade)?;
+            self.ramp(&track.panner.pan(), channel.pan, fade)?;
+        }
         for track in &self.local_tracks {
-            out_stream.add_track(&track.get_track()?);
+            self.ramp(&track.send.gain(), mixer.send_gain(&track.key(), SEND_BUS), fade)?;
         }
-        Ok(out_stream)
+        self.ramp(&self.monitor.level.gain(), mixer.master_gain(), fade)?;
+        self.ramp(&self.monitor.input.gain(), mixer.input_monitor_gain(), fade)?;
+        self.ramp(&self.monitor.click.gain(), mixer.click_gain(), fade)?;
+        for level in self.send.levels() {
+            self.ramp(&level.gain(), mixer.send_bus_gain(), fade)?;
+        }
+        Ok(())
+    }
+
+    fn ramp(&self, param: &AudioParam, value: f32, fade: f64) -> Result<(), JsValue> {
+        let now = self.ctx.current_time();
+        param.cancel_scheduled_values(now)?;
+        if fade > 0.0 {
+            param.set_value_at_time(param.value(), now)?;
+            param.linear_ramp_to_value_at_time(value, now + fade)?;
+        } else {
+            param.set_value(value);
+        }
+        Ok(())
     }
+
+    /// What goes out to the other members, one stream per input
+    pub fn streams(&self) -> Vec<SendStream> {
+        self.send.streams()
+    }
+
+    /// Starts recording every track, named as in the mixer
+    pub fn record(&self, mixer: &MixerState) -> Result<Recording, JsValue> {
+        let sources = self
+            .tracks()
+            .map(|track| {
+                let key = track.key();
+                let label = mixer.channel(&key).map(|c| c.label.clone());
+                RecordSource {
+                    label: label.unwrap_or_else(|| track.label.clone()),
+                    owner: track.owner,
+                    local: self.is_local(&key),
+                    feed: track.feed.clone().into(),
+                }
+            })
+            .collect();
+        Recording::start(&self.ctx, sources)
+    }
+}
+
+/// Newer browsers route an AudioContext straight to any output
+fn can_set_sink(ctx: &AudioContext) -> bool {
+    Reflect::get(ctx, &JsValue::from_str("setSinkId")).is_ok_and(|f| f.is_function())
+}
+
+/// Asks for the device of an input, which may wait on the user allowing it.
+/// Nothing in the graph changes until the stream is handed to `add_device`.
+pub async fn open_device(spec: &InputSpec, sample_rate: f32) -> Result<MediaStream, JsValue> {
+    let devices = window().expect("Oh my god").navigator().media_devices()?;
+    let constraints = constraints(spec, sample_rate)?;
+    let stream = devices.get_user_media_with_constraints(&constraints)?;
+    Ok(MediaStream::from(JsFuture::from(stream).await?))
+}
+
+/// Stopping the tracks lets go of the device and turns off the browser's
+/// recording indicator
+pub fn stop_tracks(stream: &MediaStream) {
+    for track in stream.get_tracks().iter() {
+        MediaStreamTrack::from(track).stop();
+    }
+}
+
+/// Raw sound for musicians, all the processing meant for calls is off, at
+/// the context's rate so nothing gets resampled on the way in
+fn constraints(spec: &InputSpec, sample_rate: f32) -> Result<MediaStreamConstraints, JsValue> {
+    let mut audio = json!({
+        "latency": 0,
+        "sampleRate": { "ideal": sample_rate },
+        "echoCancellation": false,
+        "noiseSuppression": false,
+        "autoGainControl": false,
+        "deviceId": {
+            "exact": spec.device
+        }
+    });
+    if let Some(count) = spec.channels.needed() {
+        audio["channelCount"] = json!({ "ideal": count });
+    }
+
+    let mut constraints = MediaStreamConstraints::new();
+    constraints.audio(&js_sys::JSON::parse(&audio.to_string())?);
+    Ok(constraints)
 }
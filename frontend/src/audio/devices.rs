@@ -3,6 +3,7 @@ use wasm_bindgen::prelude::*;
 use web_sys::{self, window};
 use gloo_utils::format::JsValueSerdeExt;
 use serde::{Serialize,Deserialize};
+use leptos::{on_cleanup, Callable, Callback};
 
 pub async fn get_devices() -> Result<Vec<InputDeviceInfo>, JsValue> {
     let mut constraints = web_sys::MediaStreamConstraints::new();
@@ -25,3 +26,14 @@ pub struct InputDeviceInfo {
     pub kind:String,
     pub label:String,
 }
+
+/// Calls `on_change` whenever a device is plugged in or removed, until the
+/// owning component is cleaned up
+pub fn on_device_change(on_change: Callback<()>) -> Result<(), JsValue> {
+    let devices = window().expect("Oh my god").navigator().media_devices()?;
+    let cb = Closure::wrap(Box::new(move || on_change.call(())) as Box<dyn FnMut()>);
+    devices.set_ondevicechange(Some(cb.as_ref().unchecked_ref()));
+    cb.forget();
+    on_cleanup(move || devices.set_ondevicechange(None));
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Which channels of a device an input takes, zero based
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputChannels {
+    /// Whatever the device delivers, as it is
+    All,
+    Mono(u32),
+    /// The given channel and the one after it
+    Stereo(u32),
+}
+
+impl InputChannels {
+    /// How many channels the device has to open for this input
+    pub fn needed(&self) -> Option<u32> {
+        match self {
+            InputChannels::All => None,
+            InputChannels::Mono(first) => Some(first + 1),
+            InputChannels::Stereo(first) => Some(first + 2),
+        }
+    }
+
+    /// The choices offered for an interface with up to `count` channels
+    pub fn choices(count: u32) -> Vec<InputChannels> {
+        let mut choices = vec![InputChannels::All];
+        choices.extend((0..count).map(InputChannels::Mono));
+        choices.extend((0..count.saturating_sub(1)).step_by(2).map(InputChannels::Stereo));
+        choices
+    }
+
+    pub fn as_string(&self) -> String {
+        match self {
+            InputChannels::All => String::from("all"),
+            InputChannels::Mono(first) => format!("mono-{first}"),
+            InputChannels::Stereo(first) => format!("stereo-{first}"),
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            InputChannels::All => String::from("All channels"),
+            InputChannels::Mono(first) => format!("Channel {}", first + 1),
+            InputChannels::Stereo(first) => format!("Channels {}-{}", first + 1, first + 2),
+        }
+    }
+}
+
+impl std::str::FromStr for InputChannels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let channel = |n: &str| n.parse().map_err(|_| format!("Bad channel in {s}"));
+        match s.split_once('-') {
+            None if s == "all" => Ok(InputChannels::All),
+            Some(("mono", n)) => Ok(InputChannels::Mono(channel(n)?)),
+            Some(("stereo", n)) => Ok(InputChannels::Stereo(channel(n)?)),
+            _ => Err(format!("Unknown input channels {s}")),
+        }
+    }
+}
+
+/// One source of sound, e.g. a vocal mic on the first channel of an
+/// interface. Several inputs may use the same device, each taking its own
+/// channels, and each is sent to the others as a track of its own.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InputSpec {
+    pub id: Uuid,
+    pub device: String,
+    pub name: String,
+    pub channels: InputChannels,
+}
+
+impl InputSpec {
+    pub fn new(device: String, name: String, channels: InputChannels) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            device,
+            name,
+            channels,
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::{cell::Cell, rc::Rc};
+
+use wasm_bindgen::prelude::*;
+use web_sys::{AudioContext, GainNode};
+
+/// Seconds of beats scheduled ahead, more than a timer may run late
+const LOOKAHEAD: f64 = 0.1;
+/// Seconds a tick sounds
+const TICK: f64 = 0.03;
+const TICK_LEVEL: f32 = 0.5;
+
+/// A click to play along with. Ticks are scheduled on the audio clock a
+/// little ahead, so the timer calling `schedule` doesn't have to be exact.
+#[derive(Clone, Debug)]
+pub struct Metronome {
+    ctx: AudioContext,
+    pub output: GainNode,
+    /// When the next beat falls and where in the bar it is, nothing while
+    /// stopped
+    next: Rc<Cell<Option<(f64, u32)>>>,
+}
+
+impl Metronome {
+    pub fn new(ctx: &AudioContext) -> Result<Metronome, JsValue> {
+        Ok(Metronome {
+            ctx: ctx.clone(),
+            output: ctx.create_gain()?,
+            next: Rc::new(Cell::new(None)),
+        })
+    }
+
+    pub fn start(&self) {
+        self.next.set(Some((self.ctx.current_time() + LOOKAHEAD, 0)));
+    }
+
+    pub fn stop(&self) {
+        self.next.set(None);
+    }
+
+    /// Schedules the beats within the look ahead, to be called well within
+    /// it. The first beat of a bar is accented.
+    pub fn schedule(&self, bpm: f64, beats_per_bar: u32) -> Result<(), JsValue> {
+        let Some((next, mut beat)) = self.next.get() else {
+            return Ok(());
+        };
+        let now = self.ctx.current_time();
+        // After a stall carry on from now instead of catching up
+        let mut at = next.max(now);
+        while at < now + LOOKAHEAD {
+            self.tick(at, beat == 0)?;
+            at += 60.0 / bpm.max(1.0);
+            beat = (beat + 1) % beats_per_bar.max(1);
+        }
+        self.next.set(Some((at, beat)));
+        Ok(())
+    }
+
+    fn tick(&self, when: f64, accent: bool) -> Result<(), JsValue> {
+        let oscillator = self.ctx.create_oscillator()?;
+        let pitch = if accent { 1760.0 } else { 880.0 };
+        oscillator.frequency().set_value_at_time(pitch, when)?;
+
+        let envelope = self.ctx.create_gain()?;
+        envelope.gain().set_value_at_time(TICK_LEVEL, when)?;
+        envelope.gain().set_target_at_time(0.0, when, TICK / 3.0)?;
+
+        oscillator
+            .connect_with_audio_node(&envelope)?
+            .connect_with_audio_node(&self.output)?;
+        oscillator.start_with_when(when)?;
+        oscillator.stop_with_when(when + TICK)?;
+        Ok(())
+    }
+}
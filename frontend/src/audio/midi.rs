@@ -0,0 +1,136 @@
+use std::collections::{HashMap, HashSet};
+
+use js_sys::{Map, Uint8Array};
+use leptos::*;
+use protocol::{MidiMessage, ALL_NOTES_OFF};
+use uuid::Uuid;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{window, MidiAccess, MidiInput, MidiMessageEvent, MidiOutput};
+
+/// Asks for access to the MIDI devices of this computer
+pub async fn midi_access() -> Result<MidiAccess, JsValue> {
+    let navigator = window().expect("No window").navigator();
+    let access = JsFuture::from(navigator.request_midi_access()?).await?;
+    Ok(access.unchecked_into::<MidiAccess>())
+}
+
+/// Hands every message from all connected MIDI inputs to `on_message`,
+/// returns the names of the inputs
+pub fn listen_to_inputs(
+    access: &MidiAccess,
+    on_message: Callback<MidiMessage>,
+) -> Result<Vec<String>, JsValue> {
+    let inputs = access.inputs().unchecked_into::<Map>();
+    let mut names = vec![];
+
+    for input in inputs.values() {
+        let input = input?.unchecked_into::<MidiInput>();
+        names.push(input.name().unwrap_or_else(|| String::from("MIDI input")));
+
+        let onmidimessage = move |ev: MidiMessageEvent| {
+            if let Ok(data) = ev.data() {
+                on_message.call(MidiMessage {
+                    timestamp: ev.time_stamp(),
+                    data,
+                });
+            }
+        };
+        let cb = Closure::wrap(Box::new(onmidimessage) as Box<dyn FnMut(_)>);
+        input.set_onmidimessage(Some(cb.as_ref().unchecked_ref()));
+        cb.forget();
+    }
+    Ok(names)
+}
+
+/// The MIDI outputs of this computer, for playing what other members send
+/// on a synth of one's own
+pub fn list_outputs(access: &MidiAccess) -> Result<Vec<MidiOutput>, JsValue> {
+    let outputs = access.outputs().unchecked_into::<Map>();
+    outputs
+        .values()
+        .into_iter()
+        .map(|output| output.map(|output| output.unchecked_into::<MidiOutput>()))
+        .collect()
+}
+
+fn now() -> f64 {
+    window()
+        .and_then(|w| w.performance())
+        .map_or(0.0, |p| p.now())
+}
+
+/// Sends a message to an output `delay` seconds from now
+pub fn send_to_output(output: &MidiOutput, data: &[u8], delay: f64) -> Result<(), JsValue> {
+    output.send_with_timestamp(&Uint8Array::from(data), now() + delay.max(0.0) * 1000.0)
+}
+
+/// Ends whatever is sounding on an output, on every channel
+pub fn silence_output(output: &MidiOutput) -> Result<(), JsValue> {
+    for channel in 0..16 {
+        output.send(&Uint8Array::from(&[0xb0 | channel, ALL_NOTES_OFF, 0][..]))?;
+    }
+    Ok(())
+}
+
+/// Where MIDI from the other members goes. Each member is a source of its
+/// own that can be switched off, what comes through plays on the built-in
+/// synth or on a MIDI output of this computer.
+#[derive(Clone, Copy)]
+pub struct MidiRouting {
+    /// Members whose notes are left out. What ends notes still comes
+    /// through, so switching a member off mid chord leaves nothing hanging.
+    pub muted: RwSignal<HashSet<Uuid>>,
+    /// The built-in synth when unset
+    pub output: RwSignal<Option<MidiOutput>>,
+}
+
+impl MidiRouting {
+    pub fn passes(&self, owner: Uuid, message: &MidiMessage) -> bool {
+        message.ends_notes() || !self.muted.with_untracked(|muted| muted.contains(&owner))
+    }
+}
+
+pub fn provide_midi_routing() {
+    provide_context(MidiRouting {
+        muted: create_rw_signal(HashSet::new()),
+        output: create_rw_signal(None),
+    });
+}
+
+pub fn use_midi_routing() -> MidiRouting {
+    expect_context::<MidiRouting>()
+}
+
+/// How much later than its original spacing a remote note may arrive and
+/// still be played in time
+const MIDI_JITTER_MS: f64 = 10.0;
+
+/// Keeps the spacing between notes from remote members by playing them a
+/// small fixed delay after the fastest one that came through
+#[derive(Clone, Debug, Default)]
+pub struct MidiClock {
+    /// Smallest difference between our clock and a sender's stamps seen so far
+    offsets: HashMap<Uuid, f64>,
+}
+
+impl MidiClock {
+    /// Seconds to wait before playing a message stamped `timestamp`
+    pub fn delay(&mut self, owner: Uuid, timestamp: f64) -> f64 {
+        let now = now();
+        let offset = self
+            .offsets
+            .entry(owner)
+            .and_modify(|o| *o = o.min(now - timestamp))
+            .or_insert(now - timestamp);
+        (timestamp + *offset + MIDI_JITTER_MS - now) / 1000.0
+    }
+
+    pub fn owners(&self) -> Vec<Uuid> {
+        self.offsets.keys().cloned().collect()
+    }
+
+    pub fn forget(&mut self, owner: Uuid) {
+        self.offsets.remove(&owner);
+    }
+}
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+use web_sys::AudioContextOptions;
+
+/// Sample rates offered on the settings page, in Hz
+pub const SAMPLE_RATES: [u32; 3] = [44100, 48000, 96000];
+
+/// What the audio context should favour when picking its buffer sizes
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LatencyHint {
+    /// The smallest buffers the device manages without glitches
+    #[default]
+    Interactive,
+    Balanced,
+    /// Large buffers, easiest on the battery but far too slow to play along
+    Playback,
+}
+
+impl LatencyHint {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LatencyHint::Interactive => "interactive",
+            LatencyHint::Balanced => "balanced",
+            LatencyHint::Playback => "playback",
+        }
+    }
+}
+
+impl std::str::FromStr for LatencyHint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interactive" => Ok(LatencyHint::Interactive),
+            "balanced" => Ok(LatencyHint::Balanced),
+            "playback" => Ok(LatencyHint::Playback),
+            other => Err(format!("Unknown latency hint {other}")),
+        }
+    }
+}
+
+/// How the audio context is created, applies the next time it is
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct AudioSettings {
+    /// In Hz, the output device's own rate when unset
+    pub sample_rate: Option<u32>,
+    pub latency: LatencyHint,
+}
+
+impl AudioSettings {
+    pub fn context_options(&self) -> AudioContextOptions {
+        let mut options = AudioContextOptions::new();
+        options.latency_hint(&JsValue::from_str(self.latency.as_str()));
+        if let Some(rate) = self.sample_rate {
+            options.sample_rate(rate as f32);
+        }
+        options
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::Duration,
+};
+
+use js_sys::{Array, Date, Promise, Uint8Array};
+use leptos::{document, set_timeout};
+use serde::Serialize;
+use uuid::Uuid;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    window, AudioContext, AudioNode, Blob, BlobEvent, BlobPropertyBag, Event, HtmlAnchorElement,
+    MediaRecorder, MediaRecorderOptions, MediaStreamAudioDestinationNode, Url,
+};
+
+use super::bundle::zip;
+
+/// Opus where the browser records it, its default otherwise
+const MIME_TYPES: [&str; 2] = ["audio/webm;codecs=opus", "audio/ogg;codecs=opus"];
+
+/// A track to record, tapped before its fader so the take stays clean
+pub struct RecordSource {
+    pub label: String,
+    pub owner: Uuid,
+    pub local: bool,
+    pub feed: AudioNode,
+}
+
+struct Take {
+    source: RecordSource,
+    tap: MediaStreamAudioDestinationNode,
+    recorder: MediaRecorder,
+    chunks: Rc<RefCell<Vec<Blob>>>,
+    /// Page clock milliseconds when the recorder got going
+    started: Rc<Cell<Option<f64>>>,
+    _on_data: Closure<dyn FnMut(BlobEvent)>,
+    _on_start: Closure<dyn FnMut(Event)>,
+}
+
+/// Written next to the takes so they can be lined up in an editor
+#[derive(Serialize)]
+struct Manifest {
+    /// Unix timestamp in milliseconds
+    started_at: f64,
+    sample_rate: f32,
+    takes: Vec<TakeInfo>,
+}
+
+#[derive(Serialize)]
+struct TakeInfo {
+    file: String,
+    label: String,
+    owner: Uuid,
+    local: bool,
+    /// How long after the shared start the take began, in milliseconds
+    offset: Option<f64>,
+}
+
+/// Every track of the session recorded into a file of its own, all started
+/// together
+pub struct Recording {
+    started_at: f64,
+    /// Page clock milliseconds at the shared start
+    start: f64,
+    sample_rate: f32,
+    takes: Vec<Take>,
+}
+
+impl Recording {
+    pub fn start(ctx: &AudioContext, sources: Vec<RecordSource>) -> Result<Recording, JsValue> {
+        let mut options = MediaRecorderOptions::new();
+        if let Some(mime) = MIME_TYPES.into_iter().find(|m| MediaRecorder::is_type_supported(m)) {
+            options.mime_type(mime);
+        }
+
+        // Everything is set up before the first recorder starts, to keep
+        // the starts close together
+        let mut takes = vec![];
+        for source in sources {
+            let tap = ctx.create_media_stream_destination()?;
+            source.feed.connect_with_audio_node(&tap)?;
+            let recorder = MediaRecorder::new_with_media_stream_and_media_recorder_options(
+                &tap.stream(),
+                &options,
+            )?;
+
+            let chunks = Rc::new(RefCell::new(vec![]));
+            let on_data = {
+                let chunks = chunks.clone();
+                Closure::wrap(Box::new(move |ev: BlobEvent| {
+                    if let Some(data) = ev.data() {
+                        chunks.borrow_mut().push(data);
+                    }
+                }) as Box<dyn FnMut(_)>)
+            };
+            recorder.set_ondataavailable(Some(on_data.as_ref().unchecked_ref()));
+
+            let started = Rc::new(Cell::new(None));
+            let on_start = {
+                let started = started.clone();
+                Closure::wrap(Box::new(move |ev: Event| started.set(Some(ev.time_stamp())))
+                    as Box<dyn FnMut(_)>)
+            };
+            recorder.set_onstart(Some(on_start.as_ref().unchecked_ref()));
+
+            takes.push(Take {
+                source,
+                tap,
+                recorder,
+                chunks,
+                started,
+                _on_data: on_data,
+                _on_start: on_start,
+            });
+        }
+
+        let start = window()
+            .and_then(|w| w.performance())
+            .map_or(0.0, |p| p.now());
+        for take in &takes {
+            take.recorder.start()?;
+        }
+        Ok(Recording {
+            started_at: Date::now(),
+            start,
+            sample_rate: ctx.sample_rate(),
+            takes,
+        })
+    }
+
+    pub fn tracks(&self) -> usize {
+        self.takes.len()
+    }
+
+    /// Stops all takes and throws them away
+    pub fn discard(self) {
+        for take in &self.takes {
+            take.recorder.set_ondataavailable(None);
+            take.recorder.stop().ok();
+            take.source.feed.disconnect_with_audio_node(&take.tap).ok();
+        }
+    }
+
+    /// Stops all takes and bundles them into a zip archive with a
+    /// `session.json` describing them
+    pub async fn finish(self) -> Result<Vec<u8>, JsValue> {
+        let mut stopped = vec![];
+        for take in &self.takes {
+            stopped.push(Promise::new(&mut |resolve, _| {
+                take.recorder.set_onstop(Some(&resolve));
+            }));
+            take.recorder.stop()?;
+        }
+
+        let mut files = vec![];
+        let mut infos = vec![];
+        for (i, (take, stopped)) in self.takes.iter().zip(stopped).enumerate() {
+            JsFuture::from(stopped).await?;
+            take.source.feed.disconnect_with_audio_node(&take.tap).ok();
+
+            let mime = take.recorder.mime_type();
+            let parts = take.chunks.borrow().iter().collect::<Array>();
+            let mut properties = BlobPropertyBag::new();
+            properties.type_(&mime);
+            let blob = Blob::new_with_blob_sequence_and_options(&parts, &properties)?;
+            let data = Uint8Array::new(&JsFuture::from(blob.array_buffer()).await?).to_vec();
+
+            let label = &take.source.label;
+            let file = format!("{:02} {}.{}", i + 1, file_name(label), extension(&mime));
+            infos.push(TakeInfo {
+                file: file.clone(),
+                label: label.clone(),
+                owner: take.source.owner,
+                local: take.source.local,
+                offset: take.started.get().map(|started| started - self.start),
+            });
+            files.push((file, data));
+        }
+
+        let manifest = Manifest {
+            started_at: self.started_at,
+            sample_rate: self.sample_rate,
+            takes: infos,
+        };
+        let manifest = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+        files.push((String::from("session.json"), manifest));
+        Ok(zip(&files)?)
+    }
+}
+
+/// Hands a zip archive to the browser as a download
+pub fn download(archive: &[u8], name: &str) -> Result<(), JsValue> {
+    let parts = Array::of1(&Uint8Array::from(archive));
+    let mut properties = BlobPropertyBag::new();
+    properties.type_("application/zip");
+    let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &properties)?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+
+    let link: HtmlAnchorElement = document().create_element("a")?.dyn_into()?;
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    // The download has to get hold of the data before it goes
+    set_timeout(
+        move || Url::revoke_object_url(&url).unwrap_or_default(),
+        Duration::from_secs(10),
+    );
+    Ok(())
+}
+
+/// Labels are member and input names, keep them but nothing a file system
+/// could choke on
+fn file_name(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == ' ' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+fn extension(mime: &str) -> &'static str {
+    match mime.split(';').next().unwrap_or_default() {
+        "audio/webm" => "webm",
+        "audio/ogg" => "ogg",
+        "audio/mp4" => "m4a",
+        _ => "audio",
+    }
+}
@@ -0,0 +1,132 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use protocol::{ALL_NOTES_OFF, ALL_SOUND_OFF};
+use uuid::Uuid;
+use wasm_bindgen::prelude::*;
+use web_sys::{AudioContext, GainNode, OscillatorNode, OscillatorType};
+
+/// Seconds for a note to fade in and out
+const ATTACK: f64 = 0.005;
+const RELEASE: f64 = 0.08;
+/// Keeps a handful of full velocity notes from clipping
+const VOICE_LEVEL: f32 = 0.2;
+/// Seconds a note may sound before it is released anyway, in case its note
+/// off never arrives
+const MAX_NOTE: f64 = 20.0;
+
+type Key = (Uuid, u8, u8);
+
+#[derive(Clone, Debug)]
+struct Voice {
+    oscillator: OscillatorNode,
+    envelope: GainNode,
+}
+
+/// A small polyphonic synth that makes received MIDI notes audible
+#[derive(Clone, Debug)]
+pub struct Synth {
+    ctx: AudioContext,
+    pub output: GainNode,
+    /// Sounding notes by sender, MIDI channel and note number
+    voices: Rc<RefCell<HashMap<Key, Voice>>>,
+    /// When notes that weren't sounding were ended. Note offs travel on a
+    /// channel of their own and may overtake their note on.
+    ended: Rc<RefCell<HashMap<Key, f64>>>,
+}
+
+fn frequency(note: u8) -> f32 {
+    440.0 * 2f32.powf((f32::from(note) - 69.0) / 12.0)
+}
+
+impl Synth {
+    pub fn new(ctx: &AudioContext) -> Result<Synth, JsValue> {
+        Ok(Synth {
+            ctx: ctx.clone(),
+            output: ctx.create_gain()?,
+            voices: Rc::new(RefCell::new(HashMap::new())),
+            ended: Rc::new(RefCell::new(HashMap::new())),
+        })
+    }
+
+    /// Plays a MIDI message from `owner`, `delay` seconds from now. Only
+    /// note on, note off and all notes/sound off are understood.
+    pub fn play(&self, owner: Uuid, data: &[u8], delay: f64) -> Result<(), JsValue> {
+        let [status, note, velocity, ..] = *data else {
+            return Ok(());
+        };
+        let when = self.ctx.current_time() + delay.max(0.0);
+        let channel = status & 0x0f;
+        match status & 0xf0 {
+            0x90 if velocity > 0 => self.note_on((owner, channel, note), velocity, when),
+            0x80 | 0x90 => self.note_off((owner, channel, note), when),
+            0xb0 if note == ALL_SOUND_OFF || note == ALL_NOTES_OFF => {
+                self.release(|(o, c, _)| *o == owner && *c == channel, when)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn note_on(&self, key: Key, velocity: u8, when: f64) -> Result<(), JsValue> {
+        if self.ended.borrow_mut().remove(&key).is_some_and(|ended| ended >= when) {
+            return Ok(());
+        }
+        self.stop(key, when)?;
+
+        let oscillator = self.ctx.create_oscillator()?;
+        oscillator.set_type(OscillatorType::Triangle);
+        oscillator.frequency().set_value_at_time(frequency(key.2), when)?;
+
+        let envelope = self.ctx.create_gain()?;
+        let level = VOICE_LEVEL * f32::from(velocity) / 127.0;
+        envelope.gain().set_value_at_time(0.0, when)?;
+        envelope.gain().linear_ramp_to_value_at_time(level, when + ATTACK)?;
+
+        oscillator
+            .connect_with_audio_node(&envelope)?
+            .connect_with_audio_node(&self.output)?;
+        oscillator.start_with_when(when)?;
+
+        let voice = Voice { oscillator, envelope };
+        fade_out(&voice, when + MAX_NOTE)?;
+        self.voices.borrow_mut().insert(key, voice);
+        Ok(())
+    }
+
+    fn note_off(&self, key: Key, when: f64) -> Result<(), JsValue> {
+        if !self.stop(key, when)? {
+            self.ended.borrow_mut().insert(key, when);
+        }
+        Ok(())
+    }
+
+    /// Returns whether the note was sounding
+    fn stop(&self, key: Key, when: f64) -> Result<bool, JsValue> {
+        let Some(voice) = self.voices.borrow_mut().remove(&key) else {
+            return Ok(false);
+        };
+        fade_out(&voice, when)?;
+        Ok(true)
+    }
+
+    fn release(&self, matches: impl Fn(&Key) -> bool, when: f64) -> Result<(), JsValue> {
+        let keys: Vec<Key> = self.voices.borrow().keys().filter(|k| matches(k)).cloned().collect();
+        for key in keys {
+            self.stop(key, when)?;
+        }
+        self.ended.borrow_mut().retain(|key, _| !matches(key));
+        Ok(())
+    }
+
+    /// Silences everything a member was playing, e.g. when they leave
+    pub fn release_all(&self, owner: Uuid) -> Result<(), JsValue> {
+        self.release(|(o, _, _)| *o == owner, self.ctx.current_time())
+    }
+}
+
+/// A voice may be faded out twice, by its note off and by `MAX_NOTE`,
+/// whichever comes first wins
+fn fade_out(voice: &Voice, when: f64) -> Result<(), JsValue> {
+    voice.envelope.gain().set_target_at_time(0.0, when, RELEASE / 3.0)?;
+    voice.oscillator.stop_with_when(when + RELEASE)?;
+    Ok(())
+}
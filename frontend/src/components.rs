@@ -1,15 +1,38 @@
 mod auth;
 mod band_member;
+mod channel_strip;
+mod control_bar;
 mod fader;
+mod input_list;
+mod invite;
 mod knob;
+mod latency_matrix;
 mod level_meter;
+mod lobby;
+mod midi_panel;
 mod mixerboard;
 mod navbar;
+mod output_select;
+mod profile;
+mod record_panel;
+mod scenes;
 mod session;
 pub use auth::Auth;
 pub use band_member::BandMember;
-pub use knob::Gain;
+pub use channel_strip::{BusStrip, ChannelStrip};
+pub use control_bar::ControlBar;
+pub use invite::InviteLink;
+pub use fader::Fader;
+pub use input_list::InputList;
+pub use knob::{Gain, Level, Pan};
+pub use latency_matrix::LatencyMatrix;
 pub use level_meter::LevelMeter;
+pub use lobby::Lobby;
+pub use midi_panel::MidiPanel;
 pub use mixerboard::MixerBoard;
 pub use navbar::NavBar;
-pub use session::Session;
+pub use output_select::OutputSelect;
+pub use profile::ProfileEditor;
+pub use record_panel::RecordPanel;
+pub use scenes::ScenePanel;
+pub use session::{Session, SessionAccess};
@@ -1,129 +1,353 @@
-use crate::network::Rtc;
+use std::{collections::HashMap, time::Duration};
+
+use crate::network::{
+    use_control_hub, use_midi_hub, use_track_labels, BitrateController, JitterBufferAuto,
+    PeerState, PeerStats, Rtc, MAX_JITTER_TARGET_MS,
+};
+use crate::audio::{use_audio_clock, SendStream};
+use crate::settings::use_settings;
 use gloo_console::log;
 use leptos::*;
-use protocol::{ClientCommand, ServerCommand};
+use protocol::{
+    ClientCommand, MemberProfile, PeerMessage, QualityPreset, Role, ServerCommand, DIAGNOSTICS_INTERVAL,
+};
 use uuid::Uuid;
 use wasm_bindgen::JsValue;
-use web_sys::{MediaStream, MediaStreamTrack, RtcRtpSender, RtcSignalingState};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{MediaStreamTrack, RtcRtpReceiver, RtcRtpSender};
+
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+fn milliseconds(value: Option<f64>) -> String {
+    value.map_or(String::from("-"), |v| format!("{v:.0} ms"))
+}
+
+fn kbps(value: Option<f64>) -> String {
+    value.map_or(String::from("-"), |v| format!("{v:.0}"))
+}
+
+/// A track on its way to the peer. The peer knows it by the stream it was
+/// first sent in, which stays the same when the track is replaced.
+#[derive(Clone, Debug)]
+struct Outgoing {
+    sender: RtcRtpSender,
+    track: MediaStreamTrack,
+    stream: String,
+}
 
 #[component]
 pub fn BandMember(
     uuid: Uuid,
+    profile: Signal<MemberProfile>,
     action: ReadSignal<ServerCommand>,
     #[prop(into)] send_message: Callback<ClientCommand>,
     #[prop(into)] add_track: Callback<MediaStreamTrack>,
-    #[prop(into)] update_action: Callback<ServerCommand>,
-    stream: ReadSignal<Option<MediaStream>>,
+    #[prop(into)] remove_track: Callback<MediaStreamTrack>,
+    streams: ReadSignal<Vec<SendStream>>,
+    quality: ReadSignal<QualityPreset>,
 ) -> impl IntoView {
+    let (peer_state, set_peer_state) = create_signal(PeerState::Connecting);
+    // Some(true) when we gave up on the connection, Some(false) when the peer did
+    let (reset, set_reset) = create_signal(None::<bool>);
+    let (stats, set_stats) = create_signal(PeerStats::default());
+    let settings = use_settings();
+    let bitrate = store_value(BitrateController::new(quality.get_untracked().bitrate() / 1000));
+    // How long incoming audio is buffered, picked by hand or automatically
+    let (jitter_auto, set_jitter_auto) = create_signal(true);
+    let (jitter_target, set_jitter_target) = create_signal(20u32);
+    let jitter_controller = store_value(JitterBufferAuto::new(jitter_target.get_untracked()));
+
+    let hub = use_control_hub();
+    let midi = use_midi_hub();
+    let clock = use_audio_clock();
+    on_cleanup(move || {
+        hub.unregister(uuid);
+        midi.unregister(uuid);
+    });
+
+    // The peer names its inputs by stream, the mixer knows them by track
+    let track_labels = use_track_labels();
+    let stream_tracks = store_value(HashMap::<String, String>::new());
+    let stream_labels = store_value(HashMap::<String, String>::new());
+    let publish_labels = move || {
+        let labels: Vec<(String, String)> = stream_labels.with_value(|labels| {
+            stream_tracks.with_value(|tracks| {
+                labels
+                    .iter()
+                    .filter_map(|(stream, label)| Some((tracks.get(stream)?.clone(), label.clone())))
+                    .collect()
+            })
+        });
+        track_labels.set(labels);
+    };
+    let outgoing = store_value(Vec::<Outgoing>::new());
+    let send_labels = move || {
+        let labels = outgoing.with_value(|outgoing| {
+            streams.with_untracked(|streams| {
+                outgoing
+                    .iter()
+                    .filter_map(|out| {
+                        let send = streams.iter().find(|send| {
+                            send.stream.get_tracks().iter().any(|t| MediaStreamTrack::from(t) == out.track)
+                        })?;
+                        Some((out.stream.clone(), send.label.clone()))
+                    })
+                    .collect()
+            })
+        });
+        hub.send(uuid, &PeerMessage::TrackLabels(labels));
+    };
+
+    let on_track = move |(track, stream): (MediaStreamTrack, Option<String>)| {
+        if let Some(stream) = stream {
+            stream_tracks.update_value(|tracks| {
+                tracks.insert(stream, track.id());
+            });
+            publish_labels();
+        }
+        add_track.call(track);
+    };
+
     // Create a RTC connection object
-    let mut connection = Rtc::new(uuid, send_message.clone()).unwrap();
-    connection.add_track_callback(add_track).unwrap();
-    connection.add_ice_callback().unwrap();
-    let (connection, set_connection) = create_signal(connection);
-
-    let update_action = move |command: ServerCommand| {
-        let state = connection.get().connection.signaling_state();
-        if state == RtcSignalingState::Stable {
-            update_action.call(command);
+    let build_connection = move || {
+        let mut connection = Rtc::new(uuid, send_message.clone()).unwrap();
+        connection
+            .add_track_callback(Callback::new(on_track), remove_track)
+            .unwrap();
+        connection.add_ice_callback().unwrap();
+        connection.add_negotiation_callback().unwrap();
+        connection
+            .add_state_callback(
+                Callback::new(move |state| set_peer_state.set(state)),
+                Callback::new(move |_| set_reset.set(Some(true))),
+            )
+            .unwrap();
+        connection
+            .add_control_channel(
+                Callback::new(move |message| match message {
+                    PeerMessage::TrackLabels(labels) => {
+                        stream_labels.update_value(|known| known.extend(labels));
+                        publish_labels();
+                    }
+                    message => hub.dispatch(uuid, message),
+                }),
+                Callback::new(move |_| send_labels()),
+                Callback::new(move |_| clock.now()),
+            )
+            .unwrap();
+        connection
+            .add_midi_channel(Callback::new(move |message| midi.dispatch(uuid, message)))
+            .unwrap();
+        if let Some(channel) = &connection.control {
+            hub.register(uuid, channel.clone());
+        }
+        if let Some(route) = &connection.midi {
+            midi.register(uuid, route.clone());
+        }
+        connection
+    };
+    let (connection, set_connection) = create_signal(build_connection());
+
+    // Results of actions on a connection that has since been replaced are dropped
+    let store_connection = move |rtc: Rtc| {
+        if connection.with_untracked(|current| current.connection == rtc.connection) {
+            set_connection.set(rtc);
         }
     };
 
-    // TODO: The on negotiation callback flow is currently broken. Needs to be adjusted
-    // so that the app follows https://blog.mozilla.org/webrtc/perfect-negotiation-in-webrtc/
-    connection.get_untracked().add_negotiation_callback(update_action.into()).unwrap();
+    // When an ICE restart didn't help, both ends replace their peer connection
+    // and negotiate from scratch
+    create_effect(move |_| {
+        if let Some(notify) = reset.get() {
+            log!("Rebuilding peer connection");
+            let old = connection.get_untracked();
+            for receiver in old.connection.get_receivers().iter() {
+                remove_track.call(RtcRtpReceiver::from(receiver).track());
+            }
+            old.close();
+
+            if notify {
+                send_message.call(ClientCommand::ResetConnection(uuid));
+            }
+            let mut rtc = build_connection();
+            rtc.polite = old.polite;
+            set_peer_state.set(PeerState::Connecting);
+            set_stats.set(PeerStats::default());
+            set_connection.set(rtc);
+        }
+    });
+
+    // Poll the connection statistics, rates are relative to the previous poll
+    let poll = set_interval_with_handle(
+        move || {
+            let rtc = connection.get_untracked();
+            let previous = stats.get_untracked();
+            spawn_local(async move {
+                let current = match rtc.stats(&previous).await {
+                    Ok(current) => current,
+                    Err(e) => {
+                        log!("Failed to read stats", e);
+                        return;
+                    }
+                };
+
+                // Adapt what we send to this peer to what reaches it
+                let bounds = settings.get_untracked().bitrate;
+                if let Some(kbps) = bitrate.try_update_value(|b| b.update(&current, &bounds)) {
+                    if let Err(e) = rtc.set_max_bitrate(kbps, bounds.policy).await {
+                        log!("Failed to set bitrate", e);
+                    }
+                }
+
+                // Receivers come and go with negotiation, so the target is
+                // applied on every poll
+                if jitter_auto.get_untracked() {
+                    if let Some(target) = jitter_controller.try_update_value(|j| j.update(&current)) {
+                        set_jitter_target.set(target);
+                    }
+                }
+                if let Err(e) = rtc.set_jitter_buffer_target(jitter_target.get_untracked()) {
+                    log!("Failed to set jitter buffer target", e);
+                }
+                set_stats.set(current);
+            });
+        },
+        STATS_INTERVAL,
+    );
+    if let Ok(handle) = poll {
+        on_cleanup(move || handle.clear());
+    }
 
+    // Keep a record on the server in case the rehearsal needs a post-mortem
+    let upload = set_interval_with_handle(
+        move || {
+            if peer_state.get_untracked() != PeerState::Connected {
+                return;
+            }
+            let codec = connection.with_untracked(|rtc| rtc.codec.as_ref().map(|c| c.to_string()));
+            let sample = stats.with_untracked(|stats| stats.sample(codec));
+            send_message.call(ClientCommand::Diagnostics(uuid, sample));
+        },
+        DIAGNOSTICS_INTERVAL,
+    );
+    if let Ok(handle) = upload {
+        on_cleanup(move || handle.clear());
+    }
 
     // Make sure new local tracks, i.e device inputs, are sent over to
-    // this bandmember
+    // this bandmember. A track that is no longer wanted makes room for a new
+    // one with replaceTrack, which needs no renegotiation.
     create_effect(move |_| {
-        let connection = connection.get_untracked();
-        match stream.get() {
-            Some(stream) => {
-                // Get current acutal senders
-                let current_tracks = connection
-                    .connection
-                    .get_senders()
-                    .iter()
-                    .map(|sender| RtcRtpSender::from(sender))
-                    .filter(|s| s.track().is_some())
-                    .collect::<Vec<RtcRtpSender>>();
+        let connection = connection.get();
+        let streams = streams.get();
+        let senders = connection
+            .connection
+            .get_senders()
+            .iter()
+            .map(RtcRtpSender::from)
+            .collect::<Vec<RtcRtpSender>>();
 
-                let wanted_tracks: Vec<MediaStreamTrack> = stream
+        // Every input goes in a stream of its own, which is how the
+        // peer tells them apart
+        let wanted: Vec<(MediaStreamTrack, &SendStream)> = streams
+            .iter()
+            .flat_map(|send| {
+                send.stream
                     .get_tracks()
                     .iter()
-                    .map(|track| MediaStreamTrack::from(track))
-                    .collect();
+                    .map(move |track| (MediaStreamTrack::from(track), send))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
 
-                // Remove unwanted tracks
-                let remove_tracks: Vec<&RtcRtpSender> = current_tracks
-                    .iter()
-                    .filter(|t| !wanted_tracks.contains(&t.track().expect("Missing track")))
-                    .collect();
-                for track in &remove_tracks {
-                    log!("Removing specific track");
-                    connection.connection.remove_track(track);
-                }
+        outgoing.update_value(|outgoing| {
+            // A rebuilt connection starts out sending nothing
+            outgoing.retain(|out| senders.contains(&out.sender));
 
-                // Add wanted tracks that isn't already added
-                let keep_tracks: Vec<MediaStreamTrack> = current_tracks
-                    .iter()
-                    .filter(|t| !remove_tracks.contains(t))
-                    .map(|t| MediaStreamTrack::from(t.track().expect("Missing track")))
-                    .collect();
-                for track in &wanted_tracks {
-                    if !keep_tracks.contains(track) {
+            let mut spare: Vec<usize> = (0..outgoing.len())
+                .filter(|&i| !wanted.iter().any(|(track, _)| *track == outgoing[i].track))
+                .collect();
+            for (track, send) in &wanted {
+                if outgoing.iter().any(|out| out.track == *track) {
+                    continue;
+                }
+                match spare.pop() {
+                    Some(i) => {
+                        log!("Replacing specific track");
+                        let replaced = outgoing[i].sender.replace_track(Some(track));
+                        spawn_local(async move {
+                            if let Err(e) = JsFuture::from(replaced).await {
+                                log!("Failed to replace track", e);
+                            }
+                        });
+                        outgoing[i].track = track.clone();
+                    }
+                    None => {
                         log!("Adding specific track");
-                        connection.connection.add_track_0(track, &stream);
+                        outgoing.push(Outgoing {
+                            sender: connection.connection.add_track_0(track, &send.stream),
+                            track: track.clone(),
+                            stream: send.stream.id(),
+                        });
                     }
                 }
             }
-            None => log!("No stream yet"),
-        };
+
+            // Remove unwanted tracks
+            for i in spare.into_iter().rev() {
+                log!("Removing specific track");
+                connection.connection.remove_track(&outgoing.remove(i).sender);
+            }
+        });
+        send_labels();
     });
 
     create_resource(
         move || action.get(),
         move |action: ServerCommand| {
             let mut connection = connection.get_untracked();
+            connection.quality = quality.get_untracked();
 
             async move {
                 log!(format!("{:?}", action));
                 match action {
-                    ServerCommand::CreateOffer(uuid) => {
-                        let offer = connection
-                            .create_offer()
-                            .await
-                            .expect("Failed to create offer");
-                        send_message.call(ClientCommand::Offer(uuid, offer));
-                        set_connection.set(connection);
+                    ServerCommand::CreateOffer(uuid) | ServerCommand::RestartIce(uuid) => {
+                        let ice_restart = matches!(action, ServerCommand::RestartIce(_));
+                        match connection.create_offer(ice_restart).await {
+                            Ok(Some(offer)) => send_message.call(ClientCommand::Offer(uuid, offer)),
+                            Ok(None) => {}
+                            Err(e) => log!("Failed to create offer", e),
+                        }
+                        store_connection(connection);
+                    }
+                    ServerCommand::ResetConnection(_) => {
+                        set_reset.set(Some(false));
                     }
                     ServerCommand::CreateAnswer(uuid, offer) => {
-                        let should_answer = connection.polite || !connection.pending_offer;
-                        if should_answer {
-                            let answer = connection
-                                .create_answer(offer)
-                                .await
-                                .expect("Failed to create anwser");
-                            send_message.call(ClientCommand::Answer(uuid, answer));
-                            set_connection.set(connection);
+                        match connection.create_answer(offer).await {
+                            Ok(Some(answer)) => send_message.call(ClientCommand::Answer(uuid, answer)),
+                            Ok(None) => {}
+                            Err(e) => log!("Failed to create answer", e),
                         }
+                        store_connection(connection);
                     }
                     ServerCommand::GetAnswer(_, answer) => {
-                        connection
-                            .get_answer(answer)
-                            .await
-                            .expect("Failed to get answer");
-                        set_connection.set(connection);
+                        if let Err(e) = connection.get_answer(answer).await {
+                            log!("Failed to get answer", e);
+                        }
+                        store_connection(connection);
                     }
 
                     ServerCommand::AddIceCandidate(_, ice) => {
-                        connection.get_ice(ice).await.expect("Failed to get ice");
-                        set_connection.set(connection);
+                        if let Err(e) = connection.get_ice(ice).await {
+                            log!("Failed to get ice", e);
+                        }
+                        store_connection(connection);
                     }
 
-                    ServerCommand::AddMember(_, polite) => {
+                    ServerCommand::AddMember(_, polite, _, _) => {
                         connection.polite = polite;
-                        set_connection.set(connection);
+                        store_connection(connection);
                     }
                     _ => log!("Got unexpected command!"),
                 }
@@ -131,11 +355,137 @@ pub fn BandMember(
         },
     );
 
-    view!(<div> {uuid.to_string()} </div>
-        <For
-            each=move || connection.get().msgs
-            key=|msg| msg.clone()
-            children= move |msg| view!{<p>{msg}</p>}
-        />
+    let role = match action.get_untracked() {
+        ServerCommand::AddMember(_, _, _, role) => role,
+        _ => Role::Performer,
+    };
+
+    let avatar = move || {
+        profile.get().avatar.map(|src| {
+            view! {
+                <figure class="image is-48x48">
+                    <img class="is-rounded" src=src/>
+                </figure>
+            }
+        })
+    };
+
+    let mouth_to_ear = move || {
+        let ptime = quality.get().opus_params().ptime;
+        milliseconds(stats.get().mouth_to_ear(ptime))
+    };
+
+    view!(
+        <div class="box">
+            <article class="media">
+                <div class="media-left">{avatar}</div>
+                <div class="media-content">
+                    <p class="title is-5">
+                        {move || profile.get().name}
+                        {(role == Role::Listener).then(|| view! { <span class="tag is-light ml-2">listening</span> })}
+                    </p>
+                    <p class="subtitle is-6">{move || profile.get().instrument.unwrap_or_default()}</p>
+                    <span class=move || {
+                        let color = match peer_state.get() {
+                            PeerState::Connected => "is-success",
+                            PeerState::Reconnecting => "is-warning",
+                            PeerState::Connecting => "is-info",
+                            PeerState::Closed => "is-danger",
+                        };
+                        format!("tag {}", color)
+                    }>
+                        {move || peer_state.get().as_str()}
+                    </span>
+                    {move || connection.get().codec.map(|codec| view! {
+                        <span class="tag is-light ml-2" title=codec.parameters.unwrap_or_default()>
+                            {codec.to_string()}
+                        </span>
+                    })}
+                </div>
+            </article>
+            <nav class="level is-mobile mt-2">
+                <div class="level-item has-text-centered">
+                    <div>
+                        <p class="heading">Mouth to ear</p>
+                        <p class="title is-6" title="Estimate, excluding audio device buffers">{mouth_to_ear}</p>
+                    </div>
+                </div>
+                <div class="level-item has-text-centered">
+                    <div>
+                        <p class="heading">RTT</p>
+                        <p class="title is-6">{move || milliseconds(stats.get().round_trip_time)}</p>
+                    </div>
+                </div>
+                <div class="level-item has-text-centered">
+                    <div>
+                        <p class="heading">Jitter</p>
+                        <p class="title is-6">{move || milliseconds(stats.get().jitter)}</p>
+                    </div>
+                </div>
+                <div class="level-item has-text-centered">
+                    <div>
+                        <p class="heading">Buffer</p>
+                        <p class="title is-6">{move || milliseconds(stats.get().jitter_buffer_delay)}</p>
+                    </div>
+                </div>
+                <div class="level-item has-text-centered">
+                    <div>
+                        <p class="heading">Loss</p>
+                        <p class="title is-6">
+                            {move || stats.get().packet_loss.map_or(String::from("-"), |l| format!("{:.1} %", l * 100.0))}
+                        </p>
+                    </div>
+                </div>
+                <div class="level-item has-text-centered">
+                    <div>
+                        <p class="heading">kbit/s in / out</p>
+                        <p class="title is-6">
+                            {move || format!("{} / {}", kbps(stats.get().bitrate_in), kbps(stats.get().bitrate_out))}
+                        </p>
+                    </div>
+                </div>
+            </nav>
+            <div class="field is-horizontal">
+                <div class="field-label is-small">
+                    <label class="label">{move || format!("Buffer target {} ms", jitter_target.get())}</label>
+                </div>
+                <div class="field-body">
+                    <input
+                        class="slider is-fullwidth"
+                        type="range"
+                        min="0"
+                        max=MAX_JITTER_TARGET_MS
+                        step="1"
+                        prop:value=move || jitter_target.get()
+                        disabled=jitter_auto
+                        on:input=move |e| {
+                            if let Ok(target) = event_target_value(&e).parse() {
+                                set_jitter_target.set(target)
+                            }
+                        }
+                    />
+                    <label class="checkbox ml-2">
+                        <input
+                            type="checkbox"
+                            prop:checked=jitter_auto
+                            on:change=move |e| {
+                                let auto = event_target_checked(&e);
+                                if auto {
+                                    // Continue searching from where the slider was left
+                                    jitter_controller.set_value(JitterBufferAuto::new(jitter_target.get_untracked()));
+                                }
+                                set_jitter_auto.set(auto);
+                            }
+                        />
+                        " auto"
+                    </label>
+                </div>
+            </div>
+            <For
+                each=move || connection.get().msgs
+                key=|msg| msg.clone()
+                children= move |msg| view!{<p>{msg}</p>}
+            />
+        </div>
     )
 }
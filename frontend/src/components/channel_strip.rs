@@ -0,0 +1,69 @@
+use leptos::*;
+use web_sys::AnalyserNode;
+
+use crate::components::{Fader, Level, LevelMeter, Pan};
+
+/// Controls for one track of the mixer
+#[component]
+pub fn ChannelStrip(
+    #[prop(into)] label: Signal<String>,
+    analyser: AnalyserNode,
+    gain_db: Signal<f32>,
+    pan: Signal<f32>,
+    mute: Signal<bool>,
+    solo: Signal<bool>,
+    #[prop(into)] set_gain_db: Callback<f32>,
+    #[prop(into)] set_pan: Callback<f32>,
+    #[prop(into)] set_mute: Callback<bool>,
+    #[prop(into)] set_solo: Callback<bool>,
+    /// Level into the send bus and how to change it, for local tracks
+    send: Option<(Signal<f32>, Callback<f32>)>,
+) -> impl IntoView {
+    view! {
+        <div class="box has-text-centered mr-2" style="display: inline-block;">
+            <p class="heading">{label}</p>
+            {send.map(|(send_db, set_send_db)| view! {
+                <p class="is-size-7">Send</p>
+                <Level value=send_db on_change=set_send_db/>
+            })}
+            <Pan value=pan on_change=set_pan/>
+            <div class="is-flex">
+                <Fader value=gain_db on_change=set_gain_db/>
+                <LevelMeter analyser=analyser/>
+            </div>
+            <div class="buttons has-addons is-centered">
+                <button
+                    class=move || if mute.get() { "button is-small is-danger" } else { "button is-small" }
+                    on:click=move |_| set_mute.call(!mute.get_untracked())
+                >
+                    M
+                </button>
+                <button
+                    class=move || if solo.get() { "button is-small is-warning" } else { "button is-small" }
+                    on:click=move |_| set_solo.call(!solo.get_untracked())
+                >
+                    S
+                </button>
+            </div>
+        </div>
+    }
+}
+
+/// Level of a whole bus
+#[component]
+pub fn BusStrip(
+    label: &'static str,
+    level: Signal<f32>,
+    #[prop(into)] set_level: Callback<f32>,
+    #[prop(optional)] analyser: Option<AnalyserNode>,
+) -> impl IntoView {
+    view! {
+        <div class="box has-text-centered mr-2" style="display: inline-block;">
+            <p class="heading">{label}</p>
+            <div class="is-flex">
+                <Fader value=level on_change=set_level/>
+                {analyser.map(|analyser| view! { <LevelMeter analyser=analyser/> })}
+            </div>
+        </div>
+    }
+}
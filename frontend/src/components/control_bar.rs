@@ -0,0 +1,63 @@
+use std::collections::{HashMap, HashSet};
+
+use leptos::*;
+use protocol::{MemberProfile, PeerMessage};
+use uuid::Uuid;
+
+use crate::network::use_control_hub;
+
+/// Ready flags and cues shared directly between the members
+#[component]
+pub fn ControlBar(profiles: ReadSignal<HashMap<Uuid, MemberProfile>>) -> impl IntoView {
+    let hub = use_control_hub();
+    let (ready, set_ready) = create_signal(false);
+    let (ready_peers, set_ready_peers) = create_signal(HashSet::<Uuid>::new());
+    let (last_cue, set_last_cue) = create_signal(None::<String>);
+
+    hub.subscribe(Callback::new(move |(uuid, message)| match message {
+        PeerMessage::Ready(is_ready) => set_ready_peers.update(|peers| {
+            if is_ready {
+                peers.insert(uuid);
+            } else {
+                peers.remove(&uuid);
+            }
+        }),
+        PeerMessage::Cue(cue) => {
+            let name = profiles.with_untracked(|p| p.get(&uuid).map(|p| p.name.clone()));
+            set_last_cue.set(Some(format!("{} from {}", cue, name.unwrap_or_default())));
+        }
+        _ => {}
+    }));
+
+    // Members that left can't be ready
+    create_effect(move |_| {
+        let members = profiles.get();
+        set_ready_peers.update(|peers| peers.retain(|uuid| members.contains_key(uuid)));
+    });
+
+    let toggle_ready = move |_| {
+        let is_ready = !ready.get();
+        set_ready.set(is_ready);
+        hub.broadcast(&PeerMessage::Ready(is_ready));
+    };
+    let count_in = move |_| {
+        hub.broadcast(&PeerMessage::Cue(String::from("count in")));
+        set_last_cue.set(Some(String::from("count in sent")));
+    };
+
+    view! {
+        <div class="buttons has-addons">
+            <button
+                class=move || if ready.get() { "button is-success" } else { "button" }
+                on:click=toggle_ready
+            >
+                {move || if ready.get() { "Ready" } else { "Not ready" }}
+            </button>
+            <button class="button" on:click=count_in>Count in</button>
+            <span class="tag is-medium ml-2">
+                {move || format!("{} / {} ready", ready_peers.get().len(), profiles.get().len())}
+            </span>
+            {move || last_cue.get().map(|cue| view! { <span class="tag is-info is-medium ml-2">{cue}</span> })}
+        </div>
+    }
+}
@@ -1,19 +1,39 @@
+use js_sys::{Array, Reflect};
 use leptos::*;
+use wasm_bindgen::JsValue;
+use web_sys::Event;
 
+/// AUX widgets report changes made by the user as `auxuseraction` events
+/// carrying the option name and its new value
+pub(crate) fn aux_value(ev: &Event) -> Option<f32> {
+    let detail = Reflect::get(ev, &JsValue::from_str("detail")).ok()?;
+    let args = Array::from(&Reflect::get(&detail, &JsValue::from_str("args")).ok()?);
+    if args.get(0).as_string().as_deref() != Some("value") {
+        return None;
+    }
+    args.get(1).as_f64().map(|v| v as f32)
+}
+
+/// Level in dB
 #[component]
-fn Fader() -> impl IntoView {
+pub fn Fader(value: Signal<f32>, #[prop(into)] on_change: Callback<f32>) -> impl IntoView {
     view! {
         <aux-fader
           min="-96"
-          max="24"
+          max="12"
           base="0"
-          value="0"
+          value=move || value.get()
           layout="right"
-          style="height: 300px;"
-          show_value="false"
+          style="height: 200px;"
+          show_value="true"
           label="Level"
           value.size="3"
           value.format="js:function(v){return parseInt(v)+'dB'}"
+          on:auxuseraction=move |ev: Event| {
+              if let Some(db) = aux_value(&ev) {
+                  on_change.call(db)
+              }
+          }
         ></aux-fader>
     }
 }
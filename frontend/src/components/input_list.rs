@@ -0,0 +1,130 @@
+use leptos::*;
+
+use crate::audio::{InputChannels, InputDeviceInfo, InputSpec};
+
+/// Channels offered when splitting an interface, more than most have
+const MAX_INPUT_CHANNELS: u32 = 8;
+
+/// The inputs I play into, each can take some channels of a device.
+/// Picking another device for an input swaps it while playing.
+#[component]
+pub fn InputList(
+    inputs: RwSignal<Vec<InputSpec>>,
+    #[prop(into)] devices: Signal<Vec<InputDeviceInfo>>,
+) -> impl IntoView {
+    let device_options = move |selected: Signal<String>| {
+        devices.with(|devices| {
+            devices
+                .iter()
+                .map(|dev| {
+                    let id = dev.deviceId.clone();
+                    view! {
+                        <option value=id.clone() selected=move || selected.get() == id>
+                            {dev.label.clone()}
+                        </option>
+                    }
+                })
+                .collect_view()
+        })
+    };
+
+    let (device, set_device) = create_signal(String::from("default"));
+    let (channels, set_channels) = create_signal(InputChannels::All);
+    let (name, set_name) = create_signal(String::new());
+    let add = move |_| {
+        let name = match name.get_untracked().trim() {
+            "" => format!("Input {}", inputs.with_untracked(|i| i.len()) + 1),
+            name => name.to_string(),
+        };
+        let spec = InputSpec::new(device.get_untracked(), name, channels.get_untracked());
+        inputs.update(|inputs| inputs.push(spec));
+        set_name.set(String::new());
+    };
+
+    view! {
+        <div class="field">
+            <label class="label">Sound inputs</label>
+            <For
+                each=move || inputs.get()
+                key=|spec| spec.id
+                children=move |spec| {
+                    let id = spec.id;
+                    let current = Signal::derive(move || {
+                        inputs.with(|inputs| {
+                            inputs.iter().find(|i| i.id == id).map(|i| i.device.clone()).unwrap_or_default()
+                        })
+                    });
+                    let switch = move |e| {
+                        let device = event_target_value(&e);
+                        inputs.update(|inputs| {
+                            if let Some(spec) = inputs.iter_mut().find(|i| i.id == id) {
+                                spec.device = device;
+                            }
+                        })
+                    };
+                    view! {
+                        <div class="field has-addons mb-1">
+                            <div class="control">
+                                <span class="button is-static">{spec.name.clone()}</span>
+                            </div>
+                            <div class="control">
+                                <div class="select">
+                                    <select on:change=switch>
+                                        {move || device_options(current)}
+                                    </select>
+                                </div>
+                            </div>
+                            <div class="control">
+                                <span class="button is-static">{spec.channels.description()}</span>
+                            </div>
+                            <div class="control">
+                                <button
+                                    class="button"
+                                    on:click=move |_| inputs.update(|inputs| inputs.retain(|i| i.id != id))
+                                >
+                                    Remove
+                                </button>
+                            </div>
+                        </div>
+                    }
+                }
+            />
+        </div>
+        <div class="field has-addons">
+            <div class="control">
+                <input
+                    class="input"
+                    type="text"
+                    placeholder="Vocals, guitar..."
+                    prop:value=name
+                    on:input=move |e| set_name.set(event_target_value(&e))
+                />
+            </div>
+            <div class="control">
+                <div class="select">
+                    <select on:change=move |e| set_device.set(event_target_value(&e))>
+                        {move || device_options(device.into())}
+                    </select>
+                </div>
+            </div>
+            <div class="control">
+                <div class="select">
+                    <select on:change=move |e| {
+                        if let Ok(choice) = event_target_value(&e).parse() {
+                            set_channels.set(choice)
+                        }
+                    }>
+                        {InputChannels::choices(MAX_INPUT_CHANNELS).into_iter().map(|choice| view! {
+                            <option value=choice.as_string() selected=move || channels.get() == choice>
+                                {choice.description()}
+                            </option>
+                        }).collect_view()}
+                    </select>
+                </div>
+            </div>
+            <div class="control">
+                <button class="button" on:click=add>Add input</button>
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,73 @@
+use leptos::*;
+use protocol::{InviteRequest, Role};
+
+use crate::network::{create_invite, use_access_token, Error};
+
+/// Hand out a link that lets a guest into this session without an account
+#[component]
+pub fn InviteLink(session: String) -> impl IntoView {
+    let (role, set_role) = create_signal(Role::Performer);
+    let (valid_for, set_valid_for) = create_signal(60 * 60);
+    // Only the session's creator and its performers may invite
+    let token = use_access_token();
+
+    let invite = create_action(move |request: &InviteRequest| {
+        let request = request.clone();
+        let token = token.get_untracked();
+        async move { create_invite(token, &request).await }
+    });
+
+    let on_create = move |_| {
+        invite.dispatch(InviteRequest {
+            session: session.clone(),
+            role: role.get(),
+            valid_for: valid_for.get(),
+        })
+    };
+
+    let link = move || {
+        invite.value().get().map(|result| match result {
+            Ok(invite) => {
+                let origin = window().location().origin().unwrap_or_default();
+                let link = format!("{}/join/{}", origin, invite.token);
+                view! { <input class="input" type="text" readonly prop:value=link/> }.into_view()
+            }
+            Err(Error::SignedOut) => view! { <p class="help is-danger">Sign in to invite others</p> }.into_view(),
+            Err(e) => view! { <p class="help is-danger">{format!("Could not create invite: {:?}", e)}</p> }.into_view(),
+        })
+    };
+
+    view! {
+        <div class="field has-addons">
+            <div class="control">
+                <div class="select">
+                    <select on:change=move |e| {
+                        if let Ok(r) = event_target_value(&e).parse() {
+                            set_role.set(r)
+                        }
+                    }>
+                        <option value="performer">performer</option>
+                        <option value="listener">listener</option>
+                    </select>
+                </div>
+            </div>
+            <div class="control">
+                <div class="select">
+                    <select on:change=move |e| {
+                        if let Ok(seconds) = event_target_value(&e).parse() {
+                            set_valid_for.set(seconds)
+                        }
+                    }>
+                        <option value="3600">1 hour</option>
+                        <option value="86400">1 day</option>
+                        <option value="604800">1 week</option>
+                    </select>
+                </div>
+            </div>
+            <div class="control">
+                <button class="button is-info" on:click=on_create>Invite</button>
+            </div>
+        </div>
+        {link}
+    }
+}
@@ -1,4 +1,7 @@
 use leptos::*;
+use web_sys::Event;
+
+use super::fader::aux_value;
 
 #[component]
 pub fn Gain(value: ReadSignal<f32>) -> impl IntoView {
@@ -15,14 +18,42 @@ pub fn Gain(value: ReadSignal<f32>) -> impl IntoView {
     }
 }
 
+/// Stereo position from -1 (left) to 1 (right)
 #[component]
-pub fn Pan(value: ReadSignal<f32>) -> impl IntoView {
+pub fn Pan(value: Signal<f32>, #[prop(into)] on_change: Callback<f32>) -> impl IntoView {
     view! {
         <aux-knob
           min="-1"
           max="1"
-          value=value
+          base="0"
+          value=move || value.get()
+          preset="small"
+          labels="js:[{value:-1,label:'L'},{value:0,label:'C'},{value:1,label:'R'}]"
+          on:auxuseraction=move |ev: Event| {
+              if let Some(pan) = aux_value(&ev) {
+                  on_change.call(pan)
+              }
+          }
+        />
+    }
+}
+
+/// Level in dB, for sends where a fader would take too much room
+#[component]
+pub fn Level(value: Signal<f32>, #[prop(into)] on_change: Callback<f32>) -> impl IntoView {
+    view! {
+        <aux-knob
+          min="-96"
+          max="12"
+          base="0"
+          value=move || value.get()
           preset="small"
+          labels="js:[{value:-96,label:'-inf'},{value:0,label:'0'},{value:12,label:'+12'}]"
+          on:auxuseraction=move |ev: Event| {
+              if let Some(db) = aux_value(&ev) {
+                  on_change.call(db)
+              }
+          }
         />
     }
 }
@@ -0,0 +1,140 @@
+use std::{collections::HashMap, time::Duration};
+
+use leptos::*;
+use protocol::{MemberProfile, PeerMessage};
+use uuid::Uuid;
+
+use crate::audio::use_audio_clock;
+use crate::network::{use_control_hub, LatencyTracker};
+
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+/// How often our own measurements are shared with the others
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+fn cell(rtt: Option<f64>) -> String {
+    rtt.map_or(String::from("-"), |rtt| format!("{rtt:.0} ms"))
+}
+
+/// Round trip times between every pair of members, measured by the members
+/// themselves over their control channels
+#[component]
+pub fn LatencyMatrix(
+    profiles: ReadSignal<HashMap<Uuid, MemberProfile>>,
+    me: ReadSignal<Option<Uuid>>,
+) -> impl IntoView {
+    let hub = use_control_hub();
+    let clock = use_audio_clock();
+    let trackers = create_rw_signal(HashMap::<Uuid, LatencyTracker>::new());
+    // What the other members measured, by who measured it
+    let reports = create_rw_signal(HashMap::<Uuid, HashMap<Uuid, f64>>::new());
+    let next_ping = store_value(0u32);
+
+    hub.subscribe(Callback::new(move |(uuid, message)| match message {
+        PeerMessage::Pong { sent, epoch, received, received_epoch, .. } => {
+            if let Some(now) = clock.now() {
+                trackers.update(|t| {
+                    let tracker = t.entry(uuid).or_default();
+                    tracker.add_sample((sent, epoch), (received, received_epoch), now);
+                });
+            }
+        }
+        PeerMessage::Latencies(latencies) => {
+            reports.update(|r| {
+                r.insert(uuid, latencies.into_iter().collect());
+            });
+        }
+        _ => {}
+    }));
+
+    let ping = set_interval_with_handle(
+        move || {
+            let Some((sent, epoch)) = clock.now() else {
+                return;
+            };
+            let id = next_ping.get_value();
+            next_ping.set_value(id.wrapping_add(1));
+            hub.broadcast(&PeerMessage::Ping { id, sent, epoch });
+        },
+        PING_INTERVAL,
+    );
+    let report = set_interval_with_handle(
+        move || {
+            let latencies = trackers.with_untracked(|t| {
+                t.iter()
+                    .filter_map(|(uuid, tracker)| Some((*uuid, tracker.rtt()?)))
+                    .collect()
+            });
+            hub.broadcast(&PeerMessage::Latencies(latencies));
+        },
+        REPORT_INTERVAL,
+    );
+    on_cleanup(move || {
+        for handle in [ping, report].into_iter().flatten() {
+            handle.clear();
+        }
+    });
+
+    // Forget members that left
+    create_effect(move |_| {
+        let members = profiles.get();
+        trackers.update(|t| t.retain(|uuid, _| members.contains_key(uuid)));
+        reports.update(|r| r.retain(|uuid, _| members.contains_key(uuid)));
+    });
+
+    // Everybody in the session, me first
+    let members = move || {
+        let profiles = profiles.get();
+        let mut others: Vec<(Uuid, String)> = profiles
+            .iter()
+            .map(|(uuid, profile)| (*uuid, profile.name.clone()))
+            .collect();
+        others.sort_by(|a, b| a.1.cmp(&b.1));
+        me.get()
+            .map(|me| (me, String::from("me")))
+            .into_iter()
+            .chain(others)
+            .collect::<Vec<(Uuid, String)>>()
+    };
+
+    let rtt = move |from: Uuid, to: Uuid| {
+        if Some(from) == me.get() {
+            trackers.with(|t| t.get(&to).and_then(|tracker| tracker.rtt()))
+        } else {
+            reports.with(|r| r.get(&from).and_then(|latencies| latencies.get(&to).copied()))
+        }
+    };
+
+    view! {
+        <div class="box">
+            <p class="title is-6">Latency between members</p>
+            <table class="table is-narrow is-fullwidth">
+                <thead>
+                    <tr>
+                        <th></th>
+                        {move || members().into_iter().map(|(_, name)| view! { <th>{name}</th> }).collect_view()}
+                        <th title="Their clock relative to mine">Clock offset</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    {move || {
+                        let members = members();
+                        members.clone().into_iter().map(|(from, name)| {
+                            let offset = trackers
+                                .with(|t| t.get(&from).and_then(|tracker| tracker.clock_offset()));
+                            view! {
+                                <tr>
+                                    <th>{name}</th>
+                                    {members.iter().map(|(to, _)| {
+                                        let value = if from == *to { String::from("") } else { cell(rtt(from, *to)) };
+                                        view! { <td>{value}</td> }
+                                    }).collect_view()}
+                                    <td>{cell(offset)}</td>
+                                </tr>
+                            }
+                        }).collect_view()
+                    }}
+                </tbody>
+            </table>
+        </div>
+    }
+}
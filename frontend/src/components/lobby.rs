@@ -0,0 +1,151 @@
+use gloo_console::log;
+use leptos::*;
+use leptos_router::*;
+use protocol::{LobbyCommand, QualityPreset, SessionInfo, SessionVisibility};
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{MessageEvent, WebSocket};
+
+use crate::network::{use_access_token, ws_url};
+use crate::settings::use_settings;
+
+pub fn studio_path(session: &str, visibility: SessionVisibility, quality: QualityPreset) -> String {
+    format!(
+        "studio?session={}&visibility={}&quality={}",
+        String::from(js_sys::encode_uri_component(session)),
+        visibility.as_str(),
+        quality.as_str()
+    )
+}
+
+/// Live listing of the sessions I can join
+#[component]
+pub fn Lobby() -> impl IntoView {
+    let (sessions, set_sessions) = create_signal(Vec::<SessionInfo>::new());
+    // The server shows band sessions to the bands it knows we play in
+    let url = match use_access_token().get_untracked() {
+        Some(token) => ws_url(&format!(
+            "lobby?token={}",
+            String::from(js_sys::encode_uri_component(&token))
+        )),
+        None => ws_url("lobby"),
+    };
+    let ws = WebSocket::new(&url).unwrap();
+
+    let onmessage = move |event: MessageEvent| {
+        let message = event.data().as_string().unwrap();
+        match serde_json::from_str::<LobbyCommand>(&message) {
+            Ok(LobbyCommand::Sessions(list)) => set_sessions.set(list),
+            Ok(LobbyCommand::SessionChanged(info)) => set_sessions.update(|list| {
+                match list.iter_mut().find(|s| s.name == info.name) {
+                    Some(session) => *session = info,
+                    None => list.push(info),
+                }
+            }),
+            Ok(LobbyCommand::SessionClosed(name)) => {
+                set_sessions.update(|list| list.retain(|s| s.name != name))
+            }
+            Err(e) => log!(format!("Bad lobby message: {}", e)),
+        }
+    };
+    let cb = Closure::wrap(Box::new(onmessage) as Box<dyn FnMut(_)>);
+    ws.set_onmessage(Some(cb.as_ref().unchecked_ref()));
+    cb.forget();
+
+    on_cleanup(move || {
+        ws.close().ok();
+    });
+
+    view! {
+        <CreateSession/>
+        <table class="table is-fullwidth is-hoverable">
+            <thead>
+                <tr>
+                    <th>Session</th>
+                    <th>Visibility</th>
+                    <th>Quality</th>
+                    <th>Participants</th>
+                    <th></th>
+                </tr>
+            </thead>
+            <tbody>
+                <For
+                    each=move || sessions.get()
+                    key=|session| (session.name.clone(), session.participants)
+                    children=move |session| {
+                        let href = studio_path(&session.name, session.visibility, session.quality);
+                        view! {
+                            <tr>
+                                <td>{session.name}</td>
+                                <td>{session.visibility.as_str()}</td>
+                                <td>{session.quality.as_str()}</td>
+                                <td>{session.participants}</td>
+                                <td><A class="button is-small is-primary" href=href>Join</A></td>
+                            </tr>
+                        }
+                    }
+                />
+            </tbody>
+        </table>
+    }
+}
+
+#[component]
+fn CreateSession() -> impl IntoView {
+    let (name, set_name) = create_signal(String::new());
+    let (visibility, set_visibility) = create_signal(SessionVisibility::Public);
+    let (quality, set_quality) = create_signal(QualityPreset::default());
+    let settings = use_settings();
+    let navigate = use_navigate();
+
+    let create = move |_| {
+        let name = name.get();
+        if name.trim().is_empty() {
+            return;
+        }
+        navigate(&studio_path(&name, visibility.get(), quality.get()), Default::default());
+    };
+
+    view! {
+        <div class="field has-addons">
+            <div class="control is-expanded">
+                <input
+                    class="input"
+                    type="text"
+                    placeholder="New session"
+                    prop:value=name
+                    on:input=move |e| set_name.set(event_target_value(&e))
+                />
+            </div>
+            <div class="control">
+                <div class="select">
+                    <select on:change=move |e| {
+                        if let Ok(v) = event_target_value(&e).parse() {
+                            set_visibility.set(v)
+                        }
+                    }>
+                        <option value="public">public</option>
+                        <option value="band" disabled=move || settings.get().band.is_none()>band</option>
+                        <option value="private">private</option>
+                    </select>
+                </div>
+            </div>
+            <div class="control">
+                <div class="select">
+                    <select on:change=move |e| {
+                        if let Ok(q) = event_target_value(&e).parse() {
+                            set_quality.set(q)
+                        }
+                    }>
+                        <option value="low">low (64 kbps)</option>
+                        <option value="standard" selected>standard (128 kbps)</option>
+                        <option value="high">high (256 kbps)</option>
+                        <option value="studio">studio (510 kbps)</option>
+                    </select>
+                </div>
+            </div>
+            <div class="control">
+                <button class="button is-primary" on:click=create>Create</button>
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use leptos::*;
+use protocol::{MemberProfile, MidiMessage};
+use uuid::Uuid;
+use web_sys::MidiOutput;
+
+use crate::audio::{list_outputs, listen_to_inputs, midi_access, use_midi_routing};
+use crate::network::use_midi_hub;
+
+/// Sends what is played on the local MIDI devices to every member, and picks
+/// which members' MIDI is played here and where
+#[component]
+pub fn MidiPanel(profiles: ReadSignal<HashMap<Uuid, MemberProfile>>) -> impl IntoView {
+    let midi = use_midi_hub();
+    let routing = use_midi_routing();
+    let (inputs, set_inputs) = create_signal(None::<Result<Vec<String>, String>>);
+    let (outputs, set_outputs) = create_signal(Vec::<MidiOutput>::new());
+
+    let connect = create_action(move |_: &()| async move {
+        let forward = Callback::new(move |message: MidiMessage| midi.broadcast(&message));
+        let names = match midi_access().await {
+            Ok(access) => {
+                set_outputs.set(list_outputs(&access).unwrap_or_default());
+                listen_to_inputs(&access, forward)
+                    .map_err(|e| format!("Could not listen to MIDI inputs: {:?}", e))
+            }
+            Err(_) => Err(String::from("MIDI access was denied or isn't supported")),
+        };
+        set_inputs.set(Some(names));
+    });
+
+    // Forget members that left
+    create_effect(move |_| {
+        let members = profiles.get();
+        routing.muted.update(|muted| muted.retain(|uuid| members.contains_key(uuid)));
+    });
+
+    let sources = move || {
+        let mut members: Vec<(Uuid, String)> = profiles
+            .get()
+            .into_iter()
+            .map(|(uuid, profile)| (uuid, profile.name))
+            .collect();
+        members.sort_by(|a, b| a.1.cmp(&b.1));
+        members
+    };
+
+    let pick_output = move |e| {
+        let id = event_target_value(&e);
+        let output = outputs.with(|outputs| outputs.iter().find(|o| o.id() == id).cloned());
+        routing.output.set(output);
+    };
+
+    view! {
+        <div class="field">
+            <label class="label">MIDI</label>
+            {move || match inputs.get() {
+                None => view! {
+                    <button class="button" on:click=move |_| connect.dispatch(())>
+                        "Connect MIDI devices"
+                    </button>
+                }.into_view(),
+                Some(Ok(names)) if names.is_empty() => view! {
+                    <p class="help">No MIDI inputs found</p>
+                }.into_view(),
+                Some(Ok(names)) => view! {
+                    <div class="tags">
+                        {names.into_iter().map(|name| view! { <span class="tag is-link">{name}</span> }).collect_view()}
+                    </div>
+                }.into_view(),
+                Some(Err(e)) => view! { <p class="help is-danger">{e}</p> }.into_view(),
+            }}
+        </div>
+        <div class="field">
+            <label class="label">MIDI from</label>
+            <For
+                each=sources
+                key=|(uuid, name)| (*uuid, name.clone())
+                children=move |(uuid, name)| view! {
+                    <label class="checkbox mr-3">
+                        <input
+                            type="checkbox"
+                            prop:checked=move || routing.muted.with(|muted| !muted.contains(&uuid))
+                            on:change=move |e| {
+                                let on = event_target_checked(&e);
+                                routing.muted.update(|muted| {
+                                    if on {
+                                        muted.remove(&uuid);
+                                    } else {
+                                        muted.insert(uuid);
+                                    }
+                                });
+                            }
+                        />
+                        " " {name}
+                    </label>
+                }
+            />
+        </div>
+        <div class="field">
+            <label class="label">Play on</label>
+            <div class="select">
+                <select on:change=pick_output>
+                    <option value="" selected=move || routing.output.with(Option::is_none)>
+                        Built-in synth
+                    </option>
+                    {move || outputs.get().into_iter().map(|output| {
+                        let id = output.id();
+                        let name = output.name().unwrap_or_else(|| String::from("MIDI output"));
+                        let selected = {
+                            let id = id.clone();
+                            move || routing.output.with(|o| o.as_ref().is_some_and(|o| o.id() == id))
+                        };
+                        view! { <option value=id selected=selected>{name}</option> }
+                    }).collect_view()}
+                </select>
+            </div>
+            <p class="help">Connect MIDI devices to play on an output of this computer</p>
+        </div>
+    }
+}
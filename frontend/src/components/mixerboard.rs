@@ -1,46 +1,297 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
-use crate::audio::{get_devices, AudioGraph, InputDeviceInfo};
-use crate::components::LevelMeter;
+use crate::audio::{
+    get_devices, on_device_change, open_device, send_to_output, silence_output, stop_tracks,
+    use_audio_clock, use_midi_routing, AudioGraph, InputChannels, InputDeviceInfo, InputSpec, MidiClock, SendStream,
+};
+use crate::components::{
+    BusStrip, ChannelStrip, InputList, OutputSelect, RecordPanel, ScenePanel,
+};
+use crate::network::{use_midi_hub, use_track_labels};
+use crate::settings::use_settings;
 use gloo_console::log;
 use leptos::*;
+use protocol::mixer::{ChannelKey, ChannelState, MixerAction, MixerState, SEND_BUS};
+use protocol::{MemberProfile, MidiMessage, Role};
 use uuid::Uuid;
-use web_sys::{self, MediaStream, MediaStreamTrack};
+use web_sys::{self, MediaStreamTrack, MidiOutput};
+
+/// How long recalling a scene takes, long enough not to click
+const SCENE_FADE: f64 = 0.2;
+const LATENCY_INTERVAL: Duration = Duration::from_secs(1);
+/// Well within the metronome's look ahead
+const CLICK_INTERVAL: Duration = Duration::from_millis(25);
+const BEATS_PER_BAR: u32 = 4;
 
 #[component]
 pub fn MixerBoard(
-    #[prop(into)] set_stream: Callback<MediaStream>,
+    #[prop(into)] set_streams: Callback<Vec<SendStream>>,
     tracks: ReadSignal<HashMap<Uuid, Vec<MediaStreamTrack>>>,
+    profiles: ReadSignal<HashMap<Uuid, MemberProfile>>,
+    role: ReadSignal<Option<Role>>,
 ) -> impl IntoView {
-    // This is for the device selector
-    let (input_device, set_input_device) = create_signal("default".to_string());
+    // The inputs I play into, to begin with whatever the browser picks
+    let inputs = create_rw_signal(vec![InputSpec::new(
+        String::from("default"),
+        String::from("Input"),
+        InputChannels::All,
+    )]);
+    let track_labels = use_track_labels();
+
+    // Devices come and go while playing, an input whose device is unplugged
+    // falls back to the default one and carries on
+    let devices = create_rw_signal(Vec::<InputDeviceInfo>::new());
+    let of_kind = move |kind: &'static str| {
+        Signal::derive(move || {
+            devices.with(|devices| {
+                devices.iter().filter(|dev| dev.kind == kind).cloned().collect::<Vec<_>>()
+            })
+        })
+    };
+    let input_devices = of_kind("audioinput");
+    let output_devices = of_kind("audiooutput");
+    let refresh_devices = move || {
+        spawn_local(async move {
+            let Ok(found) = get_devices().await else {
+                return;
+            };
+            let mics: Vec<_> = found.iter().filter(|dev| dev.kind == "audioinput").collect();
+            // Without permission to record the ids are blank, nothing is gone
+            if !mics.iter().any(|dev| dev.deviceId.is_empty()) {
+                let gone = |spec: &InputSpec| {
+                    spec.device != "default" && !mics.iter().any(|dev| dev.deviceId == spec.device)
+                };
+                if inputs.with_untracked(|inputs| inputs.iter().any(gone)) {
+                    inputs.update(|inputs| {
+                        for spec in inputs.iter_mut().filter(|spec| gone(spec)) {
+                            log!(format!("{} was unplugged, using the default device", spec.name));
+                            spec.device = String::from("default");
+                        }
+                    });
+                }
+            }
+            devices.set(found);
+        })
+    };
+    refresh_devices();
+    if let Err(e) = on_device_change(Callback::new(move |_| refresh_devices())) {
+        log!("Failed to watch for devices", e);
+    }
 
     // Setup audio graph
-    let mut graph = AudioGraph::new().expect("Failed to create audio ctx");
+    let settings = use_settings();
+    let mut graph =
+        AudioGraph::new(&settings.get_untracked().audio).expect("Failed to create audio ctx");
     graph.suspend().unwrap();
+    use_audio_clock().set_context(graph.context().clone());
     let (graph, set_graph) = create_signal(graph);
 
+    // What the context ended up with, the output latency is only known
+    // once it runs and may change with the device
+    let (latencies, set_latencies) = create_signal((None, None));
+    let poll = set_interval_with_handle(
+        move || set_latencies.set(graph.with_untracked(|graph| graph.latencies())),
+        LATENCY_INTERVAL,
+    );
+    if let Ok(handle) = poll {
+        on_cleanup(move || handle.clear());
+    }
+    let sample_rate = graph.with_untracked(|graph| graph.context().sample_rate());
+    let context_info = move || {
+        let (base, output) = latencies.get();
+        let ms = |seconds: Option<f64>| {
+            seconds.map_or(String::from("-"), |s| format!("{:.1} ms", s * 1000.0))
+        };
+        format!("{sample_rate} Hz, base latency {}, output latency {}", ms(base), ms(output))
+    };
+
+    // The monitor mix plays where the settings say, or on the default
+    // device while the chosen one is unplugged
+    create_resource(
+        move || {
+            let chosen = settings.with(|s| s.output_device.clone()).unwrap_or_default();
+            let present = output_devices.with(|devices| devices.iter().any(|dev| dev.deviceId == chosen));
+            if present {
+                chosen
+            } else {
+                String::new()
+            }
+        },
+        move |device| {
+            let graph = graph.get_untracked();
+            async move {
+                if let Err(e) = graph.set_output(&device).await {
+                    log!("Failed to choose the output", e);
+                }
+            }
+        },
+    );
+
+    // What the mix should sound like, the graph follows it. Changes from the
+    // strips apply right away, scenes fade in.
+    let mixer = create_rw_signal(MixerState::default());
+    let next_fade = store_value(0.0);
+    let sync_mixer = move |graph: &AudioGraph| {
+        let channels = profiles.with_untracked(|profiles| {
+            track_labels.with_untracked(|labels| named_channels(graph, profiles, labels))
+        });
+        mixer.update(|mixer| mixer.sync_channels(&channels));
+    };
+    create_effect(move |_| {
+        profiles.with(|_| ());
+        track_labels.with(|_| ());
+        graph.with_untracked(sync_mixer);
+    });
+    create_effect(move |_| {
+        let fade = next_fade.get_value();
+        next_fade.set_value(0.0);
+        let applied = mixer.with(|mixer| graph.with_untracked(|graph| graph.reconcile(mixer, fade)));
+        if let Err(e) = applied {
+            log!("Failed to apply the mix", e);
+        }
+    });
+    let recall = move |scene: MixerState| {
+        next_fade.set_value(SCENE_FADE);
+        mixer.update(|mixer| mixer.recall(&scene));
+    };
+
+    // Strips follow the tracks, ones that ended or whose member left go
     create_effect(move |_| {
         let tracks = tracks.get();
-        let mut graph = graph.get_untracked();
-        for (owner, track) in tracks {
-            for t in track {
-                graph.add_input(owner, t).unwrap();
+        set_graph.update_untracked(|graph| {
+            let gone: Vec<String> = graph
+                .remote_tracks
+                .iter()
+                .filter(|t| !tracks.values().flatten().any(|track| track.id() == t.id))
+                .map(|t| t.id.clone())
+                .collect();
+            for id in gone {
+                graph.remove_input(&id);
+            }
+            for (owner, track) in tracks {
+                for t in track {
+                    if let Err(e) = graph.add_input(owner, t) {
+                        log!("Failed to add a track of", owner.to_string(), e);
+                    }
+                }
+            }
+        });
+        graph.with_untracked(sync_mixer);
+    });
+
+    // Remote MIDI plays on the built-in synth or the MIDI output picked in
+    // the MIDI panel
+    let midi_clock = store_value(MidiClock::default());
+    let midi_routing = use_midi_routing();
+    use_midi_hub().subscribe(Callback::new(move |(owner, message): (Uuid, MidiMessage)| {
+        if !midi_routing.passes(owner, &message) {
+            return;
+        }
+        let delay = midi_clock
+            .try_update_value(|clock| clock.delay(owner, message.timestamp))
+            .unwrap_or_default();
+        let played = match midi_routing.output.get_untracked() {
+            Some(output) => send_to_output(&output, &message.data, delay),
+            None => graph.with_untracked(|graph| graph.synth.play(owner, &message.data, delay)),
+        };
+        if let Err(e) = played {
+            log!("Failed to play MIDI", e);
+        }
+    }));
+
+    // Notes sounding where MIDI no longer goes would never be ended
+    create_effect(move |previous: Option<Option<MidiOutput>>| {
+        let output = midi_routing.output.get();
+        match previous {
+            Some(Some(old)) => {
+                if let Err(e) = silence_output(&old) {
+                    log!("Failed to silence MIDI output", e);
+                }
+            }
+            Some(None) => {
+                for owner in midi_clock.with_value(|clock| clock.owners()) {
+                    graph.with_untracked(|graph| graph.synth.release_all(owner)).ok();
+                }
             }
+            None => {}
+        }
+        output
+    });
+
+    // Don't leave notes hanging when a member leaves mid chord
+    create_effect(move |_| {
+        let members = profiles.get();
+        let gone: Vec<Uuid> = midi_clock.with_value(|clock| {
+            clock.owners().into_iter().filter(|o| !members.contains_key(o)).collect()
+        });
+        for owner in gone {
+            graph.with_untracked(|graph| graph.synth.release_all(owner)).ok();
+            midi_clock.update_value(|clock| clock.forget(owner));
         }
-        set_graph.set_untracked(graph);
     });
 
+    // My inputs follow the input list. Opening a device may wait on the
+    // user allowing it, so devices are opened first and the graph only
+    // changes once they are there. A newer change to the list takes over
+    // from one still waiting, whose devices are let go again.
+    let generation = store_value(0_u64);
     create_resource(
-        move || input_device.get(),
-        move |device| {
-            let mut graph = graph.clone().get_untracked();
-            let set_graph = set_graph.clone();
+        move || (inputs.get(), role.get()),
+        move |(inputs, role)| {
+            generation.update_value(|g| *g += 1);
+            let run = generation.get_value();
+            let superseded = move || generation.get_value() != run;
             async move {
-                log!("Switching dev");
-                graph.add_device(&device).await.unwrap();
-                graph.connect().unwrap();
-                set_graph.set(graph);
+                match role {
+                    Some(Role::Performer) => {
+                        let pending: Vec<InputSpec> = set_graph.try_update_untracked(|graph| {
+                            let gone: Vec<String> = graph
+                                .local_tracks
+                                .iter()
+                                .map(|track| track.id.clone())
+                                .filter(|id| !inputs.iter().any(|spec| spec.id.to_string() == *id))
+                                .collect();
+                            for id in gone {
+                                graph.remove_device(&id);
+                            }
+                            inputs.iter().filter(|spec| graph.update_device(spec)).cloned().collect()
+                        })?;
+                        let sample_rate = graph.with_untracked(|graph| graph.context().sample_rate());
+                        for spec in pending {
+                            let stream = match open_device(&spec, sample_rate).await {
+                                Ok(stream) => stream,
+                                Err(e) => {
+                                    log!(format!("Failed to open {}", spec.name), e);
+                                    continue;
+                                }
+                            };
+                            if superseded() {
+                                stop_tracks(&stream);
+                                return None;
+                            }
+                            set_graph.update_untracked(|graph| {
+                                if let Err(e) = graph.add_device(&spec, stream) {
+                                    log!(format!("Failed to open {}", spec.name), e);
+                                }
+                            });
+                        }
+                    }
+                    Some(Role::Listener) => log!("Listening only, not opening any inputs"),
+                    // Wait for the server to tell us how we joined
+                    None => return None,
+                }
+                if superseded() {
+                    return None;
+                }
+                // Device names are only shown once we may record
+                refresh_devices();
+                set_graph.update(|graph| {
+                    if let Err(e) = graph.connect() {
+                        log!("Failed to play the monitor mix", e);
+                    }
+                });
+                graph.with_untracked(sync_mixer);
+                Some(())
             }
         },
     );
@@ -57,20 +308,58 @@ pub fn MixerBoard(
         }
     };
 
-    // Update outgoing stream when audio graph stream changes
-    let out_stream = create_memo(move |_| graph.get().stream().unwrap());
+    // The click plays into my monitor mix only
+    let (tempo, set_tempo) = create_signal(120.0);
+    let (clicking, set_clicking) = create_signal(false);
+    let ticker = set_interval_with_handle(
+        move || {
+            graph.with_untracked(|graph| {
+                if let Err(e) = graph.metronome.schedule(tempo.get_untracked(), BEATS_PER_BAR) {
+                    log!("Failed to schedule the click", e);
+                }
+            })
+        },
+        CLICK_INTERVAL,
+    );
+    if let Ok(handle) = ticker {
+        on_cleanup(move || handle.clear());
+    }
+    let toggle_click = move |_| {
+        let on = !clicking.get_untracked();
+        graph.with_untracked(|graph| {
+            if on {
+                graph.metronome.start()
+            } else {
+                graph.metronome.stop()
+            }
+        });
+        set_clicking.set(on);
+    };
+
+    // Update outgoing streams when the inputs change
+    let out_streams = create_memo(move |_| graph.with(|graph| graph.streams()));
     create_effect(move |_| {
-        let stream = out_stream.get();
-        set_stream.call(stream);
+        let streams = out_streams.get();
+        set_streams.call(streams);
     });
 
-    let r_graph = graph.clone();
-    let remote_tracks = move || r_graph.get().remote_tracks;
+    let strips = move || {
+        mixer.with(|mixer| {
+            mixer
+                .channels
+                .iter()
+                .map(|channel| channel.key.clone())
+                .collect::<Vec<_>>()
+        })
+    };
 
     view! {
     <section class="hero is-primary">
         <div class="hero-body">
-            <SelectDevices set_input_device=set_input_device/>
+            {move || (role.get() != Some(Role::Listener)).then(|| view! {
+                <InputList inputs=inputs devices=input_devices/>
+            })}
+            <OutputSelect devices=output_devices/>
             <div class="field">
                 <input
                     id="isLiveSwitch"
@@ -82,63 +371,146 @@ pub fn MixerBoard(
             <div>
                 {"This is where I configure my inputs and sound"}
             </div>
+            <p class="help">{context_info}</p>
         </div>
+        <div class="section">
         <For
-         each=move || remote_tracks()
-         key=|track| track.label.clone()
-         children= move |track| {
-            log!("Remote is real");
+         each=strips
+         key=|key| key.clone()
+         children= move |key| {
+            let Some((analyser, local)) = graph.with_untracked(|graph| {
+                let track = graph.track(&key)?;
+                Some((track.analyser.clone(), graph.is_local(&key)))
+            }) else {
+                return ().into_view();
+            };
+            let label = channel_signal(mixer, &key, |c| c.label.clone());
+            let gain_db = channel_signal(mixer, &key, |c| c.gain_db);
+            let pan = channel_signal(mixer, &key, |c| c.pan);
+            let mute = channel_signal(mixer, &key, |c| c.mute);
+            let solo = channel_signal(mixer, &key, |c| c.solo);
+            let send_db = channel_signal(mixer, &key, |c| {
+                c.sends.get(SEND_BUS).copied().unwrap_or(0.0)
+            });
+            let key = store_value(key);
+            let set_send_db = Callback::new(move |db| mixer.update(|mixer| {
+                mixer.apply(MixerAction::SetSend(key.get_value(), SEND_BUS.to_string(), db))
+            }));
             view!{
-                <div>{track.label}</div>
-                <LevelMeter analyser= track.analyser.clone()  />
-            }
+                <ChannelStrip
+                    label=label
+                    analyser=analyser
+                    gain_db=gain_db
+                    pan=pan
+                    mute=mute
+                    solo=solo
+                    set_gain_db=move |db| mixer.update(|mixer| {
+                        mixer.apply(MixerAction::SetGain(key.get_value(), db))
+                    })
+                    set_pan=move |p| mixer.update(|mixer| {
+                        mixer.apply(MixerAction::SetPan(key.get_value(), p))
+                    })
+                    set_mute=move |m| mixer.update(|mixer| {
+                        mixer.apply(MixerAction::SetMute(key.get_value(), m))
+                    })
+                    set_solo=move |s| mixer.update(|mixer| {
+                        mixer.apply(MixerAction::SetSolo(key.get_value(), s))
+                    })
+                    send=local.then_some((send_db, set_send_db))
+                />
+            }.into_view()
         }
         />
-        <LevelMeter analyser=graph.get().analyser />
+        </div>
+        <div class="section">
+            <BusStrip
+                label="Monitor"
+                level=Signal::derive(move || mixer.with(|mixer| mixer.master_db))
+                set_level=move |db| mixer.update(|mixer| mixer.apply(MixerAction::SetMaster(db)))
+                analyser=graph.get().monitor.analyser
+            />
+            <BusStrip
+                label="My input"
+                level=Signal::derive(move || mixer.with(|mixer| mixer.input_monitor_db))
+                set_level=move |db| mixer.update(|mixer| mixer.apply(MixerAction::SetInputMonitor(db)))
+            />
+            <BusStrip
+                label="Click"
+                level=Signal::derive(move || mixer.with(|mixer| mixer.click_db))
+                set_level=move |db| mixer.update(|mixer| mixer.apply(MixerAction::SetClick(db)))
+            />
+            <div class="box has-text-centered mr-2" style="display: inline-block;">
+                <p class="heading">Metronome</p>
+                <input
+                    class="input is-small"
+                    type="number"
+                    min="30"
+                    max="300"
+                    prop:value=move || tempo.get().to_string()
+                    on:change=move |e| {
+                        if let Ok(bpm) = event_target_value(&e).parse::<f64>() {
+                            set_tempo.set(bpm.clamp(30.0, 300.0))
+                        }
+                    }
+                />
+                <button
+                    class=move || if clicking.get() { "button is-small is-warning mt-2" } else { "button is-small mt-2" }
+                    on:click=toggle_click
+                >
+                    {move || if clicking.get() { "Stop" } else { "Start" }}
+                </button>
+            </div>
+            <BusStrip
+                label="Send"
+                level=Signal::derive(move || mixer.with(|mixer| mixer.send_db))
+                set_level=move |db| mixer.update(|mixer| mixer.apply(MixerAction::SetSendBus(db)))
+                analyser=graph.get().send.analyser
+            />
+        </div>
+        <ScenePanel mixer=mixer on_recall=recall/>
+        <div class="section">
+            <RecordPanel graph=graph mixer=mixer/>
+        </div>
     </section>
     }
 }
 
-#[component]
-fn SelectDevices(set_input_device: WriteSignal<String>) -> impl IntoView {
-    let devices = create_resource(
-        || (),
-        |_| async move {
-            let devices = get_devices().await.expect("Failed");
-            devices
-                .into_iter()
-                .filter(|dev| dev.kind == "audioinput")
-                .collect::<Vec<InputDeviceInfo>>()
-        },
-    );
-
-    view! {
-    <Transition
-     fallback = || view!{<p> Loading... </p>}
-    >
-        {
-            move || {
-                if ! devices.loading().get() {
-                    view!{
-                        <div class="field">
-                            <label class="label">Sound input</label>
-                            <div  class="select" >
-                                <select on:change=move |e| set_input_device.set(event_target_value(&e))>
-                                    <For
-                                     each = move || devices.get().unwrap()
-                                     key=|dev: &InputDeviceInfo| dev.deviceId.clone()
-                                     children=move |dev| {
-                                        view!{<option value={dev.deviceId.clone()}>{&dev.label}</option>}
-                                    }/>
-                                </select>
-                            </div>
-                        </div>
-                    }
-                } else {
-                    view!{<div> WAT </div>}
-                }
+/// Remote tracks are labelled after their owner and the input the owner
+/// named them after, unlike track ids that is still the same in the next
+/// session, which lets scenes find the channel again
+fn named_channels(
+    graph: &AudioGraph,
+    profiles: &HashMap<Uuid, MemberProfile>,
+    labels: &HashMap<String, String>,
+) -> Vec<(ChannelKey, String)> {
+    let mut counts = HashMap::<Uuid, usize>::new();
+    graph
+        .channels()
+        .into_iter()
+        .map(|(key, label)| {
+            let Some(profile) = profiles.get(&key.owner) else {
+                return (key, label);
+            };
+            if let Some(input) = labels.get(&key.track) {
+                return (key, format!("{} {input}", profile.name));
             }
-        }
-        </Transition>
-    }
+            let count = counts.entry(key.owner).or_default();
+            *count += 1;
+            let label = match *count {
+                1 => profile.name.clone(),
+                n => format!("{} {n}", profile.name),
+            };
+            (key, label)
+        })
+        .collect()
+}
+
+/// One setting of a channel, only changes when that setting does
+fn channel_signal<T: Clone + Default + PartialEq + 'static>(
+    mixer: RwSignal<MixerState>,
+    key: &ChannelKey,
+    field: fn(&ChannelState) -> T,
+) -> Signal<T> {
+    let key = key.clone();
+    create_memo(move |_| mixer.with(|mixer| mixer.channel(&key).map(field).unwrap_or_default())).into()
 }
@@ -0,0 +1,43 @@
+use leptos::*;
+
+use crate::audio::InputDeviceInfo;
+use crate::settings::use_settings;
+
+/// Where I hear the session, e.g. headphones on an interface. The choice is
+/// kept in the settings.
+#[component]
+pub fn OutputSelect(#[prop(into)] devices: Signal<Vec<InputDeviceInfo>>) -> impl IntoView {
+    let settings = use_settings();
+    let selected = move || settings.with(|s| s.output_device.clone().unwrap_or_default());
+    let pick = move |e| {
+        let device = event_target_value(&e);
+        settings.update(|s| s.output_device = (!device.is_empty()).then_some(device));
+    };
+
+    view! {
+        <div class="field">
+            <label class="label">Sound output</label>
+            <div class="control">
+                <div class="select">
+                    <select on:change=pick>
+                        <option value="" selected=move || selected().is_empty()>System default</option>
+                        {move || devices.with(|devices| {
+                            devices
+                                .iter()
+                                .filter(|dev| dev.deviceId != "default")
+                                .map(|dev| {
+                                    let id = dev.deviceId.clone();
+                                    view! {
+                                        <option value=id.clone() selected=move || selected() == id>
+                                            {dev.label.clone()}
+                                        </option>
+                                    }
+                                })
+                                .collect_view()
+                        })}
+                    </select>
+                </div>
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,54 @@
+use crate::settings::use_settings;
+use leptos::*;
+
+fn non_empty(value: String) -> Option<String> {
+    if value.trim().is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Edit how I am presented to the other members of a session
+#[component]
+pub fn ProfileEditor() -> impl IntoView {
+    let settings = use_settings();
+    let profile = create_memo(move |_| settings.get().profile);
+
+    view! {
+        <div class="field">
+            <label class="label">Name</label>
+            <div class="control">
+                <input
+                    class="input"
+                    type="text"
+                    placeholder="Guest"
+                    prop:value=move || profile.get().name
+                    on:change=move |e| settings.update(|s| s.profile.name = event_target_value(&e))
+                />
+            </div>
+        </div>
+        <div class="field">
+            <label class="label">Instrument</label>
+            <div class="control">
+                <input
+                    class="input"
+                    type="text"
+                    prop:value=move || profile.get().instrument.unwrap_or_default()
+                    on:change=move |e| settings.update(|s| s.profile.instrument = non_empty(event_target_value(&e)))
+                />
+            </div>
+        </div>
+        <div class="field">
+            <label class="label">Avatar url</label>
+            <div class="control">
+                <input
+                    class="input"
+                    type="url"
+                    prop:value=move || profile.get().avatar.unwrap_or_default()
+                    on:change=move |e| settings.update(|s| s.profile.avatar = non_empty(event_target_value(&e)))
+                />
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,71 @@
+use gloo_console::log;
+use js_sys::Date;
+use leptos::*;
+use protocol::mixer::MixerState;
+
+use crate::audio::{download, AudioGraph, Recording};
+
+/// Records every track, mine before any processing and everyone else's as
+/// received, and hands them out as separate files in a zip when stopped
+#[component]
+pub fn RecordPanel(graph: ReadSignal<AudioGraph>, mixer: RwSignal<MixerState>) -> impl IntoView {
+    let recording = store_value(None::<Recording>);
+    // How many tracks are being recorded
+    let (taking, set_taking) = create_signal(None::<usize>);
+    let (saving, set_saving) = create_signal(false);
+    on_cleanup(move || {
+        if let Some(take) = recording.try_update_value(|r| r.take()).flatten() {
+            take.discard();
+        }
+    });
+
+    let start = move |_| {
+        let started = mixer.with_untracked(|mixer| graph.with_untracked(|graph| graph.record(mixer)));
+        match started {
+            Ok(take) => {
+                set_taking.set(Some(take.tracks()));
+                recording.set_value(Some(take));
+            }
+            Err(e) => log!("Failed to start recording", e),
+        }
+    };
+    let stop = move |_| {
+        let Some(take) = recording.try_update_value(|r| r.take()).flatten() else {
+            return;
+        };
+        set_taking.set(None);
+        set_saving.set(true);
+        spawn_local(async move {
+            let stamp = String::from(Date::new_0().to_iso_string()).replace(':', "-");
+            match take.finish().await {
+                Ok(archive) => {
+                    if let Err(e) = download(&archive, &format!("livet-{stamp}.zip")) {
+                        log!("Failed to download the recording", e);
+                    }
+                }
+                Err(e) => log!("Failed to save the recording", e),
+            }
+            set_saving.set(false);
+        });
+    };
+
+    view! {
+        <div class="field is-grouped">
+            <div class="control">
+                {move || match taking.get() {
+                    Some(_) => view! { <button class="button is-danger" on:click=stop>Stop</button> },
+                    None => view! {
+                        <button class="button" class:is-loading=saving disabled=saving on:click=start>
+                            Record
+                        </button>
+                    },
+                }}
+            </div>
+            <div class="control">
+                {move || taking.get().map(|tracks| view! {
+                    <span class="tag is-danger is-light">{format!("Recording {tracks} tracks")}</span>
+                })}
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,114 @@
+use gloo_console::log;
+use leptos::*;
+use protocol::mixer::{MixerScene, MixerState};
+
+use crate::network::{delete_scene, get_scenes, put_scene, use_access_token};
+use crate::settings::{load_scenes, save_scenes, use_settings};
+
+/// Saves the mix under a name and brings it back later. Scenes are kept in
+/// the browser and, when enabled in the settings and signed in, on the
+/// server as well.
+#[component]
+pub fn ScenePanel(
+    mixer: RwSignal<MixerState>,
+    #[prop(into)] on_recall: Callback<MixerState>,
+) -> impl IntoView {
+    let settings = use_settings();
+    let band = settings.get_untracked().band;
+    let token = use_access_token();
+    let server = move || {
+        let settings = settings.get_untracked();
+        let band = settings.sync_scenes.then_some(settings.band).flatten()?;
+        Some((token.get_untracked()?, band))
+    };
+
+    let scenes = create_rw_signal(load_scenes(band));
+    create_effect(move |_| scenes.with(|scenes| save_scenes(band, scenes)));
+
+    let store = move |scene: MixerScene| {
+        scenes.update(|scenes| {
+            scenes.retain(|s| s.name != scene.name);
+            scenes.push(scene);
+            scenes.sort_by(|a, b| a.name.cmp(&b.name));
+        })
+    };
+
+    // What is on the server wins over what this browser remembers
+    if let Some((token, band)) = server() {
+        spawn_local(async move {
+            match get_scenes(Some(token), band).await {
+                Ok(stored) => stored.into_iter().for_each(store),
+                Err(e) => log!(format!("Failed to load scenes: {:?}", e)),
+            }
+        });
+    }
+
+    let (name, set_name) = create_signal(String::new());
+    let save = move |_| {
+        let name = name.get_untracked().trim().to_string();
+        if name.is_empty() {
+            return;
+        }
+        let scene = MixerScene {
+            name,
+            mixer: mixer.get_untracked(),
+        };
+        store(scene.clone());
+        if let Some((token, band)) = server() {
+            spawn_local(async move {
+                if let Err(e) = put_scene(Some(token), band, &scene).await {
+                    log!(format!("Failed to upload scene: {:?}", e));
+                }
+            });
+        }
+    };
+    let remove = move |name: String| {
+        scenes.update(|scenes| scenes.retain(|s| s.name != name));
+        if let Some((token, band)) = server() {
+            spawn_local(async move {
+                if let Err(e) = delete_scene(Some(token), band, &name).await {
+                    log!(format!("Failed to delete scene: {:?}", e));
+                }
+            });
+        }
+    };
+
+    view! {
+        <div class="box">
+            <p class="heading">Scenes</p>
+            <div class="field has-addons">
+                <div class="control">
+                    <input
+                        class="input is-small"
+                        type="text"
+                        placeholder="Song or part"
+                        prop:value=name
+                        on:input=move |e| set_name.set(event_target_value(&e))
+                    />
+                </div>
+                <div class="control">
+                    <button class="button is-small is-primary" on:click=save>Save</button>
+                </div>
+            </div>
+            <div class="field is-grouped is-grouped-multiline">
+                <For
+                    each=move || scenes.get()
+                    key=|scene| scene.name.clone()
+                    children=move |scene| {
+                        let name = scene.name.clone();
+                        view! {
+                            <div class="control">
+                                <div class="tags has-addons">
+                                    <a class="tag is-link" on:click=move |_| on_recall.call(scene.mixer.clone())>
+                                        {scene.name.clone()}
+                                    </a>
+                                    <a class="tag is-delete" on:click=move |_| remove(name.clone())></a>
+                                </div>
+                            </div>
+                        }
+                    }
+                />
+            </div>
+        </div>
+    }
+}
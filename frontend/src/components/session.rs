@@ -2,22 +2,78 @@ use std::collections::HashMap;
 
 use gloo_console::log;
 use leptos::*;
-use protocol::{ClientCommand, ServerCommand};
+use protocol::{ClientCommand, MemberProfile, QualityPreset, Role, ServerCommand, SessionVisibility};
 use uuid::Uuid;
 use wasm_bindgen::{closure::Closure, JsCast};
-use web_sys::{MediaStream, MediaStreamTrack, MessageEvent, WebSocket};
+use web_sys::{MediaStreamTrack, MessageEvent, WebSocket};
 
+use crate::audio::SendStream;
 use crate::components::BandMember;
+use crate::network::{use_access_token, ws_url};
+use crate::settings::use_settings;
+
+/// How to get into a session, directly by name or through an invite
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionAccess {
+    Open {
+        session: String,
+        visibility: SessionVisibility,
+        quality: QualityPreset,
+    },
+    Invite(String),
+}
+
+impl SessionAccess {
+    fn query(&self) -> Vec<String> {
+        match self {
+            SessionAccess::Open {
+                session,
+                visibility,
+                quality,
+            } => vec![
+                format!("session={}", String::from(js_sys::encode_uri_component(session))),
+                format!("visibility={}", visibility.as_str()),
+                format!("quality={}", quality.as_str()),
+            ],
+            SessionAccess::Invite(token) => vec![format!(
+                "invite={}",
+                String::from(js_sys::encode_uri_component(token))
+            )],
+        }
+    }
+}
 
 #[component]
 pub fn Session(
-    stream: ReadSignal<Option<MediaStream>>,
+    access: SessionAccess,
+    streams: ReadSignal<Vec<SendStream>>,
     set_tracks: WriteSignal<HashMap<Uuid, Vec<MediaStreamTrack>>>,
+    profiles: RwSignal<HashMap<Uuid, MemberProfile>>,
+    set_role: WriteSignal<Option<Role>>,
+    set_me: WriteSignal<Option<Uuid>>,
 ) -> impl IntoView {
     let (members, set_members) = create_signal(HashMap::new());
     let (connected, set_connected) = create_signal(false);
+    // The session's creator picked the quality, the server tells us on joining
+    let (quality, set_quality) = create_signal(QualityPreset::default());
 
-    let ws = WebSocket::new("ws://127.0.0.1:3000/ws").unwrap();
+    // Introduce ourselves with the name from the settings
+    let settings = use_settings();
+    let profile = create_memo(move |_| settings.get().profile);
+    let name = profile.get_untracked().name;
+    let mut params = access.query();
+    if !name.is_empty() {
+        params.push(format!("name={}", String::from(js_sys::encode_uri_component(&name))));
+    }
+    if let Some(band) = settings.get_untracked().band {
+        params.push(format!("band={band}"));
+    }
+    // Signed in musicians get their stored profile
+    if let Some(token) = use_access_token().get_untracked() {
+        params.push(format!("token={}", String::from(js_sys::encode_uri_component(&token))));
+    }
+    let url = ws_url(&format!("ws?{}", params.join("&")));
+    let ws = WebSocket::new(&url).unwrap();
 
     let onopen = move |_: MessageEvent| set_connected.update(|c| *c = true);
     let cb = Closure::wrap(Box::new(onopen) as Box<dyn FnMut(_)>);
@@ -33,18 +89,36 @@ pub fn Session(
         let message = event.data().as_string().unwrap();
         match serde_json::from_str::<protocol::ServerCommand>(&message) {
             Ok(command) => match command.clone() {
-                ServerCommand::AddMember(uuid, polite) => {
+                ServerCommand::Joined(me, role, session_quality, instance) => {
+                    log!("===================== Joined as", role.as_str(), session_quality.as_str());
+                    log!("Diagnostics are kept under", instance.to_string());
+                    set_me.set(Some(me));
+                    set_role.set(Some(role));
+                    set_quality.set(session_quality);
+                }
+                ServerCommand::AddMember(uuid, polite, profile, role) => {
                     log!("===================== Add", &uuid.to_string());
-                    let action = create_rw_signal(ServerCommand::AddMember(uuid, polite));
+                    profiles.update(|ps| {
+                        ps.insert(uuid, profile.clone());
+                    });
+                    let action = create_rw_signal(ServerCommand::AddMember(uuid, polite, profile, role));
                     set_members.update(move |ms| {
                         ms.insert(uuid, action);
                     });
                 }
+                ServerCommand::UpdateMember(uuid, profile) => {
+                    profiles.update(|ps| {
+                        ps.insert(uuid, profile);
+                    });
+                }
                 ServerCommand::DropMember(uuid) => {
                     log!("===================== Drop", &uuid.to_string());
                     set_tracks.update(|tracks| {
                         tracks.remove(&uuid);
                     });
+                    profiles.update(|ps| {
+                        ps.remove(&uuid);
+                    });
                     set_members.update(move |ms| {
                         ms.remove(&uuid);
                     });
@@ -72,6 +146,20 @@ pub fn Session(
     cb.forget();
 
     let (ws, _) = create_signal(ws);
+
+    // Keep the other members up to date with profile changes. The first run
+    // only sees what we joined with, the server already has that or the
+    // stored profile, which mustn't be overwritten by these settings.
+    create_effect(move |first_run: Option<()>| {
+        let profile = profile.get();
+        if first_run.is_some() && connected.get_untracked() {
+            let message = serde_json::to_string(&ClientCommand::UpdateProfile(profile)).unwrap();
+            if ws.get_untracked().send_with_str(&message).is_err() {
+                log!("Couldn't send profile to signal server");
+            }
+        }
+    });
+
     let send_message = move || {
         let ws = ws.get();
         move |message: ClientCommand| {
@@ -92,15 +180,24 @@ pub fn Session(
         each=move || members.get()
         key= |(k,_)| k.clone()
         children= move |(k, v)| {
-            let (action, set_action) = v.split();
+            let action = v.read_only();
+            let profile = Signal::derive(move || {
+                profiles.get().get(&k).cloned().unwrap_or_default()
+            });
             view!{
             <BandMember
                 uuid={k.to_owned()}
+                profile=profile
                 action=action
-                stream=stream
+                streams=streams
+                quality=quality
                 send_message=send_message()
                 add_track=move |track: MediaStreamTrack| set_tracks.update(|tracks| tracks.entry(k).or_insert(vec![]).push(track))
-                update_action= move |command| set_action.set(command)
+                remove_track=move |track: MediaStreamTrack| set_tracks.update(|tracks| {
+                    if let Some(tracks) = tracks.get_mut(&k) {
+                        tracks.retain(|t| t.id() != track.id());
+                    }
+                })
             />}
         }
     />}
@@ -2,9 +2,11 @@ mod audio;
 mod components;
 mod network;
 mod pages;
+mod settings;
 
 use crate::components::{Auth, NavBar};
-use crate::pages::{Home, Settings, Studio};
+use crate::pages::{Home, Join, Settings, Studio};
+use crate::settings::provide_settings;
 use console_error_panic_hook;
 use leptos::*;
 use leptos_router::*;
@@ -20,6 +22,8 @@ fn main() {
 
 #[component]
 fn App() -> impl IntoView {
+    provide_settings();
+
     view! {
     <div id="root">
         <Router>
@@ -39,6 +43,10 @@ fn App() -> impl IntoView {
                             path="studio"
                             view=Studio
                         />
+                        <Route
+                            path="join/:token"
+                            view=Join
+                        />
                     </Routes>
                 </main>
             </Auth>
@@ -1,11 +1,51 @@
+mod api;
+mod bitrate;
+mod control;
 mod ice;
+mod jitter;
+mod latency;
+mod recovery;
 mod rtc;
 mod sdp;
+mod stats;
 
+pub use api::{create_invite, delete_scene, get_bands, get_invite, get_scenes, put_scene};
+pub use bitrate::{BitrateController, BitratePolicy, BitrateSettings};
+pub use control::{
+    provide_channel_hubs, use_control_hub, use_midi_hub, use_track_labels, ControlHub, MidiHub,
+    Route, TrackLabels,
+};
+pub use jitter::{JitterBufferAuto, MAX_JITTER_TARGET_MS};
+pub use latency::LatencyTracker;
+pub use recovery::PeerState;
 pub use rtc::Rtc;
+pub use stats::PeerStats;
+
+use leptos::{use_context, Signal};
+use leptos_oidc::Auth;
+
+const SIGNAL_SERVER: &str = "127.0.0.1:3000";
+
+/// The signed in user's access token, which the server checks to know who
+/// we are
+pub fn use_access_token() -> Signal<Option<String>> {
+    let auth = use_context::<Auth>();
+    Signal::derive(move || auth.as_ref().and_then(|auth| auth.access_token()))
+}
+
+pub fn ws_url(path: &str) -> String {
+    format!("ws://{SIGNAL_SERVER}/{path}")
+}
+
+pub fn http_url(path: &str) -> String {
+    format!("http://{SIGNAL_SERVER}/{path}")
+}
 
 #[derive(Clone, Debug)]
 pub enum Error {
     ConfigurationError,
     ConnectionError,
+    RequestFailed(u16),
+    /// Needs a signed in user
+    SignedOut,
 }
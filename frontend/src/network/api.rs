@@ -0,0 +1,94 @@
+use gloo_net::http::{Request, RequestBuilder, Response};
+use protocol::mixer::{MixerScene, MixerState};
+use protocol::{BandSummary, Invite, InviteRequest};
+
+use super::{http_url, Error};
+
+fn check(response: Response) -> Result<Response, Error> {
+    if response.ok() {
+        Ok(response)
+    } else {
+        Err(Error::RequestFailed(response.status()))
+    }
+}
+
+/// Requests on behalf of the signed in user
+fn authorized(request: RequestBuilder, token: Option<String>) -> Result<RequestBuilder, Error> {
+    let token = token.ok_or(Error::SignedOut)?;
+    Ok(request.header("Authorization", &format!("Bearer {token}")))
+}
+
+pub async fn create_invite(token: Option<String>, request: &InviteRequest) -> Result<Invite, Error> {
+    let response = authorized(Request::post(&http_url("invites")), token)?
+        .json(request)
+        .map_err(|_| Error::ConfigurationError)?
+        .send()
+        .await
+        .map_err(|_| Error::ConnectionError)?;
+    check(response)?
+        .json()
+        .await
+        .map_err(|_| Error::ConnectionError)
+}
+
+pub async fn get_invite(token: &str) -> Result<Invite, Error> {
+    let response = Request::get(&http_url(&format!("invites/{token}")))
+        .send()
+        .await
+        .map_err(|_| Error::ConnectionError)?;
+    check(response)?
+        .json()
+        .await
+        .map_err(|_| Error::ConnectionError)
+}
+
+/// The bands the signed in user plays in
+pub async fn get_bands(token: Option<String>) -> Result<Vec<BandSummary>, Error> {
+    let response = authorized(Request::get(&http_url("bands")), token)?
+        .send()
+        .await
+        .map_err(|_| Error::ConnectionError)?;
+    check(response)?
+        .json()
+        .await
+        .map_err(|_| Error::ConnectionError)
+}
+
+/// Scenes belong to the signed in user, the server tells them by the token
+fn scenes_url(band: i32) -> String {
+    http_url(&format!("bands/{band}/scenes"))
+}
+
+fn scene_url(band: i32, name: &str) -> String {
+    let name = String::from(js_sys::encode_uri_component(name));
+    format!("{}/{name}", scenes_url(band))
+}
+
+pub async fn get_scenes(token: Option<String>, band: i32) -> Result<Vec<MixerScene>, Error> {
+    let response = authorized(Request::get(&scenes_url(band)), token)?
+        .send()
+        .await
+        .map_err(|_| Error::ConnectionError)?;
+    check(response)?
+        .json()
+        .await
+        .map_err(|_| Error::ConnectionError)
+}
+
+pub async fn put_scene(token: Option<String>, band: i32, scene: &MixerScene) -> Result<(), Error> {
+    let response = authorized(Request::put(&scene_url(band, &scene.name)), token)?
+        .json::<MixerState>(&scene.mixer)
+        .map_err(|_| Error::ConfigurationError)?
+        .send()
+        .await
+        .map_err(|_| Error::ConnectionError)?;
+    check(response).map(|_| ())
+}
+
+pub async fn delete_scene(token: Option<String>, band: i32, name: &str) -> Result<(), Error> {
+    let response = authorized(Request::delete(&scene_url(band, name)), token)?
+        .send()
+        .await
+        .map_err(|_| Error::ConnectionError)?;
+    check(response).map(|_| ())
+}
@@ -0,0 +1,225 @@
+use js_sys::{Array, Reflect};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::RtcRtpSender;
+
+use super::{PeerStats, Rtc};
+
+/// What to give up first when the network can't keep up
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BitratePolicy {
+    /// Back off early, before queues build up in the network
+    #[default]
+    Latency,
+    /// Only back off when packets actually get lost
+    Quality,
+}
+
+impl BitratePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BitratePolicy::Latency => "latency",
+            BitratePolicy::Quality => "quality",
+        }
+    }
+
+    fn priority(&self) -> &'static str {
+        match self {
+            BitratePolicy::Latency => "high",
+            BitratePolicy::Quality => "medium",
+        }
+    }
+}
+
+impl std::str::FromStr for BitratePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "latency" => Ok(BitratePolicy::Latency),
+            "quality" => Ok(BitratePolicy::Quality),
+            other => Err(format!("Unknown bitrate policy {other}")),
+        }
+    }
+}
+
+/// Bounds in kbit/s
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct BitrateSettings {
+    pub policy: BitratePolicy,
+    pub min: u32,
+    pub max: u32,
+}
+
+impl Default for BitrateSettings {
+    fn default() -> Self {
+        Self {
+            policy: BitratePolicy::default(),
+            min: 32,
+            max: 510,
+        }
+    }
+}
+
+impl BitrateSettings {
+    fn clamp(&self, kbps: f64) -> u32 {
+        let max = self.max.max(self.min);
+        (kbps.round() as u32).clamp(self.min, max)
+    }
+}
+
+/// Steers the outgoing bitrate towards what the path to one peer can carry,
+/// based on what the peer reports back about our stream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BitrateController {
+    current: f64,
+    /// Lowest round trip time seen, anything above it is queueing
+    base_rtt: Option<f64>,
+}
+
+impl BitrateController {
+    pub fn new(start: u32) -> Self {
+        Self {
+            current: f64::from(start),
+            base_rtt: None,
+        }
+    }
+
+    /// Returns the bitrate to use from now on
+    pub fn update(&mut self, stats: &PeerStats, settings: &BitrateSettings) -> u32 {
+        if let Some(rtt) = stats.round_trip_time {
+            self.base_rtt = Some(self.base_rtt.map_or(rtt, |base| base.min(rtt)));
+        }
+        let loss = stats.outbound_loss.unwrap_or(0.0);
+
+        let (congested, decrease, increase) = match settings.policy {
+            BitratePolicy::Latency => {
+                let queueing = match (stats.round_trip_time, self.base_rtt) {
+                    (Some(rtt), Some(base)) => rtt > base * 1.5 + 5.0,
+                    _ => false,
+                };
+                let jittery = stats.outbound_jitter.map_or(false, |j| j > 20.0);
+                (loss > 0.02 || queueing || jittery, 0.7, 1.05)
+            }
+            BitratePolicy::Quality => (loss > 0.05, 0.85, 1.1),
+        };
+
+        self.current = if congested {
+            self.current * decrease
+        } else {
+            self.current * increase
+        };
+        let target = settings.clamp(self.current);
+        self.current = f64::from(target);
+        target
+    }
+}
+
+impl Rtc {
+    /// Applies the bitrate limit and priority to every audio sender, senders
+    /// that already use them are left alone
+    pub async fn set_max_bitrate(&self, kbps: u32, policy: BitratePolicy) -> Result<(), JsValue> {
+        let max_bitrate = JsValue::from(kbps * 1000);
+        let priority = JsValue::from_str(policy.priority());
+
+        for sender in self.connection.get_senders().iter() {
+            let sender = RtcRtpSender::from(sender);
+            if sender.track().map(|t| t.kind()).as_deref() != Some("audio") {
+                continue;
+            }
+            let parameters = sender.get_parameters();
+            let encodings = Array::from(&Reflect::get(&parameters, &JsValue::from_str("encodings"))?);
+
+            let mut changed = false;
+            for encoding in encodings.iter() {
+                for (key, value) in [
+                    ("maxBitrate", &max_bitrate),
+                    ("priority", &priority),
+                    ("networkPriority", &priority),
+                ] {
+                    let key = JsValue::from_str(key);
+                    if Reflect::get(&encoding, &key)? != *value {
+                        Reflect::set(&encoding, &key, value)?;
+                        changed = true;
+                    }
+                }
+            }
+
+            if changed {
+                JsFuture::from(sender.set_parameters_with_parameters(&parameters)).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(policy: BitratePolicy) -> BitrateSettings {
+        BitrateSettings {
+            policy,
+            ..BitrateSettings::default()
+        }
+    }
+
+    fn stats(rtt: Option<f64>, loss: Option<f64>) -> PeerStats {
+        PeerStats {
+            round_trip_time: rtt,
+            outbound_loss: loss,
+            ..PeerStats::default()
+        }
+    }
+
+    #[test]
+    fn quality_only_backs_off_on_loss() {
+        let settings = settings(BitratePolicy::Quality);
+        let mut controller = BitrateController::new(100);
+        assert_eq!(controller.update(&stats(Some(20.0), None), &settings), 110);
+        // Queueing is no reason to give up quality
+        assert_eq!(controller.update(&stats(Some(200.0), Some(0.01)), &settings), 121);
+        assert_eq!(controller.update(&stats(Some(20.0), Some(0.1)), &settings), 103);
+    }
+
+    #[test]
+    fn latency_backs_off_when_queues_build_up() {
+        let settings = settings(BitratePolicy::Latency);
+        let mut controller = BitrateController::new(100);
+        assert_eq!(controller.update(&stats(Some(20.0), None), &settings), 105);
+        assert_eq!(controller.update(&stats(Some(30.0), None), &settings), 110);
+        assert_eq!(controller.update(&stats(Some(50.0), None), &settings), 77);
+        // The lowest round trip is the baseline, later ones don't raise it
+        assert_eq!(controller.update(&stats(Some(34.0), None), &settings), 81);
+        assert_eq!(controller.update(&stats(Some(20.0), Some(0.03)), &settings), 57);
+    }
+
+    #[test]
+    fn latency_backs_off_on_jitter() {
+        let settings = settings(BitratePolicy::Latency);
+        let mut controller = BitrateController::new(100);
+        let jittery = PeerStats {
+            outbound_jitter: Some(30.0),
+            ..PeerStats::default()
+        };
+        assert_eq!(controller.update(&jittery, &settings), 70);
+    }
+
+    #[test]
+    fn stays_within_settings() {
+        let settings = BitrateSettings {
+            policy: BitratePolicy::Quality,
+            min: 48,
+            max: 128,
+        };
+        let mut controller = BitrateController::new(120);
+        assert_eq!(controller.update(&stats(None, None), &settings), 128);
+        assert_eq!(controller.update(&stats(None, None), &settings), 128);
+
+        let mut controller = BitrateController::new(50);
+        assert_eq!(controller.update(&stats(None, Some(0.5)), &settings), 48);
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use gloo::console::log;
+use leptos::*;
+use protocol::{MidiMessage, PeerMessage};
+use serde::{de::DeserializeOwned, Serialize};
+use uuid::Uuid;
+use wasm_bindgen::prelude::*;
+use web_sys::{MessageEvent, RtcDataChannel, RtcDataChannelInit, RtcDataChannelState};
+
+use super::Rtc;
+
+/// Both ends create the channels with the same ids, so they don't need to be
+/// announced in band
+const CONTROL_CHANNEL_ID: u16 = 0;
+const MIDI_CHANNEL_ID: u16 = 1;
+const MIDI_RELIABLE_CHANNEL_ID: u16 = 2;
+
+/// A member's channels for one kind of message. What has to arrive goes over
+/// `reliable`, the rest over `fast` where there is one.
+#[derive(Clone, Debug)]
+pub struct Route {
+    pub reliable: RtcDataChannel,
+    pub fast: Option<RtcDataChannel>,
+}
+
+impl Route {
+    fn pick<M: Delivery>(&self, message: &M) -> &RtcDataChannel {
+        match &self.fast {
+            Some(fast) if !message.must_arrive() => fast,
+            _ => &self.reliable,
+        }
+    }
+}
+
+impl From<RtcDataChannel> for Route {
+    fn from(reliable: RtcDataChannel) -> Self {
+        Route { reliable, fast: None }
+    }
+}
+
+/// Whether a message is worth the wait of a retransmission
+pub trait Delivery {
+    fn must_arrive(&self) -> bool;
+}
+
+impl Delivery for PeerMessage {
+    fn must_arrive(&self) -> bool {
+        true
+    }
+}
+
+impl Delivery for MidiMessage {
+    fn must_arrive(&self) -> bool {
+        self.ends_notes()
+    }
+}
+
+impl Rtc {
+    fn negotiated_channel(&self, label: &str, id: u16, reliable: bool) -> RtcDataChannel {
+        let mut init = RtcDataChannelInit::new();
+        init.negotiated(true);
+        init.id(id);
+        if !reliable {
+            // A late note is worse than a missing one
+            init.ordered(false);
+            init.max_retransmits(0);
+        }
+        self.connection
+            .create_data_channel_with_data_channel_dict(label, &init)
+    }
+
+    /// Opens the control channel, pings are answered right away with the
+    /// time and epoch from `clock` and anything else is handed to
+    /// `on_message`. `on_open` is called whenever the channel (re)opens.
+    pub fn add_control_channel(
+        &mut self,
+        on_message: Callback<PeerMessage>,
+        on_open: Callback<()>,
+        clock: Callback<(), Option<(f64, u32)>>,
+    ) -> Result<(), JsValue> {
+        let channel = self.negotiated_channel("control", CONTROL_CHANNEL_ID, true);
+        let onopen = Closure::wrap(Box::new(move || on_open.call(())) as Box<dyn FnMut()>);
+        channel.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+        onopen.forget();
+
+        let reply = channel.clone();
+        on_messages(&channel, move |message| match message {
+            // A clock that stands still has nothing to tell
+            PeerMessage::Ping { id, sent, epoch } => {
+                if let Some((received, received_epoch)) = clock.call(()) {
+                    let pong = PeerMessage::Pong { id, sent, epoch, received, received_epoch };
+                    send_on(&reply, &pong);
+                }
+            }
+            message => on_message.call(message),
+        });
+        self.control = Some(channel);
+        Ok(())
+    }
+
+    /// Opens the MIDI channels. Notes go over an unordered channel without
+    /// retransmissions, what ends them over a reliable one so no note is
+    /// left hanging.
+    pub fn add_midi_channel(&mut self, on_message: Callback<MidiMessage>) -> Result<(), JsValue> {
+        let fast = self.negotiated_channel("midi", MIDI_CHANNEL_ID, false);
+        let reliable = self.negotiated_channel("midi-reliable", MIDI_RELIABLE_CHANNEL_ID, true);
+        on_messages(&fast, move |message| on_message.call(message));
+        on_messages(&reliable, move |message| on_message.call(message));
+        self.midi = Some(Route {
+            reliable,
+            fast: Some(fast),
+        });
+        Ok(())
+    }
+}
+
+fn on_messages<M: DeserializeOwned + 'static>(channel: &RtcDataChannel, mut handle: impl FnMut(M) + 'static) {
+    let onmessage = move |ev: MessageEvent| {
+        let Some(text) = ev.data().as_string() else {
+            return;
+        };
+        match serde_json::from_str::<M>(&text) {
+            Ok(message) => handle(message),
+            Err(e) => log!(format!("Bad data channel message: {}", e)),
+        }
+    };
+    let cb = Closure::wrap(Box::new(onmessage) as Box<dyn FnMut(_)>);
+    channel.set_onmessage(Some(cb.as_ref().unchecked_ref()));
+    cb.forget();
+}
+
+/// Messages sent before the channel opened are dropped, what goes over the
+/// data channels is all about the here and now
+fn send_on<M: Serialize>(channel: &RtcDataChannel, message: &M) {
+    if channel.ready_state() != RtcDataChannelState::Open {
+        return;
+    }
+    let message = serde_json::to_string(message).expect("Peer messages serialize");
+    if let Err(e) = channel.send_with_str(&message) {
+        log!("Failed to send on data channel", e);
+    }
+}
+
+/// Session wide access to one kind of data channel of all members
+pub struct ChannelHub<M: 'static> {
+    channels: StoredValue<HashMap<Uuid, Route>>,
+    subscribers: StoredValue<Vec<(usize, Callback<(Uuid, M)>)>>,
+    next_id: StoredValue<usize>,
+}
+
+pub type ControlHub = ChannelHub<PeerMessage>;
+pub type MidiHub = ChannelHub<MidiMessage>;
+
+impl<M> Clone for ChannelHub<M> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M> Copy for ChannelHub<M> {}
+
+impl<M: Clone + Serialize + Delivery + 'static> ChannelHub<M> {
+    fn new() -> Self {
+        Self {
+            channels: store_value(HashMap::new()),
+            subscribers: store_value(vec![]),
+            next_id: store_value(0),
+        }
+    }
+
+    pub fn register(&self, uuid: Uuid, route: impl Into<Route>) {
+        let route = route.into();
+        self.channels.update_value(|c| {
+            c.insert(uuid, route);
+        });
+    }
+
+    pub fn unregister(&self, uuid: Uuid) {
+        self.channels.update_value(|c| {
+            c.remove(&uuid);
+        });
+    }
+
+    /// Passes a message received from `uuid` on to every subscriber
+    pub fn dispatch(&self, uuid: Uuid, message: M) {
+        let subscribers = self.subscribers.get_value();
+        for (_, subscriber) in subscribers {
+            subscriber.call((uuid, message.clone()));
+        }
+    }
+
+    /// Calls `on_message` for every message from any member until the
+    /// calling component is cleaned up
+    pub fn subscribe(&self, on_message: Callback<(Uuid, M)>) {
+        let id = self.next_id.get_value();
+        self.next_id.set_value(id + 1);
+        self.subscribers.update_value(|s| s.push((id, on_message)));
+
+        let subscribers = self.subscribers;
+        on_cleanup(move || subscribers.update_value(|s| s.retain(|(i, _)| *i != id)));
+    }
+
+    pub fn send(&self, uuid: Uuid, message: &M) {
+        self.channels.with_value(|channels| {
+            if let Some(route) = channels.get(&uuid) {
+                send_on(route.pick(message), message);
+            }
+        });
+    }
+
+    pub fn broadcast(&self, message: &M) {
+        self.channels.with_value(|channels| {
+            for route in channels.values() {
+                send_on(route.pick(message), message);
+            }
+        });
+    }
+}
+
+/// What the other members named their inputs, by the id of the track the
+/// input arrives in here
+#[derive(Clone, Copy)]
+pub struct TrackLabels(RwSignal<HashMap<String, String>>);
+
+impl TrackLabels {
+    pub fn set(&self, labels: impl IntoIterator<Item = (String, String)>) {
+        self.0.update(|known| known.extend(labels));
+    }
+
+    pub fn with<T>(&self, f: impl FnOnce(&HashMap<String, String>) -> T) -> T {
+        self.0.with(f)
+    }
+
+    pub fn with_untracked<T>(&self, f: impl FnOnce(&HashMap<String, String>) -> T) -> T {
+        self.0.with_untracked(f)
+    }
+}
+
+pub fn provide_channel_hubs() {
+    provide_context(ControlHub::new());
+    provide_context(MidiHub::new());
+    provide_context(TrackLabels(create_rw_signal(HashMap::new())));
+}
+
+pub fn use_control_hub() -> ControlHub {
+    expect_context::<ControlHub>()
+}
+
+pub fn use_midi_hub() -> MidiHub {
+    expect_context::<MidiHub>()
+}
+
+pub fn use_track_labels() -> TrackLabels {
+    expect_context::<TrackLabels>()
+}
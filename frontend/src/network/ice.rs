@@ -1,45 +1,31 @@
 use gloo::console::log;
-use js_sys::JSON;
-use leptos::{Callable, Callback};
-use protocol::ClientCommand;
-use serde::{Deserialize, Serialize};
+use gloo_utils::format::JsValueSerdeExt;
+use leptos::Callable;
+use protocol::{ClientCommand, IceCandidate};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{RtcIceCandidate, RtcIceCandidateInit, RtcPeerConnection, RtcPeerConnectionIceEvent};
+use web_sys::{RtcIceCandidateInit, RtcPeerConnectionIceEvent};
 
 use super::Rtc;
 
-#[derive(Debug, Serialize, Deserialize)]
-#[allow(non_snake_case)]
-pub struct IceCandidate {
-    pub candidate: String,
-    pub sdpMid: String,
-    pub sdpMLineIndex: u16,
-}
-
 impl Rtc {
     pub fn add_ice_callback(&mut self) -> Result<(), JsValue> {
         let send_message = self.send_message.clone();
         let uuid = self.uuid.clone();
 
         let onicecandidate = move |ev: RtcPeerConnectionIceEvent| {
-            match ev.candidate() {
-                Some(candidate) => {
-                    let json_obj_candidate = candidate.to_json();
-                    let res = JSON::stringify(&json_obj_candidate).unwrap_throw();
-                    let js_ob = String::from(res.clone());
-                    send_message.call(ClientCommand::IceCandidate(
-                        uuid.clone(),
-                        serde_json::to_string(
-                            &serde_json::from_str::<IceCandidate>(&js_ob).unwrap(),
-                        )
-                        .unwrap(),
-                    ));
-                }
-                None => {
-                    log!("No candidate yet");
-                }
+            let candidate = match ev.candidate() {
+                Some(candidate) => match candidate.to_json().into_serde::<IceCandidate>() {
+                    Ok(candidate) => Some(candidate),
+                    Err(e) => {
+                        log!(format!("Could not serialize ice candidate: {}", e));
+                        return;
+                    }
+                },
+                // Gathering is done, let the peer know there is nothing more to come
+                None => None,
             };
+            send_message.call(ClientCommand::IceCandidate(uuid.clone(), candidate));
         };
 
         let cb = Closure::wrap(Box::new(onicecandidate) as Box<dyn FnMut(_)>);
@@ -49,31 +35,48 @@ impl Rtc {
         Ok(())
     }
 
-    pub async fn get_ice(&mut self, ice: String) -> Result<(), JsValue> {
-        log!(self.connection.signaling_state());
-        let icecandidate = serde_json::from_str::<IceCandidate>(&ice).map_err(|_| {
-            let message = format!("Could not deserialize Ice Candidate ");
-            JsValue::from_str(&message)
-        })?;
+    /// Candidates may arrive before the description they belong to, those are
+    /// held back until `flush_ice` is called after setting the remote description
+    pub async fn get_ice(&mut self, ice: Option<IceCandidate>) -> Result<(), JsValue> {
+        if self.connection.remote_description().is_none() {
+            self.pending_candidates.borrow_mut().push(ice);
+            return Ok(());
+        }
+        self.add_ice(ice).await
+    }
+
+    pub async fn flush_ice(&self) -> Result<(), JsValue> {
+        let pending: Vec<Option<IceCandidate>> =
+            self.pending_candidates.borrow_mut().drain(..).collect();
+        for ice in pending {
+            self.add_ice(ice).await?;
+        }
+        Ok(())
+    }
+
+    async fn add_ice(&self, ice: Option<IceCandidate>) -> Result<(), JsValue> {
+        let init = match &ice {
+            Some(candidate) => Some(
+                JsValue::from_serde(candidate)
+                    .map_err(|_| JsValue::from_str("Could not deserialize Ice Candidate"))?
+                    .unchecked_into::<RtcIceCandidateInit>(),
+            ),
+            None => None,
+        };
 
-        let mut rtc_ice_init = RtcIceCandidateInit::new("");
-        rtc_ice_init.candidate(&icecandidate.candidate);
-        rtc_ice_init.sdp_m_line_index(Some(icecandidate.sdpMLineIndex));
-        rtc_ice_init.sdp_mid(Some(&icecandidate.sdpMid));
+        let added = JsFuture::from(
+            self.connection
+                .add_ice_candidate_with_opt_rtc_ice_candidate_init(init.as_ref()),
+        )
+        .await;
 
-        match RtcIceCandidate::new(&rtc_ice_init) {
-            Ok(x) => {
-                JsFuture::from(
-                    self.connection
-                        .add_ice_candidate_with_opt_rtc_ice_candidate(Some(&x)),
-                )
-                .await?;
-            }
-            Err(e) => {
-                log!("Ice Candidate Addition error, {} | {:?}", ice, &e);
+        // Candidates belonging to an offer we ignored are expected to fail
+        if let Err(e) = added {
+            if !self.ignore_offer.get() {
+                log!(format!("Ice Candidate Addition error, {:?}", ice), &e);
                 return Err(e);
             }
-        };
+        }
         Ok(())
     }
 }
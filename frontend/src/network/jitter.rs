@@ -0,0 +1,127 @@
+use js_sys::Reflect;
+use wasm_bindgen::prelude::*;
+use web_sys::RtcRtpReceiver;
+
+use super::{PeerStats, Rtc};
+
+/// Upper end of the target delay slider
+pub const MAX_JITTER_TARGET_MS: u32 = 300;
+/// Auto mode accepts this many concealment events per second
+const MAX_CONCEALMENT_RATE: f64 = 0.5;
+/// Grow fast on dropouts, shrink slowly while it is quiet
+const STEP_UP_MS: f64 = 10.0;
+const STEP_DOWN_MS: f64 = 2.0;
+/// Polls without dropouts before the target is lowered
+const CALM_POLLS: u32 = 5;
+
+/// Looks for the smallest jitter buffer that keeps dropouts rare
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JitterBufferAuto {
+    target: f64,
+    calm: u32,
+}
+
+impl JitterBufferAuto {
+    pub fn new(start: u32) -> Self {
+        Self {
+            target: f64::from(start),
+            calm: 0,
+        }
+    }
+
+    /// Returns the target delay in milliseconds to use from now on
+    pub fn update(&mut self, stats: &PeerStats) -> u32 {
+        match stats.concealment_rate {
+            Some(rate) if rate > MAX_CONCEALMENT_RATE => {
+                self.target += STEP_UP_MS;
+                self.calm = 0;
+            }
+            Some(_) => {
+                self.calm += 1;
+                if self.calm >= CALM_POLLS {
+                    self.target -= STEP_DOWN_MS;
+                    self.calm = 0;
+                }
+            }
+            None => {}
+        }
+        self.target = self.target.clamp(0.0, f64::from(MAX_JITTER_TARGET_MS));
+        self.target.round() as u32
+    }
+}
+
+impl Rtc {
+    /// Asks the browser to hold incoming audio for about `target_ms` before
+    /// playing it. Chrome has long understood `playoutDelayHint` in seconds,
+    /// `jitterBufferTarget` in milliseconds is the standard one.
+    pub fn set_jitter_buffer_target(&self, target_ms: u32) -> Result<(), JsValue> {
+        let target = JsValue::from(target_ms);
+        let hint = JsValue::from(f64::from(target_ms) / 1000.0);
+
+        for receiver in self.connection.get_receivers().iter() {
+            let receiver = RtcRtpReceiver::from(receiver);
+            if receiver.track().kind() != "audio" {
+                continue;
+            }
+            for (key, value) in [("jitterBufferTarget", &target), ("playoutDelayHint", &hint)] {
+                let key = JsValue::from_str(key);
+                if Reflect::get(&receiver, &key)? != *value {
+                    Reflect::set(&receiver, &key, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn concealment(rate: f64) -> PeerStats {
+        PeerStats {
+            concealment_rate: Some(rate),
+            ..PeerStats::default()
+        }
+    }
+
+    #[test]
+    fn steps_down_once_per_calm_stretch() {
+        let mut auto = JitterBufferAuto::new(100);
+        let targets: Vec<u32> = (0..10).map(|_| auto.update(&concealment(0.0))).collect();
+        assert_eq!(targets, [100, 100, 100, 100, 98, 98, 98, 98, 98, 96]);
+    }
+
+    #[test]
+    fn steps_up_on_dropouts_and_starts_over() {
+        let mut auto = JitterBufferAuto::new(100);
+        for _ in 0..4 {
+            auto.update(&concealment(0.0));
+        }
+        assert_eq!(auto.update(&concealment(2.0)), 110);
+        for _ in 0..4 {
+            assert_eq!(auto.update(&concealment(0.0)), 110);
+        }
+        assert_eq!(auto.update(&concealment(0.0)), 108);
+    }
+
+    #[test]
+    fn holds_without_stats() {
+        let mut auto = JitterBufferAuto::new(100);
+        for _ in 0..10 {
+            assert_eq!(auto.update(&PeerStats::default()), 100);
+        }
+    }
+
+    #[test]
+    fn stays_within_bounds() {
+        let mut auto = JitterBufferAuto::new(MAX_JITTER_TARGET_MS);
+        assert_eq!(auto.update(&concealment(2.0)), MAX_JITTER_TARGET_MS);
+
+        let mut auto = JitterBufferAuto::new(1);
+        for _ in 0..CALM_POLLS {
+            auto.update(&concealment(0.0));
+        }
+        assert_eq!(auto.update(&concealment(0.0)), 0);
+    }
+}
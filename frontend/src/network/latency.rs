@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+
+/// Number of recent measurements an estimate is based on
+const WINDOW: usize = 10;
+
+/// Application level round trip time and clock offset to one member, from
+/// ping/pong exchanges over the control channel. All values in milliseconds.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencyTracker {
+    /// Round trip time and the peer's clock minus ours
+    samples: VecDeque<(f64, f64)>,
+    /// Our epoch and the peer's the samples were taken in
+    epochs: Option<(u32, u32)>,
+}
+
+impl LatencyTracker {
+    /// `sent` and `now` are our clock when the ping left and the pong came
+    /// back, `received` is the peer's clock when it answered. Each comes
+    /// with the epoch of its clock, when either clock started a new one the
+    /// earlier samples are dropped.
+    pub fn add_sample(&mut self, sent: (f64, u32), received: (f64, u32), now: (f64, u32)) {
+        if sent.1 != now.1 {
+            // Our clock started over while the ping was out
+            return;
+        }
+        let epochs = (now.1, received.1);
+        if self.epochs != Some(epochs) {
+            self.samples.clear();
+            self.epochs = Some(epochs);
+        }
+
+        let rtt = now.0 - sent.0;
+        let offset = received.0 - (sent.0 + rtt / 2.0);
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((rtt, offset));
+    }
+
+    /// Median of the recent round trip times
+    pub fn rtt(&self) -> Option<f64> {
+        let mut rtts: Vec<f64> = self.samples.iter().map(|(rtt, _)| *rtt).collect();
+        rtts.sort_by(|a, b| a.total_cmp(b));
+        rtts.get(rtts.len() / 2).copied()
+    }
+
+    /// Offset from the fastest exchange, which had the least room for
+    /// asymmetric delays
+    pub fn clock_offset(&self) -> Option<f64> {
+        self.samples
+            .iter()
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, offset)| *offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An exchange taking `rtt` with a peer whose clock is `offset` ahead
+    fn exchange(tracker: &mut LatencyTracker, at: f64, rtt: f64, offset: f64) {
+        tracker.add_sample((at, 1), (at + rtt / 2.0 + offset, 1), (at + rtt, 1));
+    }
+
+    #[test]
+    fn nothing_measured_yet() {
+        let tracker = LatencyTracker::default();
+        assert_eq!(tracker.rtt(), None);
+        assert_eq!(tracker.clock_offset(), None);
+    }
+
+    #[test]
+    fn rtt_is_the_median() {
+        let mut tracker = LatencyTracker::default();
+        for (i, rtt) in [30.0, 10.0, 500.0, 20.0, 40.0].into_iter().enumerate() {
+            exchange(&mut tracker, i as f64 * 1000.0, rtt, 0.0);
+        }
+        assert_eq!(tracker.rtt(), Some(30.0));
+    }
+
+    #[test]
+    fn rtt_forgets_old_samples() {
+        let mut tracker = LatencyTracker::default();
+        for i in 0..WINDOW {
+            exchange(&mut tracker, i as f64 * 1000.0, 200.0, 0.0);
+        }
+        for i in WINDOW..2 * WINDOW {
+            exchange(&mut tracker, i as f64 * 1000.0, 20.0, 0.0);
+        }
+        assert_eq!(tracker.rtt(), Some(20.0));
+    }
+
+    #[test]
+    fn offset_comes_from_the_fastest_exchange() {
+        let mut tracker = LatencyTracker::default();
+        // The slow exchange spent most of its time on the way back
+        tracker.add_sample((0.0, 1), (510.0, 1), (100.0, 1));
+        exchange(&mut tracker, 1000.0, 10.0, 500.0);
+        exchange(&mut tracker, 2000.0, 30.0, 500.0);
+        assert_eq!(tracker.clock_offset(), Some(500.0));
+    }
+
+    #[test]
+    fn new_epochs_start_over() {
+        let mut tracker = LatencyTracker::default();
+        exchange(&mut tracker, 0.0, 10.0, 500.0);
+
+        // The peer's context was suspended and resumed
+        tracker.add_sample((1000.0, 1), (20.0, 2), (1040.0, 1));
+        assert_eq!(tracker.rtt(), Some(40.0));
+        assert_eq!(tracker.clock_offset(), Some(-1000.0));
+
+        // Ours started over while the ping was out
+        tracker.add_sample((2000.0, 1), (1020.0, 2), (5.0, 2));
+        assert_eq!(tracker.rtt(), Some(40.0));
+    }
+}
@@ -0,0 +1,112 @@
+use gloo::console::log;
+use leptos::{set_timeout_with_handle, Callable, Callback};
+use protocol::ClientCommand;
+use std::time::Duration;
+use wasm_bindgen::prelude::*;
+use web_sys::{Event, RtcIceConnectionState, RtcPeerConnectionState};
+
+use super::Rtc;
+
+/// How long an ICE restart gets before the connection is rebuilt from scratch
+const RESTART_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeerState {
+    Connecting,
+    Connected,
+    Reconnecting,
+    Closed,
+}
+
+impl PeerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PeerState::Connecting => "connecting",
+            PeerState::Connected => "connected",
+            PeerState::Reconnecting => "reconnecting",
+            PeerState::Closed => "closed",
+        }
+    }
+}
+
+impl Rtc {
+    /// Combines the ICE and the overall connection state. Browsers don't all
+    /// report the latter, so either of them going bad counts.
+    pub fn peer_state(&self) -> PeerState {
+        let ice = self.connection.ice_connection_state();
+        match self.connection.connection_state() {
+            RtcPeerConnectionState::Closed => PeerState::Closed,
+            RtcPeerConnectionState::Disconnected | RtcPeerConnectionState::Failed => {
+                PeerState::Reconnecting
+            }
+            _ if ice == RtcIceConnectionState::Disconnected
+                || ice == RtcIceConnectionState::Failed =>
+            {
+                PeerState::Reconnecting
+            }
+            RtcPeerConnectionState::Connected => PeerState::Connected,
+            _ => PeerState::Connecting,
+        }
+    }
+
+    /// Reports state changes and tries to recover a broken connection with an
+    /// ICE restart. If that doesn't help in time `give_up` is called so that
+    /// the connection can be replaced.
+    pub fn add_state_callback(
+        &mut self,
+        on_state: Callback<PeerState>,
+        give_up: Callback<()>,
+    ) -> Result<(), JsValue> {
+        let rtc = self.clone();
+        let restart_timer = self.restart_timer.clone();
+
+        let onstatechange = move |_: Event| {
+            let state = rtc.peer_state();
+            on_state.call(state);
+            match state {
+                PeerState::Reconnecting => {
+                    if restart_timer.get().is_none() {
+                        log!("Connection lost, restarting ICE");
+                        rtc.send_message.call(ClientCommand::RestartIce(rtc.uuid));
+
+                        let timer = restart_timer.clone();
+                        let handle = set_timeout_with_handle(
+                            move || {
+                                timer.set(None);
+                                give_up.call(());
+                            },
+                            RESTART_TIMEOUT,
+                        );
+                        restart_timer.set(handle.ok());
+                    }
+                }
+                PeerState::Connected | PeerState::Closed => {
+                    if let Some(handle) = restart_timer.take() {
+                        handle.clear();
+                    }
+                }
+                PeerState::Connecting => {}
+            }
+        };
+
+        let cb = Closure::wrap(Box::new(onstatechange) as Box<dyn FnMut(_)>);
+        self.connection
+            .set_oniceconnectionstatechange(Some(cb.as_ref().unchecked_ref()));
+        self.connection
+            .set_onconnectionstatechange(Some(cb.as_ref().unchecked_ref()));
+        cb.forget();
+        Ok(())
+    }
+
+    /// Closes the connection for good. Nothing reports the closing once the
+    /// handlers are gone, so a pending restart is called off here rather
+    /// than giving up on whatever replaces this connection.
+    pub fn close(&self) {
+        self.connection.set_oniceconnectionstatechange(None);
+        self.connection.set_onconnectionstatechange(None);
+        if let Some(handle) = self.restart_timer.take() {
+            handle.clear();
+        }
+        self.connection.close();
+    }
+}
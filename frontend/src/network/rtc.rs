@@ -1,13 +1,21 @@
-use super::Error;
+use super::{Error, Route};
 use anyhow::Result;
 use gloo::console::log;
 use gloo_utils::format::JsValueSerdeExt;
-use leptos::{Callable, Callback};
-use protocol::ClientCommand;
+use js_sys::{Array, Reflect};
+use leptos::{Callable, Callback, TimeoutHandle};
+use protocol::{sdp::Codec, ClientCommand, IceCandidate, QualityPreset};
 use serde_json::json;
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
 use uuid::Uuid;
 use wasm_bindgen::prelude::*;
-use web_sys::{AudioTrack, MediaStreamTrack, RtcConfiguration, RtcPeerConnection, TrackEvent};
+use web_sys::{
+    AudioTrack, MediaStream, MediaStreamTrack, MediaStreamTrackEvent, RtcConfiguration,
+    RtcDataChannel, RtcPeerConnection, TrackEvent,
+};
 
 #[derive(Clone)]
 pub struct Rtc {
@@ -16,7 +24,20 @@ pub struct Rtc {
     pub send_message: Callback<ClientCommand>,
     pub uuid: Uuid,
     pub polite: bool,
-    pub pending_offer: bool,
+    /// Decides how Opus gets tuned in our session descriptions
+    pub quality: QualityPreset,
+    /// The audio codec from the latest answer
+    pub codec: Option<Codec>,
+    /// Peer to peer messages, see `add_control_channel`
+    pub control: Option<RtcDataChannel>,
+    pub midi: Option<Route>,
+    /// Shared between clones so that negotiation flags set from one async
+    /// task are seen by the others
+    pub making_offer: Rc<Cell<bool>>,
+    pub ignore_offer: Rc<Cell<bool>>,
+    pub pending_candidates: Rc<RefCell<Vec<Option<IceCandidate>>>>,
+    /// Pending give up after an ICE restart, see `add_state_callback`
+    pub restart_timer: Rc<Cell<Option<TimeoutHandle>>>,
 }
 
 impl PartialEq for Rtc {
@@ -45,20 +66,49 @@ impl Rtc {
             connection,
             msgs: vec![],
             polite: false,
-            pending_offer: false,
+            quality: QualityPreset::default(),
+            codec: None,
+            control: None,
+            midi: None,
+            making_offer: Rc::new(Cell::new(false)),
+            ignore_offer: Rc::new(Cell::new(false)),
+            pending_candidates: Rc::new(RefCell::new(vec![])),
+            restart_timer: Rc::new(Cell::new(None)),
             send_message,
             uuid,
         })
     }
 
+    /// Hands over every received track with the id of the stream the
+    /// sender put it in, and again to `remove_track` once the track ends or
+    /// the sender takes it out of its stream
     pub fn add_track_callback(
         &mut self,
-        add_track: Callback<MediaStreamTrack>,
+        add_track: Callback<(MediaStreamTrack, Option<String>)>,
+        remove_track: Callback<MediaStreamTrack>,
     ) -> Result<(), JsValue> {
         let ontrack = move |ev: TrackEvent| {
             if let Some(track) = ev.track() {
                 let track = MediaStreamTrack::from(JsValue::from(track));
-                add_track.call(track);
+                let stream = Reflect::get(&ev, &JsValue::from_str("streams"))
+                    .ok()
+                    .map(|streams| Array::from(&streams).get(0))
+                    .filter(|stream| !stream.is_undefined())
+                    .map(MediaStream::from);
+
+                let ended = track.clone();
+                let onended = Closure::once(move || remove_track.call(ended));
+                track.set_onended(Some(onended.as_ref().unchecked_ref()));
+                onended.forget();
+                if let Some(stream) = &stream {
+                    let onremovetrack = Closure::wrap(Box::new(move |ev: MediaStreamTrackEvent| {
+                        remove_track.call(ev.track())
+                    }) as Box<dyn FnMut(_)>);
+                    stream.set_onremovetrack(Some(onremovetrack.as_ref().unchecked_ref()));
+                    onremovetrack.forget();
+                }
+
+                add_track.call((track, stream.map(|stream| stream.id())));
             }
         };
         let cb = Closure::wrap(Box::new(ontrack) as Box<dyn FnMut(_)>);
@@ -1,103 +1,137 @@
 use super::Rtc;
 
 use anyhow::Result;
-use futures::{future::join_all, Future};
 use gloo::console::log;
 use js_sys::Reflect;
-use leptos::{spawn_local, Callable, Callback};
-use protocol::{ClientCommand, ServerCommand};
+use leptos::Callable;
+use protocol::{
+    sdp::{Codec, SessionDescription},
+    tune_opus, ClientCommand,
+};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{Event, RtcSdpType, RtcSessionDescriptionInit, RtcSignalingState};
+use web_sys::{Event, RtcOfferOptions, RtcSdpType, RtcSessionDescriptionInit, RtcSignalingState};
+
+/// The first audio format of an answer is what both ends end up sending
+fn negotiated_codec(answer_sdp: &str) -> Option<Codec> {
+    match answer_sdp.parse::<SessionDescription>() {
+        Ok(answer) => answer.audio().find_map(|media| media.negotiated_codec()),
+        Err(e) => {
+            log!(format!("Could not inspect answer: {}", e));
+            None
+        }
+    }
+}
 
+/// Implements https://blog.mozilla.org/webrtc/perfect-negotiation-in-webrtc/
+/// Either side may offer at any time, on collision the impolite peer ignores
+/// the incoming offer while the polite one rolls back its own.
 impl Rtc {
-    pub async fn create_offer(&mut self) -> Result<String, JsValue> {
+    /// Returns `None` if the offer was abandoned because the peer's offer
+    /// got applied while we were creating ours
+    pub async fn create_offer(&mut self, ice_restart: bool) -> Result<Option<String>, JsValue> {
         self.msgs.push("offer".to_string());
 
-        let offer = JsFuture::from(self.connection.create_offer())
-            .await
-            .unwrap();
-        let offer_sdp = Reflect::get(&offer, &JsValue::from_str("sdp"))
-            .unwrap()
+        self.making_offer.set(true);
+        let offer = self.make_offer(ice_restart).await;
+        self.making_offer.set(false);
+        offer
+    }
+
+    async fn make_offer(&self, ice_restart: bool) -> Result<Option<String>, JsValue> {
+        let mut options = RtcOfferOptions::new();
+        options.ice_restart(ice_restart);
+        let offer = JsFuture::from(self.connection.create_offer_with_rtc_offer_options(&options)).await?;
+        let offer_sdp = Reflect::get(&offer, &JsValue::from_str("sdp"))?
             .as_string()
-            .unwrap();
+            .ok_or_else(|| JsValue::from_str("Offer is missing sdp"))?;
+        let offer_sdp = tune_opus(&offer_sdp, &self.quality.opus_params());
+
+        if self.connection.signaling_state() != RtcSignalingState::Stable {
+            log!("Signaling state changed while creating offer, dropping it");
+            return Ok(None);
+        }
 
         let mut offer_obj = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
         offer_obj.sdp(&offer_sdp);
 
         let sld_promise = self.connection.set_local_description(&offer_obj);
-        JsFuture::from(sld_promise).await.unwrap();
-        self.pending_offer = true;
+        JsFuture::from(sld_promise).await?;
 
-        Ok(offer_sdp)
+        Ok(Some(offer_sdp))
     }
 
-    pub async fn create_answer(&mut self, offer_sdp: String) -> Option<String> {
+    /// Returns `None` if the offer collided with our own and we are the
+    /// impolite peer, the other party then has to accept our offer instead
+    pub async fn create_answer(&mut self, offer_sdp: String) -> Result<Option<String>, JsValue> {
         // First we handle the glare scenario
-        let mut local_future: Option<JsFuture> = None;
-        if self.connection.signaling_state() != RtcSignalingState::Stable {
-            if self.polite {
-                //"We are polite and have pending request we rollback and let the other peer go first"
-                let description = RtcSessionDescriptionInit::new(RtcSdpType::Rollback);
-                let local_promise = self.connection.set_local_description(&description);
-                local_future = Some(JsFuture::from(local_promise));
-            } else {
-                log!("The other party needs to accept my offer instead");
-                return None;
-            }
-        };
+        let state = self.connection.signaling_state();
+        let offer_collision = self.making_offer.get() || state != RtcSignalingState::Stable;
 
-        let mut description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
-        description.sdp(&offer_sdp);
-        let sld_promise = self.connection.set_remote_description(&description);
-        let sld_future = JsFuture::from(sld_promise);
+        self.ignore_offer.set(!self.polite && offer_collision);
+        if self.ignore_offer.get() {
+            log!("The other party needs to accept my offer instead");
+            return Ok(None);
+        }
 
-        if let Some(local_future) = local_future {
-            join_all(vec![sld_future, local_future]).await;
-        } else {
-            sld_future.await;
+        if state != RtcSignalingState::Stable {
+            // We are polite and have a pending offer, roll back and let the other peer go first
+            let rollback = RtcSessionDescriptionInit::new(RtcSdpType::Rollback);
+            JsFuture::from(self.connection.set_local_description(&rollback)).await?;
         }
 
+        let mut description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
+        description.sdp(&offer_sdp);
+        let srd_promise = self.connection.set_remote_description(&description);
+        JsFuture::from(srd_promise).await?;
+        self.flush_ice().await?;
 
         // And then we create an answer and move forward
-        let answer = JsFuture::from(self.connection.create_answer())
-            .await
-            .unwrap();
-        let answer_sdp = Reflect::get(&answer, &JsValue::from_str("sdp"))
-            .unwrap()
+        let answer = JsFuture::from(self.connection.create_answer()).await?;
+        let answer_sdp = Reflect::get(&answer, &JsValue::from_str("sdp"))?
             .as_string()
-            .unwrap();
+            .ok_or_else(|| JsValue::from_str("Answer is missing sdp"))?;
+        let answer_sdp = tune_opus(&answer_sdp, &self.quality.opus_params());
 
         let mut answer = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
         answer.sdp(&answer_sdp);
 
         let sld_promise = self.connection.set_local_description(&answer);
-        JsFuture::from(sld_promise).await.unwrap();
+        JsFuture::from(sld_promise).await?;
+        self.codec = negotiated_codec(&answer_sdp);
 
-        Some(answer_sdp)
+        Ok(Some(answer_sdp))
     }
 
     pub async fn get_answer(&mut self, answer_sdp: String) -> Result<(), JsValue> {
         self.msgs.push("get answer".to_string());
 
+        // An answer to an offer we already rolled back is of no use
+        if self.connection.signaling_state() != RtcSignalingState::HaveLocalOffer {
+            log!("Dropping answer, no offer is pending");
+            return Ok(());
+        }
+
         let mut answer = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
         answer.sdp(&answer_sdp);
         let srd_promise = self.connection.set_remote_description(&answer);
         JsFuture::from(srd_promise).await?;
+        self.flush_ice().await?;
+        self.codec = negotiated_codec(&answer_sdp);
 
         Ok(())
     }
 
-    pub fn add_negotiation_callback(
-        &mut self,
-        renew_offer: Callback<ServerCommand>,
-    ) -> Result<(), JsValue> {
+    /// Asks the signal server to let us renegotiate, it decides which side
+    /// of the pair should make the offer
+    pub fn add_negotiation_callback(&mut self) -> Result<(), JsValue> {
+        let send_message = self.send_message.clone();
         let uuid = self.uuid.clone();
-        let negotioation_needed = move |_: Event| {
-            renew_offer.call(ServerCommand::CreateOffer(uuid));
+        let negotiation_needed = move |_: Event| {
+            send_message.call(ClientCommand::RenegotiationNeeded(uuid));
         };
 
-        let cb = Closure::wrap(Box::new(negotioation_needed) as Box<dyn FnMut(_)>);
+        let cb = Closure::wrap(Box::new(negotiation_needed) as Box<dyn FnMut(_)>);
         self.connection
             .set_onnegotiationneeded(Some(cb.as_ref().unchecked_ref()));
         cb.forget();
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use js_sys::{Map, Reflect};
+use protocol::QualitySample;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+use super::Rtc;
+
+/// Opus encoder lookahead, added on top of the packet duration
+const OPUS_LOOKAHEAD_MS: f64 = 6.5;
+
+/// What a musician wants to know about the connection to a peer. Times are
+/// in milliseconds, loss is a fraction of the packets sent to us.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PeerStats {
+    pub round_trip_time: Option<f64>,
+    pub jitter: Option<f64>,
+    pub packet_loss: Option<f64>,
+    pub jitter_buffer_delay: Option<f64>,
+    /// Concealment events per second, each one an audible dropout
+    pub concealment_rate: Option<f64>,
+    /// Incoming and outgoing audio in kbit/s
+    pub bitrate_in: Option<f64>,
+    pub bitrate_out: Option<f64>,
+    /// How our stream arrives at the peer, as reported back by it
+    pub outbound_loss: Option<f64>,
+    pub outbound_jitter: Option<f64>,
+    /// Candidate types of the pair in use
+    pub local_candidate: Option<String>,
+    pub remote_candidate: Option<String>,
+    pub counters: Counters,
+}
+
+/// Running totals from the last report, rates are derived from the change
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Counters {
+    timestamp: f64,
+    bytes_received: f64,
+    bytes_sent: f64,
+    jitter_buffer_delay: f64,
+    jitter_buffer_emitted: f64,
+    concealment_events: f64,
+}
+
+impl PeerStats {
+    /// Estimated time from a sound at the peer's microphone to our speakers:
+    /// packetization and encoding, one way across the network and the time
+    /// spent in our jitter buffer. Device buffers on both ends come on top.
+    pub fn mouth_to_ear(&self, ptime: u32) -> Option<f64> {
+        let one_way = self.round_trip_time? / 2.0;
+        Some(f64::from(ptime) + OPUS_LOOKAHEAD_MS + one_way + self.jitter_buffer_delay.unwrap_or(0.0))
+    }
+
+    /// What gets uploaded to the server for post-mortems
+    pub fn sample(&self, codec: Option<String>) -> QualitySample {
+        QualitySample {
+            round_trip_time: self.round_trip_time,
+            jitter: self.jitter,
+            packet_loss: self.packet_loss,
+            jitter_buffer_delay: self.jitter_buffer_delay,
+            bitrate_in: self.bitrate_in,
+            bitrate_out: self.bitrate_out,
+            local_candidate: self.local_candidate.clone(),
+            remote_candidate: self.remote_candidate.clone(),
+            codec,
+        }
+    }
+}
+
+fn number(stat: &JsValue, key: &str) -> Option<f64> {
+    Reflect::get(stat, &JsValue::from_str(key)).ok()?.as_f64()
+}
+
+fn string(stat: &JsValue, key: &str) -> Option<String> {
+    Reflect::get(stat, &JsValue::from_str(key)).ok()?.as_string()
+}
+
+impl Rtc {
+    /// Reads the current report and compares it with the previous one
+    pub async fn stats(&self, previous: &PeerStats) -> Result<PeerStats, JsValue> {
+        let report = JsFuture::from(self.connection.get_stats()).await?;
+        let report = report.unchecked_into::<Map>();
+
+        let mut stats = PeerStats::default();
+        let mut counters = Counters::default();
+        let mut candidate_types = HashMap::new();
+        let mut pair = None;
+        for stat in report.values() {
+            let stat = stat?;
+            let is_audio = string(&stat, "kind").as_deref() == Some("audio");
+            match string(&stat, "type").as_deref() {
+                Some("candidate-pair") if string(&stat, "state").as_deref() == Some("succeeded") => {
+                    if let Some(rtt) = number(&stat, "currentRoundTripTime") {
+                        stats.round_trip_time = Some(rtt * 1000.0);
+                    }
+                    pair = string(&stat, "localCandidateId").zip(string(&stat, "remoteCandidateId"));
+                }
+                Some("local-candidate" | "remote-candidate") => {
+                    if let Some((id, kind)) = string(&stat, "id").zip(string(&stat, "candidateType")) {
+                        candidate_types.insert(id, kind);
+                    }
+                }
+                Some("inbound-rtp") if is_audio => {
+                    counters.timestamp = number(&stat, "timestamp").unwrap_or_default();
+                    counters.bytes_received += number(&stat, "bytesReceived").unwrap_or_default();
+                    counters.jitter_buffer_delay +=
+                        number(&stat, "jitterBufferDelay").unwrap_or_default();
+                    counters.jitter_buffer_emitted +=
+                        number(&stat, "jitterBufferEmittedCount").unwrap_or_default();
+                    counters.concealment_events +=
+                        number(&stat, "concealmentEvents").unwrap_or_default();
+                    stats.jitter = number(&stat, "jitter").map(|j| j * 1000.0);
+
+                    let lost = number(&stat, "packetsLost").unwrap_or_default();
+                    let received = number(&stat, "packetsReceived").unwrap_or_default();
+                    if lost + received > 0.0 {
+                        stats.packet_loss = Some(lost.max(0.0) / (lost + received));
+                    }
+                }
+                Some("remote-inbound-rtp") if is_audio => {
+                    stats.outbound_loss = number(&stat, "fractionLost");
+                    stats.outbound_jitter = number(&stat, "jitter").map(|j| j * 1000.0);
+                }
+                Some("outbound-rtp") if is_audio => {
+                    counters.timestamp = number(&stat, "timestamp").unwrap_or(counters.timestamp);
+                    counters.bytes_sent += number(&stat, "bytesSent").unwrap_or_default();
+                }
+                _ => {}
+            }
+        }
+
+        if let Some((local, remote)) = pair {
+            stats.local_candidate = candidate_types.get(&local).cloned();
+            stats.remote_candidate = candidate_types.get(&remote).cloned();
+        }
+
+        let last = &previous.counters;
+        let elapsed = counters.timestamp - last.timestamp;
+        if last.timestamp > 0.0 && elapsed > 0.0 {
+            // bytes per millisecond to kbit/s
+            stats.bitrate_in = Some((counters.bytes_received - last.bytes_received) * 8.0 / elapsed);
+            stats.bitrate_out = Some((counters.bytes_sent - last.bytes_sent) * 8.0 / elapsed);
+            stats.concealment_rate =
+                Some((counters.concealment_events - last.concealment_events) * 1000.0 / elapsed);
+        }
+        let emitted = counters.jitter_buffer_emitted - last.jitter_buffer_emitted;
+        if emitted > 0.0 {
+            stats.jitter_buffer_delay =
+                Some((counters.jitter_buffer_delay - last.jitter_buffer_delay) / emitted * 1000.0);
+        }
+        stats.counters = counters;
+        Ok(stats)
+    }
+}
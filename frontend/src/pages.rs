@@ -1,7 +1,9 @@
 mod home;
+mod join;
 mod settings;
 mod studio;
 
 pub use home::Home;
+pub use join::Join;
 pub use settings::Settings;
 pub use studio::Studio;
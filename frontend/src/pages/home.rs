@@ -1,26 +1,13 @@
-use gloo_console::log;
+use crate::components::Lobby;
 use leptos::*;
 
 #[component]
 pub fn Home() -> impl IntoView {
-    let (thingy, _) = create_signal("Hi".to_string());
     view! {
-        <SubHome thingy={thingy} />
-    }
-}
-
-#[component]
-pub fn SubHome(thingy: ReadSignal<String>) -> impl IntoView {
-    create_resource(
-        move || thingy.get(),
-        move |msg| {
-            log!("hello");
-            async move {
-                log!("hi!");
-            }
-        }
-    );
-    view! {
-        <div>Home</div>
+        <section class="section">
+            <h1 class="title">Sessions</h1>
+            <h2 class="subtitle">Join a running session or start a new one</h2>
+            <Lobby/>
+        </section>
     }
 }
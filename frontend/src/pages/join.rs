@@ -0,0 +1,53 @@
+use leptos::*;
+use leptos_router::*;
+
+use crate::network::get_invite;
+use crate::settings::use_settings;
+
+/// Landing page for invite links, asks the guest for a name before joining
+#[component]
+pub fn Join() -> impl IntoView {
+    let params = use_params_map();
+    let token = move || params.with(|p| p.get("token").cloned().unwrap_or_default());
+    let invite = create_resource(token, |token| async move { get_invite(&token).await });
+
+    let settings = use_settings();
+    let navigate = use_navigate();
+    let join = move |_| {
+        navigate(
+            &format!("/studio?invite={}", String::from(js_sys::encode_uri_component(&token()))),
+            Default::default(),
+        )
+    };
+
+    view! {
+        <section class="section">
+            <Suspense fallback=|| view! { <p>Checking invite...</p> }>
+                {
+                    move || invite.get().map(|invite| match invite {
+                        Ok(invite) => view! {
+                            <h1 class="title">{format!("Join {}", invite.session)}</h1>
+                            <h2 class="subtitle">{format!("You are invited as {}", invite.role.as_str())}</h2>
+                            <div class="field">
+                                <label class="label">Your name</label>
+                                <div class="control">
+                                    <input
+                                        class="input"
+                                        type="text"
+                                        placeholder="Guest"
+                                        prop:value=move || settings.get().profile.name
+                                        on:input=move |e| settings.update(|s| s.profile.name = event_target_value(&e))
+                                    />
+                                </div>
+                            </div>
+                            <button class="button is-primary" on:click=join.clone()>Join session</button>
+                        }.into_view(),
+                        Err(_) => view! {
+                            <h1 class="title">This invite is invalid or has expired</h1>
+                        }.into_view(),
+                    })
+                }
+            </Suspense>
+        </section>
+    }
+}
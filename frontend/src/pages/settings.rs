@@ -1,8 +1,149 @@
+use crate::audio::{LatencyHint, SAMPLE_RATES};
+use crate::components::ProfileEditor;
+use crate::network::{get_bands, use_access_token, BitratePolicy, Error};
+use crate::settings::use_settings;
+use gloo_console::log;
 use leptos::*;
 
 #[component]
 pub fn Settings() -> impl IntoView {
+    let settings = use_settings();
+    // Only the bands the server knows we play in can be picked
+    let token = use_access_token();
+    let bands = create_resource(
+        move || token.get(),
+        |token| async move {
+            match get_bands(token).await {
+                Ok(bands) => bands,
+                Err(Error::SignedOut) => vec![],
+                Err(e) => {
+                    log!(format!("Failed to load bands: {:?}", e));
+                    vec![]
+                }
+            }
+        },
+    );
+
     view! {
-        <div>Settings</div>
+        <section class="section">
+            <h1 class="title">Settings</h1>
+            <h2 class="subtitle">Profile</h2>
+            <ProfileEditor/>
+            <div class="field">
+                <label class="label">Band</label>
+                <div class="control">
+                    <div class="select">
+                        <select on:change=move |e| settings.update(|s| s.band = event_target_value(&e).parse().ok())>
+                            <option value="" selected=move || settings.get().band.is_none()>None</option>
+                            {move || bands.get().map(|bands| bands.into_iter().map(|band| view! {
+                                <option value=band.id selected=move || settings.get().band == Some(band.id)>
+                                    {band.name.unwrap_or_else(|| format!("Band {}", band.id))}
+                                </option>
+                            }).collect_view())}
+                        </select>
+                    </div>
+                </div>
+                <p class="help">Band sessions you start belong to this band, sign in to pick one of yours</p>
+            </div>
+            <div class="field">
+                <label class="checkbox">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || settings.get().sync_scenes
+                        on:change=move |e| settings.update(|s| s.sync_scenes = event_target_checked(&e))
+                    />
+                    " Keep mixer scenes on the server"
+                </label>
+                <p class="help">Needs a band and signing in, scenes then follow you to other machines</p>
+            </div>
+            <h2 class="subtitle">Audio</h2>
+            <div class="field is-grouped">
+                <div class="control">
+                    <label class="label">Sample rate</label>
+                    <div class="select">
+                        <select on:change=move |e| {
+                            settings.update(|s| s.audio.sample_rate = event_target_value(&e).parse().ok())
+                        }>
+                            <option value="" selected=move || settings.get().audio.sample_rate.is_none()>
+                                Device default
+                            </option>
+                            {SAMPLE_RATES.into_iter().map(|rate| view! {
+                                <option value=rate selected=move || settings.get().audio.sample_rate == Some(rate)>
+                                    {format!("{rate} Hz")}
+                                </option>
+                            }).collect_view()}
+                        </select>
+                    </div>
+                </div>
+                <div class="control">
+                    <label class="label">Latency</label>
+                    <div class="select">
+                        <select on:change=move |e| {
+                            if let Ok(latency) = event_target_value(&e).parse() {
+                                settings.update(|s| s.audio.latency = latency)
+                            }
+                        }>
+                            {[LatencyHint::Interactive, LatencyHint::Balanced, LatencyHint::Playback].into_iter().map(|hint| view! {
+                                <option value=hint.as_str() selected=move || settings.get().audio.latency == hint>
+                                    {hint.as_str()}
+                                </option>
+                            }).collect_view()}
+                        </select>
+                    </div>
+                </div>
+            </div>
+            <p class="help mb-4">Applies the next time you join a session, the studio shows what the browser made of it</p>
+            <h2 class="subtitle">Outgoing bitrate</h2>
+            <div class="field">
+                <label class="label">Policy</label>
+                <div class="control">
+                    <div class="select">
+                        <select on:change=move |e| {
+                            if let Ok(policy) = event_target_value(&e).parse() {
+                                settings.update(|s| s.bitrate.policy = policy)
+                            }
+                        }>
+                            <option value="latency" selected=move || settings.get().bitrate.policy == BitratePolicy::Latency>
+                                Lowest latency
+                            </option>
+                            <option value="quality" selected=move || settings.get().bitrate.policy == BitratePolicy::Quality>
+                                Highest quality
+                            </option>
+                        </select>
+                    </div>
+                </div>
+                <p class="help">Lowest latency backs off as soon as the network starts queueing, highest quality only when packets get lost</p>
+            </div>
+            <div class="field is-grouped">
+                <div class="control">
+                    <label class="label">Minimum kbit/s</label>
+                    <input
+                        class="input"
+                        type="number"
+                        min="6"
+                        prop:value=move || settings.get().bitrate.min.to_string()
+                        on:change=move |e| {
+                            if let Ok(min) = event_target_value(&e).parse() {
+                                settings.update(|s| s.bitrate.min = min)
+                            }
+                        }
+                    />
+                </div>
+                <div class="control">
+                    <label class="label">Maximum kbit/s</label>
+                    <input
+                        class="input"
+                        type="number"
+                        max="510"
+                        prop:value=move || settings.get().bitrate.max.to_string()
+                        on:change=move |e| {
+                            if let Ok(max) = event_target_value(&e).parse() {
+                                settings.update(|s| s.bitrate.max = max)
+                            }
+                        }
+                    />
+                </div>
+            </div>
+        </section>
     }
 }
@@ -1,21 +1,63 @@
 use std::{collections::HashMap, time::Duration};
 
-use crate::components::{MixerBoard, Session};
+use crate::components::{
+    ControlBar, InviteLink, LatencyMatrix, MidiPanel, MixerBoard, ProfileEditor, Session,
+    SessionAccess,
+};
+use crate::audio::{provide_audio_clock, provide_midi_routing, SendStream};
+use crate::network::provide_channel_hubs;
 use gloo_console::log;
 use leptos::*;
-use web_sys::{MediaStream, MediaStreamTrack};
+use leptos_router::*;
+use protocol::{QualityPreset, SessionVisibility};
 
 #[component]
 pub fn Studio() -> impl IntoView {
-    let (stream, set_stream) = create_signal(None);
+    let (streams, set_streams) = create_signal(vec![]);
     let (tracks, set_tracks) = create_signal(HashMap::new());
+    let profiles = create_rw_signal(HashMap::new());
+    let (role, set_role) = create_signal(None);
+    let (me, set_me) = create_signal(None);
+    provide_channel_hubs();
+    provide_audio_clock();
+    provide_midi_routing();
+
+    // The session to join comes from the lobby link or an invite
+    let query = use_query_map();
+    let access = match query.with_untracked(|q| q.get("invite").cloned()) {
+        Some(token) => SessionAccess::Invite(token),
+        None => SessionAccess::Open {
+            session: query
+                .with_untracked(|q| q.get("session").cloned())
+                .unwrap_or_else(|| String::from("hej")),
+            visibility: query
+                .with_untracked(|q| q.get("visibility").and_then(|v| v.parse().ok()))
+                .unwrap_or(SessionVisibility::Public),
+            quality: query
+                .with_untracked(|q| q.get("quality").and_then(|v| v.parse().ok()))
+                .unwrap_or(QualityPreset::default()),
+        },
+    };
+    let invite = match &access {
+        SessionAccess::Open { session, .. } => {
+            Some(view! { <InviteLink session=session.clone()/> })
+        }
+        SessionAccess::Invite(_) => None,
+    };
 
     view! {
         <MixerBoard
-            set_stream= move |stream: MediaStream| set_stream.set(Some(stream))
+            set_streams=move |streams: Vec<SendStream>| set_streams.set(streams)
             tracks=tracks
+            profiles=profiles.read_only()
+            role=role
         />
         <div class="section">
+            <ControlBar profiles=profiles.read_only()/>
+            <MidiPanel profiles=profiles.read_only()/>
+            <LatencyMatrix profiles=profiles.read_only() me=me/>
+            <ProfileEditor/>
+            {invite}
             // <div class="columns">
             //     <div class="column"><Fader/></div>
             //     <div class="column"><Fader/></div>
@@ -23,6 +65,13 @@ pub fn Studio() -> impl IntoView {
             //     <div class="column"><Fader/></div>
             // </div>
         </div>
-        <Session stream=stream set_tracks=set_tracks/>
+        <Session
+            access=access
+            streams=streams
+            set_tracks=set_tracks
+            profiles=profiles
+            set_role=set_role
+            set_me=set_me
+        />
     }
 }
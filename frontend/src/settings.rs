@@ -0,0 +1,67 @@
+use gloo::storage::{LocalStorage, Storage};
+use gloo_console::log;
+use leptos::*;
+use protocol::mixer::MixerScene;
+use protocol::MemberProfile;
+use serde::{Deserialize, Serialize};
+
+use crate::audio::AudioSettings;
+use crate::network::BitrateSettings;
+
+const STORAGE_KEY: &str = "livet.settings";
+const SCENES_KEY: &str = "livet.scenes";
+
+/// User preferences that should survive a page reload
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct UserSettings {
+    pub profile: MemberProfile,
+    pub band: Option<i32>,
+    pub sync_scenes: bool,
+    /// Where the monitor mix plays, the system default when unset
+    pub output_device: Option<String>,
+    pub audio: AudioSettings,
+    pub bitrate: BitrateSettings,
+}
+
+impl UserSettings {
+    pub fn load() -> Self {
+        LocalStorage::get(STORAGE_KEY).unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = LocalStorage::set(STORAGE_KEY, self) {
+            log!(format!("Failed to store settings: {:?}", e));
+        }
+    }
+}
+
+/// Mixer scenes are kept per band, songs differ between bands
+fn scenes_key(band: Option<i32>) -> String {
+    match band {
+        Some(band) => format!("{SCENES_KEY}.{band}"),
+        None => SCENES_KEY.to_string(),
+    }
+}
+
+pub fn load_scenes(band: Option<i32>) -> Vec<MixerScene> {
+    LocalStorage::get(scenes_key(band)).unwrap_or_default()
+}
+
+pub fn save_scenes(band: Option<i32>, scenes: &[MixerScene]) {
+    if let Err(e) = LocalStorage::set(scenes_key(band), scenes) {
+        log!(format!("Failed to store scenes: {:?}", e));
+    }
+}
+
+/// Loads the settings into a signal that is shared through the context and
+/// written back to local storage on every change
+pub fn provide_settings() {
+    let settings = create_rw_signal(UserSettings::load());
+    create_effect(move |_| settings.get().save());
+    provide_context(settings);
+}
+
+pub fn use_settings() -> RwSignal<UserSettings> {
+    expect_context::<RwSignal<UserSettings>>()
+}
@@ -1,13 +1,26 @@
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
 use uuid::Uuid;
 
+mod opus;
+pub mod mixer;
+pub mod sdp;
+pub use opus::{tune_opus, OpusParams, QualityPreset};
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum ServerCommand {
     CreateOffer(Uuid),
+    RestartIce(Uuid),
+    ResetConnection(Uuid),
     CreateAnswer(Uuid, String),
     GetAnswer(Uuid, String),
-    AddIceCandidate(Uuid, String),
-    AddMember(Uuid, bool),
+    AddIceCandidate(Uuid, Option<IceCandidate>),
+    /// Our id, role, the session's quality and the id of this run of the
+    /// session, which its diagnostics are kept under
+    Joined(Uuid, Role, QualityPreset, Uuid),
+    AddMember(Uuid, bool, MemberProfile, Role),
+    UpdateMember(Uuid, MemberProfile),
     DropMember(Uuid),
 }
 
@@ -15,9 +28,13 @@ impl ServerCommand {
     pub fn get_uuid(&self) -> Uuid {
         match self {
             ServerCommand::CreateOffer(uuid) => uuid.clone(),
+            ServerCommand::RestartIce(uuid) => uuid.clone(),
+            ServerCommand::ResetConnection(uuid) => uuid.clone(),
             ServerCommand::CreateAnswer(uuid, _) => uuid.clone(),
             ServerCommand::GetAnswer(uuid, _) => uuid.clone(),
-            ServerCommand::AddMember(uuid, _) => uuid.clone(),
+            ServerCommand::Joined(uuid, _, _, _) => uuid.clone(),
+            ServerCommand::AddMember(uuid, _, _, _) => uuid.clone(),
+            ServerCommand::UpdateMember(uuid, _) => uuid.clone(),
             ServerCommand::DropMember(uuid) => uuid.clone(),
             ServerCommand::AddIceCandidate(uuid, _) => uuid.clone(),
         }
@@ -28,13 +45,61 @@ impl ServerCommand {
 pub enum ClientCommand {
     Offer(Uuid, String),
     Answer(Uuid, String),
-    IceCandidate(Uuid, String),
+    IceCandidate(Uuid, Option<IceCandidate>),
+    RenegotiationNeeded(Uuid),
+    RestartIce(Uuid),
+    ResetConnection(Uuid),
+    UpdateProfile(MemberProfile),
+    Diagnostics(Uuid, QualitySample),
+}
+
+/// Mirrors the browser's RTCIceCandidateInit. Sent as `None` once the
+/// sender has finished gathering candidates.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IceCandidate {
+    pub candidate: String,
+    pub sdp_mid: Option<String>,
+    pub sdp_m_line_index: Option<u16>,
+    pub username_fragment: Option<String>,
+}
+
+/// What other session members get to know about a participant
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct MemberProfile {
+    pub name: String,
+    pub instrument: Option<String>,
+    pub avatar: Option<String>,
+}
+
+impl MemberProfile {
+    pub fn guest(name: Option<String>) -> Self {
+        Self {
+            name: name.unwrap_or_else(|| String::from("Guest")),
+            instrument: None,
+            avatar: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Musician {
     pub id: i32,
     pub name: Option<String>,
+    pub instrument: Option<String>,
+    pub avatar: Option<String>,
+}
+
+impl From<Musician> for MemberProfile {
+    fn from(musician: Musician) -> Self {
+        Self {
+            name: musician
+                .name
+                .unwrap_or_else(|| format!("Musician {}", musician.id)),
+            instrument: musician.instrument,
+            avatar: musician.avatar,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -44,9 +109,237 @@ pub struct Band {
     pub member: Vec<Musician>,
 }
 
+/// A band the signed in musician plays in, to open band sessions for
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BandSummary {
+    pub id: i32,
+    pub name: Option<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Session {
     pub id: i32,
     pub name: Option<String>,
     pub member: Vec<Musician>,
 }
+
+/// Who gets to see a session in the lobby
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionVisibility {
+    #[default]
+    Public,
+    Band,
+    Private,
+}
+
+impl SessionVisibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionVisibility::Public => "public",
+            SessionVisibility::Band => "band",
+            SessionVisibility::Private => "private",
+        }
+    }
+}
+
+impl FromStr for SessionVisibility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(SessionVisibility::Public),
+            "band" => Ok(SessionVisibility::Band),
+            "private" => Ok(SessionVisibility::Private),
+            other => Err(format!("Unknown session visibility {other}")),
+        }
+    }
+}
+
+/// Lobby entry for a running session
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SessionInfo {
+    pub name: String,
+    pub visibility: SessionVisibility,
+    pub band: Option<i32>,
+    #[serde(default)]
+    pub quality: QualityPreset,
+    pub participants: usize,
+}
+
+impl SessionInfo {
+    /// Public sessions are listed for everyone, band sessions only for the
+    /// members of the band. `bands` are the bands the caller plays in.
+    pub fn visible_to(&self, bands: &[i32]) -> bool {
+        match self.visibility {
+            SessionVisibility::Public => true,
+            SessionVisibility::Band => self.band.is_some_and(|band| bands.contains(&band)),
+            SessionVisibility::Private => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum LobbyCommand {
+    Sessions(Vec<SessionInfo>),
+    SessionChanged(SessionInfo),
+    SessionClosed(String),
+}
+
+/// What a participant is allowed to do in a session
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Performer,
+    Listener,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Performer => "performer",
+            Role::Listener => "listener",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "performer" => Ok(Role::Performer),
+            "listener" => Ok(Role::Listener),
+            other => Err(format!("Unknown role {other}")),
+        }
+    }
+}
+
+/// Ask the server for an invite link to a session
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InviteRequest {
+    pub session: String,
+    pub role: Role,
+    /// Seconds until the invite expires
+    pub valid_for: i64,
+}
+
+/// Lets a guest into a session without an account
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Invite {
+    pub token: String,
+    pub session: String,
+    /// The run of the session it was handed out in, a later session under
+    /// the same name doesn't take it
+    pub instance: Uuid,
+    pub role: Role,
+    /// Unix timestamp in seconds
+    pub expires_at: i64,
+}
+
+/// How often members upload a sample per peer, the server doesn't take
+/// them any faster
+pub const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Summary of the connection to one peer, times in milliseconds and bitrates
+/// in kbit/s
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct QualitySample {
+    pub round_trip_time: Option<f64>,
+    pub jitter: Option<f64>,
+    pub packet_loss: Option<f64>,
+    pub jitter_buffer_delay: Option<f64>,
+    pub bitrate_in: Option<f64>,
+    pub bitrate_out: Option<f64>,
+    /// Candidate types of the pair in use, e.g. host, srflx or relay
+    pub local_candidate: Option<String>,
+    pub remote_candidate: Option<String>,
+    pub codec: Option<String>,
+}
+
+/// A stored quality sample, part of a session's timeline
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub participant: Uuid,
+    pub name: String,
+    pub peer: Uuid,
+    /// Unix timestamp in seconds
+    pub recorded_at: i64,
+    pub sample: QualitySample,
+}
+
+/// Sent directly between members over a data channel, bypassing the server
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PeerMessage {
+    /// A named cue, e.g. to count in a song
+    Cue(String),
+    /// Whether the sender is ready to play
+    Ready(bool),
+    /// Answered right away with a `Pong` echoing `id`, `sent` and `epoch`,
+    /// plus the responder's clock when it answered. Times are audio clock
+    /// milliseconds, epochs count how often a clock stopped or was replaced.
+    Ping { id: u32, sent: f64, epoch: u32 },
+    Pong { id: u32, sent: f64, epoch: u32, received: f64, received_epoch: u32 },
+    /// The sender's round trip times to the other members, in milliseconds
+    Latencies(Vec<(Uuid, f64)>),
+    /// Names of the sender's inputs by the id of the stream each is sent in,
+    /// stream ids survive the trip through the peer connection
+    TrackLabels(Vec<(String, String)>),
+}
+
+/// Raw MIDI bytes, stamped with the sender's clock in milliseconds so the
+/// receiver can keep the original timing
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MidiMessage {
+    pub timestamp: f64,
+    pub data: Vec<u8>,
+}
+
+impl MidiMessage {
+    /// Note offs and all notes/sound off. Losing one of these leaves notes
+    /// hanging, so they are worth waiting for.
+    pub fn ends_notes(&self) -> bool {
+        match *self.data.as_slice() {
+            [status, _, velocity, ..] if status & 0xf0 == 0x90 => velocity == 0,
+            [status, ..] if status & 0xf0 == 0x80 => true,
+            [status, controller, ..] if status & 0xf0 == 0xb0 => {
+                controller == ALL_SOUND_OFF || controller == ALL_NOTES_OFF
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Channel mode messages that silence a whole MIDI channel
+pub const ALL_SOUND_OFF: u8 = 120;
+pub const ALL_NOTES_OFF: u8 = 123;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn midi(data: &[u8]) -> MidiMessage {
+        MidiMessage {
+            timestamp: 0.0,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn note_offs_end_notes() {
+        assert!(midi(&[0x80, 60, 64]).ends_notes());
+        assert!(midi(&[0x93, 60, 0]).ends_notes());
+        assert!(midi(&[0xb0, ALL_NOTES_OFF, 0]).ends_notes());
+        assert!(midi(&[0xbf, ALL_SOUND_OFF, 0]).ends_notes());
+    }
+
+    #[test]
+    fn other_messages_dont() {
+        assert!(!midi(&[0x90, 60, 100]).ends_notes());
+        assert!(!midi(&[0xb0, 64, 127]).ends_notes());
+        assert!(!midi(&[0xe0, 0, 64]).ends_notes());
+        assert!(!midi(&[0xf8]).ends_notes());
+        assert!(!midi(&[]).ends_notes());
+    }
+}
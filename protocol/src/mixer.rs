@@ -0,0 +1,425 @@
+//! The mixer as plain data. The frontend changes it through `MixerAction`s and
+//! then makes the web audio graph follow it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Faders at or below this are silent
+pub const MIN_GAIN_DB: f32 = -96.0;
+pub const MAX_GAIN_DB: f32 = 12.0;
+
+/// The bus that goes out to the other members
+pub const SEND_BUS: &str = "send";
+
+pub fn db_to_gain(db: f32) -> f32 {
+    if db <= MIN_GAIN_DB {
+        0.0
+    } else {
+        10f32.powf(db / 20.0)
+    }
+}
+
+/// A track of a member, local tracks are owned by ourselves
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChannelKey {
+    pub owner: Uuid,
+    pub track: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ChannelState {
+    pub key: ChannelKey,
+    pub label: String,
+    pub gain_db: f32,
+    pub pan: f32,
+    pub mute: bool,
+    pub solo: bool,
+    /// Level sent to each bus, by bus name, in dB. Buses a channel has no
+    /// level for get it at unity.
+    pub sends: BTreeMap<String, f32>,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self {
+            key: ChannelKey {
+                owner: Uuid::nil(),
+                track: String::new(),
+            },
+            label: String::new(),
+            gain_db: 0.0,
+            pan: 0.0,
+            mute: false,
+            solo: false,
+            sends: BTreeMap::new(),
+        }
+    }
+}
+
+/// What I hear is the monitor bus, with remote members, the click and some
+/// of my own input. What the others hear is the send bus, which only carries
+/// my own input and is unaffected by anything on the monitor side.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct MixerState {
+    /// Ordered by key, so the strips keep their place
+    pub channels: Vec<ChannelState>,
+    /// Level of the monitor bus
+    pub master_db: f32,
+    /// How much of my own input I hear, on top of the channel faders
+    pub input_monitor_db: f32,
+    pub click_db: f32,
+    /// Level of the send bus
+    pub send_db: f32,
+}
+
+/// A named mix to come back to, for a song or a part of the set
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MixerScene {
+    pub name: String,
+    pub mixer: MixerState,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MixerAction {
+    SetGain(ChannelKey, f32),
+    SetPan(ChannelKey, f32),
+    SetMute(ChannelKey, bool),
+    SetSolo(ChannelKey, bool),
+    SetSend(ChannelKey, String, f32),
+    SetMaster(f32),
+    SetInputMonitor(f32),
+    SetClick(f32),
+    SetSendBus(f32),
+}
+
+fn clamp_db(db: f32) -> f32 {
+    db.clamp(MIN_GAIN_DB, MAX_GAIN_DB)
+}
+
+impl MixerState {
+    pub fn channel(&self, key: &ChannelKey) -> Option<&ChannelState> {
+        self.channels.iter().find(|c| &c.key == key)
+    }
+
+    fn channel_mut(&mut self, key: &ChannelKey) -> Option<&mut ChannelState> {
+        self.channels.iter_mut().find(|c| &c.key == key)
+    }
+
+    /// Makes the channels match the tracks that exist. New tracks start at
+    /// unity gain, the settings of known ones are kept.
+    pub fn sync_channels(&mut self, tracks: &[(ChannelKey, String)]) {
+        self.channels
+            .retain(|channel| tracks.iter().any(|(key, _)| *key == channel.key));
+        for (key, label) in tracks {
+            match self.channel_mut(key) {
+                Some(channel) => channel.label = label.clone(),
+                None => self.channels.push(ChannelState {
+                    key: key.clone(),
+                    label: label.clone(),
+                    ..ChannelState::default()
+                }),
+            }
+        }
+        self.channels.sort_by(|a, b| a.key.cmp(&b.key));
+    }
+
+    /// Actions on channels that don't exist are ignored
+    pub fn apply(&mut self, action: MixerAction) {
+        match action {
+            MixerAction::SetMaster(db) => self.master_db = clamp_db(db),
+            MixerAction::SetInputMonitor(db) => self.input_monitor_db = clamp_db(db),
+            MixerAction::SetClick(db) => self.click_db = clamp_db(db),
+            MixerAction::SetSendBus(db) => self.send_db = clamp_db(db),
+            MixerAction::SetGain(key, db) => {
+                if let Some(channel) = self.channel_mut(&key) {
+                    channel.gain_db = clamp_db(db);
+                }
+            }
+            MixerAction::SetPan(key, pan) => {
+                if let Some(channel) = self.channel_mut(&key) {
+                    channel.pan = pan.clamp(-1.0, 1.0);
+                }
+            }
+            MixerAction::SetMute(key, mute) => {
+                if let Some(channel) = self.channel_mut(&key) {
+                    channel.mute = mute;
+                }
+            }
+            MixerAction::SetSolo(key, solo) => {
+                if let Some(channel) = self.channel_mut(&key) {
+                    channel.solo = solo;
+                }
+            }
+            MixerAction::SetSend(key, bus, db) => {
+                if let Some(channel) = self.channel_mut(&key) {
+                    channel.sends.insert(bus, clamp_db(db));
+                }
+            }
+        }
+    }
+
+    /// Takes over the settings of a saved scene. Members get new ids every
+    /// session, so channels the scene doesn't know by key are matched by
+    /// label instead. Channels that don't match anything stay as they are.
+    pub fn recall(&mut self, scene: &MixerState) {
+        let mut used = vec![false; scene.channels.len()];
+        let by_key: Vec<Option<usize>> = self
+            .channels
+            .iter()
+            .map(|channel| scene.channels.iter().position(|c| c.key == channel.key))
+            .collect();
+        for index in by_key.iter().flatten() {
+            used[*index] = true;
+        }
+
+        for (channel, by_key) in self.channels.iter_mut().zip(by_key) {
+            let found = by_key.or_else(|| {
+                let index = scene
+                    .channels
+                    .iter()
+                    .enumerate()
+                    .position(|(i, c)| !used[i] && c.label == channel.label)?;
+                used[index] = true;
+                Some(index)
+            });
+            if let Some(saved) = found.map(|i| &scene.channels[i]) {
+                channel.gain_db = saved.gain_db;
+                channel.pan = saved.pan;
+                channel.mute = saved.mute;
+                channel.solo = saved.solo;
+                channel.sends = saved.sends.clone();
+            }
+        }
+        self.master_db = scene.master_db;
+        self.input_monitor_db = scene.input_monitor_db;
+        self.click_db = scene.click_db;
+        self.send_db = scene.send_db;
+    }
+
+    pub fn soloing(&self) -> bool {
+        self.channels.iter().any(|c| c.solo)
+    }
+
+    /// Muted channels are silent, and while any channel is soloed so is
+    /// every channel that isn't
+    pub fn audible(&self, key: &ChannelKey) -> bool {
+        let soloing = self.soloing();
+        self.channel(key)
+            .is_some_and(|c| !c.mute && (!soloing || c.solo))
+    }
+
+    /// Linear gain to apply to the channel, mute and solo included
+    pub fn effective_gain(&self, key: &ChannelKey) -> f32 {
+        match self.channel(key) {
+            Some(channel) if self.audible(key) => db_to_gain(channel.gain_db),
+            _ => 0.0,
+        }
+    }
+
+    /// Linear gain from the channel into a bus, unaffected by the fader,
+    /// mute and solo as those only shape what I hear
+    pub fn send_gain(&self, key: &ChannelKey, bus: &str) -> f32 {
+        match self.channel(key) {
+            Some(channel) => db_to_gain(channel.sends.get(bus).copied().unwrap_or(0.0)),
+            None => 0.0,
+        }
+    }
+
+    pub fn master_gain(&self) -> f32 {
+        db_to_gain(self.master_db)
+    }
+
+    pub fn input_monitor_gain(&self) -> f32 {
+        db_to_gain(self.input_monitor_db)
+    }
+
+    pub fn click_gain(&self) -> f32 {
+        db_to_gain(self.click_db)
+    }
+
+    pub fn send_bus_gain(&self) -> f32 {
+        db_to_gain(self.send_db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(track: &str) -> ChannelKey {
+        ChannelKey {
+            owner: Uuid::from_u128(1),
+            track: track.to_string(),
+        }
+    }
+
+    fn mixer(tracks: &[&str]) -> MixerState {
+        let mut mixer = MixerState::default();
+        let tracks: Vec<(ChannelKey, String)> = tracks
+            .iter()
+            .map(|t| (key(t), t.to_string()))
+            .collect();
+        mixer.sync_channels(&tracks);
+        mixer
+    }
+
+    #[test]
+    fn converts_db() {
+        assert_eq!(db_to_gain(0.0), 1.0);
+        assert!((db_to_gain(-6.0) - 0.501).abs() < 0.001);
+        assert_eq!(db_to_gain(MIN_GAIN_DB), 0.0);
+    }
+
+    #[test]
+    fn syncs_channels_and_keeps_settings() {
+        let mut mixer = mixer(&["b", "a"]);
+        assert_eq!(mixer.channels[0].key, key("a"));
+
+        mixer.apply(MixerAction::SetGain(key("a"), -10.0));
+        mixer.sync_channels(&[(key("a"), "renamed".into()), (key("c"), "c".into())]);
+
+        assert_eq!(mixer.channels.len(), 2);
+        assert_eq!(mixer.channel(&key("a")).unwrap().gain_db, -10.0);
+        assert_eq!(mixer.channel(&key("a")).unwrap().label, "renamed");
+        assert!(mixer.channel(&key("b")).is_none());
+        assert_eq!(mixer.channel(&key("c")).unwrap().gain_db, 0.0);
+    }
+
+    #[test]
+    fn clamps_values() {
+        let mut mixer = mixer(&["a"]);
+        mixer.apply(MixerAction::SetGain(key("a"), 40.0));
+        mixer.apply(MixerAction::SetPan(key("a"), -3.0));
+        mixer.apply(MixerAction::SetMaster(-200.0));
+        let channel = mixer.channel(&key("a")).unwrap();
+        assert_eq!(channel.gain_db, MAX_GAIN_DB);
+        assert_eq!(channel.pan, -1.0);
+        assert_eq!(mixer.master_gain(), 0.0);
+    }
+
+    #[test]
+    fn ignores_unknown_channels() {
+        let mut mixer = mixer(&["a"]);
+        let before = mixer.clone();
+        mixer.apply(MixerAction::SetMute(key("x"), true));
+        assert_eq!(mixer, before);
+    }
+
+    #[test]
+    fn mutes() {
+        let mut mixer = mixer(&["a", "b"]);
+        mixer.apply(MixerAction::SetMute(key("a"), true));
+        assert_eq!(mixer.effective_gain(&key("a")), 0.0);
+        assert_eq!(mixer.effective_gain(&key("b")), 1.0);
+    }
+
+    #[test]
+    fn solos() {
+        let mut mixer = mixer(&["a", "b", "c"]);
+        mixer.apply(MixerAction::SetSolo(key("a"), true));
+        mixer.apply(MixerAction::SetSolo(key("b"), true));
+        assert!(mixer.audible(&key("a")));
+        assert!(mixer.audible(&key("b")));
+        assert!(!mixer.audible(&key("c")));
+
+        // Mute wins over solo
+        mixer.apply(MixerAction::SetMute(key("b"), true));
+        assert!(!mixer.audible(&key("b")));
+
+        mixer.apply(MixerAction::SetSolo(key("a"), false));
+        mixer.apply(MixerAction::SetSolo(key("b"), false));
+        assert!(mixer.audible(&key("c")));
+    }
+
+    #[test]
+    fn sends_ignore_fader_and_mute() {
+        let mut mixer = mixer(&["a"]);
+        assert_eq!(mixer.send_gain(&key("a"), SEND_BUS), 1.0);
+        assert_eq!(mixer.send_gain(&key("x"), SEND_BUS), 0.0);
+
+        mixer.apply(MixerAction::SetSend(key("a"), SEND_BUS.into(), -6.0));
+        mixer.apply(MixerAction::SetMute(key("a"), true));
+        mixer.apply(MixerAction::SetGain(key("a"), -20.0));
+        assert!((mixer.send_gain(&key("a"), SEND_BUS) - 0.501).abs() < 0.001);
+    }
+
+    #[test]
+    fn buses_have_their_own_levels() {
+        let mut mixer = mixer(&["a"]);
+        mixer.apply(MixerAction::SetInputMonitor(MIN_GAIN_DB));
+        mixer.apply(MixerAction::SetClick(-6.0));
+        mixer.apply(MixerAction::SetSendBus(20.0));
+
+        assert_eq!(mixer.input_monitor_gain(), 0.0);
+        assert!((mixer.click_gain() - 0.501).abs() < 0.001);
+        assert_eq!(mixer.send_db, MAX_GAIN_DB);
+        // The monitor side doesn't touch what goes out
+        assert_eq!(mixer.master_gain(), 1.0);
+        assert_eq!(mixer.send_gain(&key("a"), SEND_BUS), 1.0);
+    }
+
+    #[test]
+    fn recalls_by_key() {
+        let mut mixer = mixer(&["a", "b"]);
+        let mut scene = mixer.clone();
+        scene.apply(MixerAction::SetGain(key("a"), -12.0));
+        scene.apply(MixerAction::SetMute(key("b"), true));
+        scene.apply(MixerAction::SetMaster(-3.0));
+
+        mixer.recall(&scene);
+        assert_eq!(mixer, scene);
+    }
+
+    #[test]
+    fn recalls_by_label_in_a_new_session() {
+        let mut scene = mixer(&["a", "b"]);
+        scene.apply(MixerAction::SetGain(key("a"), -12.0));
+        scene.apply(MixerAction::SetPan(key("b"), 0.5));
+
+        // Same members, new ids
+        let other = |track: &str| ChannelKey {
+            owner: Uuid::from_u128(2),
+            track: track.to_string(),
+        };
+        let mut mixer = MixerState::default();
+        mixer.sync_channels(&[
+            (other("x"), "a".into()),
+            (other("y"), "b".into()),
+            (other("z"), "unknown".into()),
+        ]);
+        mixer.apply(MixerAction::SetGain(other("z"), -5.0));
+        mixer.recall(&scene);
+
+        assert_eq!(mixer.channel(&other("x")).unwrap().gain_db, -12.0);
+        assert_eq!(mixer.channel(&other("y")).unwrap().pan, 0.5);
+        assert_eq!(mixer.channel(&other("z")).unwrap().gain_db, -5.0);
+    }
+
+    #[test]
+    fn recalls_each_saved_channel_once() {
+        let mut scene = mixer(&["a"]);
+        scene.channels[0].label = "mic".into();
+        scene.apply(MixerAction::SetGain(key("a"), -12.0));
+
+        let mut mixer = MixerState::default();
+        mixer.sync_channels(&[(key("x"), "mic".into()), (key("y"), "mic".into())]);
+        mixer.recall(&scene);
+
+        assert_eq!(mixer.channel(&key("x")).unwrap().gain_db, -12.0);
+        assert_eq!(mixer.channel(&key("y")).unwrap().gain_db, 0.0);
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut mixer = mixer(&["a", "b"]);
+        mixer.apply(MixerAction::SetPan(key("a"), 0.5));
+        mixer.apply(MixerAction::SetSend(key("b"), SEND_BUS.into(), -3.0));
+        mixer.apply(MixerAction::SetMaster(-6.0));
+
+        let json = serde_json::to_string(&mixer).unwrap();
+        assert_eq!(serde_json::from_str::<MixerState>(&json).unwrap(), mixer);
+    }
+}
@@ -0,0 +1,331 @@
+//! SDP munging that tunes Opus for music rather than speech. Browsers default
+//! to mono, low bitrates, 20 ms packets, DTX and voice processing.
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// How much bandwidth a session spends on audio quality
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityPreset {
+    Low,
+    #[default]
+    Standard,
+    High,
+    Studio,
+}
+
+impl QualityPreset {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QualityPreset::Low => "low",
+            QualityPreset::Standard => "standard",
+            QualityPreset::High => "high",
+            QualityPreset::Studio => "studio",
+        }
+    }
+
+    /// Average bitrate in bits per second
+    pub fn bitrate(&self) -> u32 {
+        match self {
+            QualityPreset::Low => 64_000,
+            QualityPreset::Standard => 128_000,
+            QualityPreset::High => 256_000,
+            QualityPreset::Studio => 510_000,
+        }
+    }
+
+    pub fn opus_params(&self) -> OpusParams {
+        OpusParams {
+            max_average_bitrate: self.bitrate(),
+            ..OpusParams::default()
+        }
+    }
+}
+
+impl FromStr for QualityPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(QualityPreset::Low),
+            "standard" => Ok(QualityPreset::Standard),
+            "high" => Ok(QualityPreset::High),
+            "studio" => Ok(QualityPreset::Studio),
+            other => Err(format!("Unknown quality preset {other}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpusParams {
+    pub stereo: bool,
+    /// Bits per second
+    pub max_average_bitrate: u32,
+    /// Packet duration in milliseconds
+    pub ptime: u32,
+    pub inband_fec: bool,
+    pub dtx: bool,
+}
+
+impl Default for OpusParams {
+    fn default() -> Self {
+        Self {
+            stereo: true,
+            max_average_bitrate: QualityPreset::Standard.bitrate(),
+            ptime: 10,
+            inband_fec: true,
+            dtx: false,
+        }
+    }
+}
+
+impl OpusParams {
+    fn fmtp(&self) -> Vec<(&'static str, String)> {
+        let flag = |on: bool| String::from(if on { "1" } else { "0" });
+        vec![
+            ("minptime", self.ptime.to_string()),
+            ("useinbandfec", flag(self.inband_fec)),
+            ("usedtx", flag(self.dtx)),
+            ("stereo", flag(self.stereo)),
+            ("sprop-stereo", flag(self.stereo)),
+            ("maxaveragebitrate", self.max_average_bitrate.to_string()),
+        ]
+    }
+}
+
+/// Rewrites the fmtp and ptime attributes of every Opus payload in `sdp`.
+/// Everything else, including unrelated fmtp parameters, is left untouched.
+pub fn tune_opus(sdp: &str, params: &OpusParams) -> String {
+    let eol = if sdp.contains("\r\n") { "\r\n" } else { "\n" };
+    let trailing_eol = sdp.ends_with(eol);
+
+    // Split into the session part and one chunk per media section
+    let mut sections: Vec<Vec<String>> = vec![vec![]];
+    for line in sdp.split(eol).filter(|l| !l.is_empty()) {
+        if line.starts_with("m=") {
+            sections.push(vec![]);
+        }
+        sections
+            .last_mut()
+            .expect("There is always a section")
+            .push(line.to_string());
+    }
+
+    for section in sections.iter_mut().skip(1) {
+        if section[0].starts_with("m=audio") {
+            tune_section(section, params);
+        }
+    }
+
+    let mut tuned = sections.concat().join(eol);
+    if trailing_eol {
+        tuned.push_str(eol);
+    }
+    tuned
+}
+
+fn opus_payload(line: &str) -> Option<&str> {
+    let (payload, codec) = line.strip_prefix("a=rtpmap:")?.split_once(' ')?;
+    if codec.to_ascii_lowercase().starts_with("opus/") {
+        Some(payload)
+    } else {
+        None
+    }
+}
+
+fn tune_section(section: &mut Vec<String>, params: &OpusParams) {
+    let payloads: Vec<String> = section
+        .iter()
+        .filter_map(|line| opus_payload(line))
+        .map(String::from)
+        .collect();
+    if payloads.is_empty() {
+        return;
+    }
+
+    for payload in &payloads {
+        let prefix = format!("a=fmtp:{payload} ");
+        match section.iter().position(|line| line.starts_with(&prefix)) {
+            Some(i) => {
+                let merged = merge_fmtp(&section[i][prefix.len()..], params);
+                section[i] = format!("{prefix}{merged}");
+            }
+            None => {
+                let rtpmap = format!("a=rtpmap:{payload} ");
+                let i = section
+                    .iter()
+                    .position(|line| line.starts_with(&rtpmap))
+                    .expect("Payload was found through its rtpmap");
+                section.insert(i + 1, format!("{prefix}{}", merge_fmtp("", params)));
+            }
+        }
+    }
+
+    // ptime applies to the whole media section
+    section.retain(|line| !line.starts_with("a=ptime:"));
+    section.push(format!("a=ptime:{}", params.ptime));
+}
+
+fn merge_fmtp(existing: &str, params: &OpusParams) -> String {
+    let mut pairs: Vec<(String, String)> = existing
+        .split(';')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => (p.to_string(), String::new()),
+        })
+        .collect();
+
+    for (key, value) in params.fmtp() {
+        match pairs.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(key)) {
+            Some(pair) => pair.1 = value,
+            None => pairs.push((key.to_string(), value)),
+        }
+    }
+
+    pairs
+        .into_iter()
+        .map(|(key, value)| {
+            if value.is_empty() {
+                key
+            } else {
+                format!("{key}={value}")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Audio only offer as produced by Chrome
+    const CHROME_OFFER: &str = "v=0\r\n\
+o=- 4215775240449105457 2 IN IP4 127.0.0.1\r\n\
+s=-\r\n\
+t=0 0\r\n\
+a=group:BUNDLE 0\r\n\
+a=extmap-allow-mixed\r\n\
+a=msid-semantic: WMS 1b0f4b9b-9c5e-4d1c-8f2a-7c2b5c1d8e44\r\n\
+m=audio 9 UDP/TLS/RTP/SAVPF 111 63 9 0 8 13 110 126\r\n\
+c=IN IP4 0.0.0.0\r\n\
+a=rtcp:9 IN IP4 0.0.0.0\r\n\
+a=ice-ufrag:Z8Rw\r\n\
+a=ice-pwd:BJ9a8YAfPZ4vcfYyqB+0WYpC\r\n\
+a=ice-options:trickle\r\n\
+a=fingerprint:sha-256 3D:8A:7E:21:A4:9C:5B:F0:86:3E:77:6E:0D:3A:D2:27:1E:C9:A7:0F:26:1B:56:5A:10:22:8E:C4:6D:1F:63:1B\r\n\
+a=setup:actpass\r\n\
+a=mid:0\r\n\
+a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r\n\
+a=extmap:2 http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time\r\n\
+a=extmap:3 http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01\r\n\
+a=extmap:4 urn:ietf:params:rtp-hdrext:sdes:mid\r\n\
+a=sendrecv\r\n\
+a=msid:1b0f4b9b-9c5e-4d1c-8f2a-7c2b5c1d8e44 5d7c3e2a-6b1f-4a0e-9d8c-2f4e6a8b0c1d\r\n\
+a=rtcp-mux\r\n\
+a=rtpmap:111 opus/48000/2\r\n\
+a=rtcp-fb:111 transport-cc\r\n\
+a=fmtp:111 minptime=10;useinbandfec=1\r\n\
+a=rtpmap:63 red/48000/2\r\n\
+a=fmtp:63 111/111\r\n\
+a=rtpmap:9 G722/8000\r\n\
+a=rtpmap:0 PCMU/8000\r\n\
+a=rtpmap:8 PCMA/8000\r\n\
+a=rtpmap:13 CN/8000\r\n\
+a=rtpmap:110 telephone-event/48000\r\n\
+a=rtpmap:126 telephone-event/8000\r\n\
+a=ssrc:2890844526 cname:rC3R2kWnTq9Yb3Fx\r\n\
+a=ssrc:2890844526 msid:1b0f4b9b-9c5e-4d1c-8f2a-7c2b5c1d8e44 5d7c3e2a-6b1f-4a0e-9d8c-2f4e6a8b0c1d\r\n";
+
+    /// Answer as produced by Firefox, which already sets a ptime and has no
+    /// fmtp parameters worth keeping
+    const FIREFOX_ANSWER: &str = "v=0\r\n\
+o=mozilla...THIS_IS_SDPARTA-99.0 6558203519573836185 0 IN IP4 0.0.0.0\r\n\
+s=-\r\n\
+t=0 0\r\n\
+a=fingerprint:sha-256 61:3B:35:D9:6F:7B:6A:42:2C:0B:41:94:9D:0E:83:07:17:DD:43:D6:92:8C:AF:E4:5D:1B:6F:88:AE:C3:10:4F\r\n\
+a=group:BUNDLE 0\r\n\
+a=ice-options:trickle\r\n\
+a=msid-semantic:WMS *\r\n\
+m=audio 9 UDP/TLS/RTP/SAVPF 109 101\r\n\
+c=IN IP4 0.0.0.0\r\n\
+a=recvonly\r\n\
+a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r\n\
+a=extmap:3 urn:ietf:params:rtp-hdrext:sdes:mid\r\n\
+a=fmtp:109 maxplaybackrate=48000;stereo=1;useinbandfec=1\r\n\
+a=fmtp:101 0-15\r\n\
+a=ice-pwd:7ba6b5a0bb7b3a3eb2a6e3e08d6d3f4c\r\n\
+a=ice-ufrag:5e3b1f2c\r\n\
+a=mid:0\r\n\
+a=ptime:20\r\n\
+a=rtcp-mux\r\n\
+a=rtpmap:109 opus/48000/2\r\n\
+a=rtpmap:101 telephone-event/8000\r\n\
+a=setup:active\r\n";
+
+    fn fmtp(sdp: &str, payload: &str) -> String {
+        let prefix = format!("a=fmtp:{payload} ");
+        sdp.split("\r\n")
+            .find(|line| line.starts_with(&prefix))
+            .map(|line| line[prefix.len()..].to_string())
+            .expect("Missing fmtp line")
+    }
+
+    #[test]
+    fn tunes_chrome_offer() {
+        let tuned = tune_opus(CHROME_OFFER, &QualityPreset::High.opus_params());
+        assert_eq!(
+            fmtp(&tuned, "111"),
+            "minptime=10;useinbandfec=1;usedtx=0;stereo=1;sprop-stereo=1;maxaveragebitrate=256000"
+        );
+        assert!(tuned.ends_with("a=ptime:10\r\n"));
+        // Other codecs are left alone
+        assert_eq!(fmtp(&tuned, "63"), "111/111");
+    }
+
+    #[test]
+    fn keeps_unrelated_lines() {
+        let tuned = tune_opus(CHROME_OFFER, &OpusParams::default());
+        for line in CHROME_OFFER.split("\r\n").filter(|l| !l.starts_with("a=fmtp:111")) {
+            assert!(tuned.contains(line), "Lost line {line}");
+        }
+        assert_eq!(tuned.lines().count(), CHROME_OFFER.lines().count() + 1);
+    }
+
+    #[test]
+    fn tunes_firefox_answer() {
+        let params = OpusParams {
+            max_average_bitrate: 96_000,
+            ..OpusParams::default()
+        };
+        let tuned = tune_opus(FIREFOX_ANSWER, &params);
+        assert_eq!(
+            fmtp(&tuned, "109"),
+            "maxplaybackrate=48000;stereo=1;useinbandfec=1;minptime=10;usedtx=0;sprop-stereo=1;maxaveragebitrate=96000"
+        );
+        assert!(!tuned.contains("a=ptime:20"));
+        assert_eq!(tuned.matches("a=ptime:10").count(), 1);
+        assert_eq!(fmtp(&tuned, "101"), "0-15");
+    }
+
+    #[test]
+    fn adds_missing_fmtp() {
+        let sdp = "v=0\nm=audio 9 UDP/TLS/RTP/SAVPF 96\na=rtpmap:96 OPUS/48000/2\n";
+        let tuned = tune_opus(sdp, &OpusParams::default());
+        assert_eq!(
+            tuned,
+            "v=0\nm=audio 9 UDP/TLS/RTP/SAVPF 96\na=rtpmap:96 OPUS/48000/2\n\
+             a=fmtp:96 minptime=10;useinbandfec=1;usedtx=0;stereo=1;sprop-stereo=1;maxaveragebitrate=128000\n\
+             a=ptime:10\n"
+        );
+    }
+
+    #[test]
+    fn ignores_sections_without_opus() {
+        let sdp = "v=0\r\nm=audio 9 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\nm=video 9 RTP/AVP 96\r\na=rtpmap:96 VP8/90000\r\n";
+        assert_eq!(tune_opus(sdp, &OpusParams::default()), sdp);
+    }
+}
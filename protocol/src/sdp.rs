@@ -0,0 +1,570 @@
+//! The subset of SDP (RFC 8866) that WebRTC offers and answers use. Lines that
+//! aren't modelled are kept verbatim so that a description survives a round
+//! trip unchanged.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Descriptions for a handful of audio tracks stay well below this
+pub const MAX_SDP_LENGTH: usize = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SdpError {
+    TooLarge(usize),
+    MissingLine(char),
+    Malformed { line: usize, reason: String },
+}
+
+impl fmt::Display for SdpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SdpError::TooLarge(length) => {
+                write!(f, "Description is {length} bytes, at most {MAX_SDP_LENGTH} allowed")
+            }
+            SdpError::MissingLine(kind) => write!(f, "Description has no {kind}= line"),
+            SdpError::Malformed { line, reason } => write!(f, "Line {line}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SdpError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionDescription {
+    pub version: u8,
+    pub origin: Origin,
+    pub name: String,
+    /// Session level lines other than attributes, such as `t=`, in order
+    pub fields: Vec<Field>,
+    pub attributes: Vec<Attribute>,
+    pub media: Vec<MediaDescription>,
+}
+
+/// The `o=` line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Origin {
+    pub username: String,
+    pub session_id: String,
+    pub session_version: u64,
+    pub network_type: String,
+    pub address_type: String,
+    pub address: String,
+}
+
+/// A line that is passed through without interpretation
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub kind: char,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaDescription {
+    pub kind: String,
+    pub port: u16,
+    pub protocol: String,
+    /// Payload types in order of preference
+    pub formats: Vec<String>,
+    pub fields: Vec<Field>,
+    pub attributes: Vec<Attribute>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Direction {
+    SendRecv,
+    SendOnly,
+    RecvOnly,
+    Inactive,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::SendRecv => "sendrecv",
+            Direction::SendOnly => "sendonly",
+            Direction::RecvOnly => "recvonly",
+            Direction::Inactive => "inactive",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RtpMap {
+    pub payload: u8,
+    pub encoding: String,
+    pub clock_rate: u32,
+    pub channels: Option<u16>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Attribute {
+    RtpMap(RtpMap),
+    Fmtp { payload: u8, parameters: String },
+    IceUfrag(String),
+    IcePwd(String),
+    IceOptions(String),
+    Candidate(String),
+    EndOfCandidates,
+    Fingerprint { hash: String, value: String },
+    Setup(String),
+    Mid(String),
+    Direction(Direction),
+    Other { name: String, value: Option<String> },
+}
+
+/// A codec offered in a media section
+#[derive(Clone, Debug, PartialEq)]
+pub struct Codec {
+    pub payload: u8,
+    pub encoding: String,
+    pub clock_rate: u32,
+    pub channels: Option<u16>,
+    pub parameters: Option<String>,
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.encoding, self.clock_rate)?;
+        if let Some(channels) = self.channels {
+            write!(f, "/{channels}")?;
+        }
+        Ok(())
+    }
+}
+
+impl SessionDescription {
+    /// Parses and checks a description received from a client
+    pub fn validate(sdp: &str) -> Result<SessionDescription, SdpError> {
+        sdp.parse()
+    }
+
+    pub fn audio(&self) -> impl Iterator<Item = &MediaDescription> {
+        self.media.iter().filter(|m| m.kind == "audio")
+    }
+}
+
+impl MediaDescription {
+    pub fn codecs(&self) -> Vec<Codec> {
+        self.formats
+            .iter()
+            .filter_map(|format| format.parse::<u8>().ok())
+            .filter_map(|payload| self.codec(payload))
+            .collect()
+    }
+
+    pub fn codec(&self, payload: u8) -> Option<Codec> {
+        let rtpmap = self.attributes.iter().find_map(|a| match a {
+            Attribute::RtpMap(map) if map.payload == payload => Some(map),
+            _ => None,
+        })?;
+        let parameters = self.attributes.iter().find_map(|a| match a {
+            Attribute::Fmtp {
+                payload: p,
+                parameters,
+            } if *p == payload => Some(parameters.clone()),
+            _ => None,
+        });
+        Some(Codec {
+            payload,
+            encoding: rtpmap.encoding.clone(),
+            clock_rate: rtpmap.clock_rate,
+            channels: rtpmap.channels,
+            parameters,
+        })
+    }
+
+    /// In an answer the first format is the one that will be sent
+    pub fn negotiated_codec(&self) -> Option<Codec> {
+        self.codecs().into_iter().next()
+    }
+}
+
+fn malformed(line: usize, reason: impl Into<String>) -> SdpError {
+    SdpError::Malformed {
+        line,
+        reason: reason.into(),
+    }
+}
+
+fn parse_origin(line: usize, value: &str) -> Result<Origin, SdpError> {
+    let parts: Vec<&str> = value.split(' ').collect();
+    let [username, session_id, session_version, network_type, address_type, address] =
+        parts[..]
+    else {
+        return Err(malformed(line, "Origin needs six fields"));
+    };
+    Ok(Origin {
+        username: username.to_string(),
+        session_id: session_id.to_string(),
+        session_version: session_version
+            .parse()
+            .map_err(|_| malformed(line, "Bad session version"))?,
+        network_type: network_type.to_string(),
+        address_type: address_type.to_string(),
+        address: address.to_string(),
+    })
+}
+
+fn parse_media(line: usize, value: &str) -> Result<MediaDescription, SdpError> {
+    let mut parts = value.split(' ');
+    let (Some(kind), Some(port), Some(protocol)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(malformed(line, "Media needs a kind, port and protocol"));
+    };
+    Ok(MediaDescription {
+        kind: kind.to_string(),
+        port: port.parse().map_err(|_| malformed(line, "Bad port"))?,
+        protocol: protocol.to_string(),
+        formats: parts.map(String::from).collect(),
+        fields: vec![],
+        attributes: vec![],
+    })
+}
+
+fn parse_payload(line: usize, payload: &str) -> Result<u8, SdpError> {
+    payload
+        .parse()
+        .map_err(|_| malformed(line, format!("Bad payload type {payload}")))
+}
+
+fn parse_attribute(line: usize, value: &str) -> Result<Attribute, SdpError> {
+    let (name, value) = match value.split_once(':') {
+        Some((name, value)) => (name, Some(value)),
+        None => (value, None),
+    };
+    let required = || value.ok_or_else(|| malformed(line, format!("{name} needs a value")));
+
+    let attribute = match name {
+        "rtpmap" => {
+            let (payload, codec) = required()?
+                .split_once(' ')
+                .ok_or_else(|| malformed(line, "rtpmap needs a payload and a codec"))?;
+            let mut codec = codec.split('/');
+            let (Some(encoding), Some(clock_rate)) = (codec.next(), codec.next()) else {
+                return Err(malformed(line, "rtpmap needs an encoding and a clock rate"));
+            };
+            Attribute::RtpMap(RtpMap {
+                payload: parse_payload(line, payload)?,
+                encoding: encoding.to_string(),
+                clock_rate: clock_rate
+                    .parse()
+                    .map_err(|_| malformed(line, "Bad clock rate"))?,
+                channels: match codec.next() {
+                    Some(channels) => Some(
+                        channels
+                            .parse()
+                            .map_err(|_| malformed(line, "Bad channel count"))?,
+                    ),
+                    None => None,
+                },
+            })
+        }
+        "fmtp" => {
+            let (payload, parameters) = required()?
+                .split_once(' ')
+                .ok_or_else(|| malformed(line, "fmtp needs a payload and parameters"))?;
+            Attribute::Fmtp {
+                payload: parse_payload(line, payload)?,
+                parameters: parameters.to_string(),
+            }
+        }
+        "ice-ufrag" => Attribute::IceUfrag(required()?.to_string()),
+        "ice-pwd" => Attribute::IcePwd(required()?.to_string()),
+        "ice-options" => Attribute::IceOptions(required()?.to_string()),
+        "candidate" => Attribute::Candidate(required()?.to_string()),
+        "end-of-candidates" => Attribute::EndOfCandidates,
+        "fingerprint" => {
+            let (hash, value) = required()?
+                .split_once(' ')
+                .ok_or_else(|| malformed(line, "fingerprint needs a hash and a value"))?;
+            Attribute::Fingerprint {
+                hash: hash.to_string(),
+                value: value.to_string(),
+            }
+        }
+        "setup" => Attribute::Setup(required()?.to_string()),
+        "mid" => Attribute::Mid(required()?.to_string()),
+        "sendrecv" if value.is_none() => Attribute::Direction(Direction::SendRecv),
+        "sendonly" if value.is_none() => Attribute::Direction(Direction::SendOnly),
+        "recvonly" if value.is_none() => Attribute::Direction(Direction::RecvOnly),
+        "inactive" if value.is_none() => Attribute::Direction(Direction::Inactive),
+        _ => Attribute::Other {
+            name: name.to_string(),
+            value: value.map(String::from),
+        },
+    };
+    Ok(attribute)
+}
+
+impl FromStr for SessionDescription {
+    type Err = SdpError;
+
+    fn from_str(sdp: &str) -> Result<Self, Self::Err> {
+        if sdp.len() > MAX_SDP_LENGTH {
+            return Err(SdpError::TooLarge(sdp.len()));
+        }
+
+        let mut version = None;
+        let mut origin = None;
+        let mut name = None;
+        let mut fields = vec![];
+        let mut attributes = vec![];
+        let mut media: Vec<MediaDescription> = vec![];
+
+        for (i, line) in sdp.lines().enumerate().filter(|(_, l)| !l.is_empty()) {
+            let number = i + 1;
+            let (kind, value) = match line.split_once('=') {
+                Some((kind, value)) if kind.len() == 1 => (kind.chars().next().unwrap(), value),
+                _ => return Err(malformed(number, "Expected <type>=<value>")),
+            };
+
+            match (kind, media.last_mut()) {
+                ('v', None) => {
+                    version = Some(value.parse().map_err(|_| malformed(number, "Bad version"))?)
+                }
+                ('o', None) => origin = Some(parse_origin(number, value)?),
+                ('s', None) => name = Some(value.to_string()),
+                ('m', _) => media.push(parse_media(number, value)?),
+                ('a', None) => attributes.push(parse_attribute(number, value)?),
+                ('a', Some(section)) => section.attributes.push(parse_attribute(number, value)?),
+                ('i' | 'c' | 'b' | 'k', Some(section)) => section.fields.push(Field {
+                    kind,
+                    value: value.to_string(),
+                }),
+                ('i' | 'u' | 'e' | 'p' | 'c' | 'b' | 't' | 'r' | 'z' | 'k', None) => {
+                    fields.push(Field {
+                        kind,
+                        value: value.to_string(),
+                    })
+                }
+                (kind, _) => return Err(malformed(number, format!("Unexpected {kind}= line"))),
+            }
+        }
+
+        if !fields.iter().any(|f| f.kind == 't') {
+            return Err(SdpError::MissingLine('t'));
+        }
+        Ok(SessionDescription {
+            version: version.ok_or(SdpError::MissingLine('v'))?,
+            origin: origin.ok_or(SdpError::MissingLine('o'))?,
+            name: name.ok_or(SdpError::MissingLine('s'))?,
+            fields,
+            attributes,
+            media,
+        })
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}\r\n", self.kind, self.value)
+    }
+}
+
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a=")?;
+        match self {
+            Attribute::RtpMap(map) => {
+                write!(f, "rtpmap:{} {}/{}", map.payload, map.encoding, map.clock_rate)?;
+                if let Some(channels) = map.channels {
+                    write!(f, "/{channels}")?;
+                }
+            }
+            Attribute::Fmtp {
+                payload,
+                parameters,
+            } => write!(f, "fmtp:{payload} {parameters}")?,
+            Attribute::IceUfrag(ufrag) => write!(f, "ice-ufrag:{ufrag}")?,
+            Attribute::IcePwd(pwd) => write!(f, "ice-pwd:{pwd}")?,
+            Attribute::IceOptions(options) => write!(f, "ice-options:{options}")?,
+            Attribute::Candidate(candidate) => write!(f, "candidate:{candidate}")?,
+            Attribute::EndOfCandidates => write!(f, "end-of-candidates")?,
+            Attribute::Fingerprint { hash, value } => write!(f, "fingerprint:{hash} {value}")?,
+            Attribute::Setup(setup) => write!(f, "setup:{setup}")?,
+            Attribute::Mid(mid) => write!(f, "mid:{mid}")?,
+            Attribute::Direction(direction) => write!(f, "{}", direction.as_str())?,
+            Attribute::Other { name, value } => {
+                write!(f, "{name}")?;
+                if let Some(value) = value {
+                    write!(f, ":{value}")?;
+                }
+            }
+        }
+        write!(f, "\r\n")
+    }
+}
+
+impl fmt::Display for MediaDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m={} {} {}", self.kind, self.port, self.protocol)?;
+        for format in &self.formats {
+            write!(f, " {format}")?;
+        }
+        write!(f, "\r\n")?;
+        for field in &self.fields {
+            write!(f, "{field}")?;
+        }
+        for attribute in &self.attributes {
+            write!(f, "{attribute}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SessionDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let o = &self.origin;
+        write!(f, "v={}\r\n", self.version)?;
+        write!(
+            f,
+            "o={} {} {} {} {} {}\r\n",
+            o.username, o.session_id, o.session_version, o.network_type, o.address_type, o.address
+        )?;
+        write!(f, "s={}\r\n", self.name)?;
+        for field in &self.fields {
+            write!(f, "{field}")?;
+        }
+        for attribute in &self.attributes {
+            write!(f, "{attribute}")?;
+        }
+        for media in &self.media {
+            write!(f, "{media}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHROME_OFFER: &str = "v=0\r\n\
+o=- 4215775240449105457 2 IN IP4 127.0.0.1\r\n\
+s=-\r\n\
+t=0 0\r\n\
+a=group:BUNDLE 0\r\n\
+a=extmap-allow-mixed\r\n\
+a=msid-semantic: WMS 1b0f4b9b-9c5e-4d1c-8f2a-7c2b5c1d8e44\r\n\
+m=audio 9 UDP/TLS/RTP/SAVPF 111 63 9 0 8 13 110 126\r\n\
+c=IN IP4 0.0.0.0\r\n\
+a=rtcp:9 IN IP4 0.0.0.0\r\n\
+a=ice-ufrag:Z8Rw\r\n\
+a=ice-pwd:BJ9a8YAfPZ4vcfYyqB+0WYpC\r\n\
+a=ice-options:trickle\r\n\
+a=fingerprint:sha-256 3D:8A:7E:21:A4:9C:5B:F0:86:3E:77:6E:0D:3A:D2:27:1E:C9:A7:0F:26:1B:56:5A:10:22:8E:C4:6D:1F:63:1B\r\n\
+a=setup:actpass\r\n\
+a=mid:0\r\n\
+a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r\n\
+a=sendrecv\r\n\
+a=msid:1b0f4b9b-9c5e-4d1c-8f2a-7c2b5c1d8e44 5d7c3e2a-6b1f-4a0e-9d8c-2f4e6a8b0c1d\r\n\
+a=rtcp-mux\r\n\
+a=rtpmap:111 opus/48000/2\r\n\
+a=rtcp-fb:111 transport-cc\r\n\
+a=fmtp:111 minptime=10;useinbandfec=1\r\n\
+a=rtpmap:63 red/48000/2\r\n\
+a=fmtp:63 111/111\r\n\
+a=rtpmap:9 G722/8000\r\n\
+a=rtpmap:0 PCMU/8000\r\n\
+a=rtpmap:8 PCMA/8000\r\n\
+a=rtpmap:13 CN/8000\r\n\
+a=rtpmap:110 telephone-event/48000\r\n\
+a=rtpmap:126 telephone-event/8000\r\n\
+a=ssrc:2890844526 cname:rC3R2kWnTq9Yb3Fx\r\n";
+
+    const FIREFOX_ANSWER: &str = "v=0\r\n\
+o=mozilla...THIS_IS_SDPARTA-99.0 6558203519573836185 0 IN IP4 0.0.0.0\r\n\
+s=-\r\n\
+t=0 0\r\n\
+a=fingerprint:sha-256 61:3B:35:D9:6F:7B:6A:42:2C:0B:41:94:9D:0E:83:07:17:DD:43:D6:92:8C:AF:E4:5D:1B:6F:88:AE:C3:10:4F\r\n\
+a=group:BUNDLE 0\r\n\
+a=ice-options:trickle\r\n\
+a=msid-semantic:WMS *\r\n\
+m=audio 9 UDP/TLS/RTP/SAVPF 109 101\r\n\
+c=IN IP4 0.0.0.0\r\n\
+a=recvonly\r\n\
+a=fmtp:109 maxplaybackrate=48000;stereo=1;useinbandfec=1\r\n\
+a=fmtp:101 0-15\r\n\
+a=ice-pwd:7ba6b5a0bb7b3a3eb2a6e3e08d6d3f4c\r\n\
+a=ice-ufrag:5e3b1f2c\r\n\
+a=mid:0\r\n\
+a=candidate:0 1 UDP 2122187007 192.168.1.12 52384 typ host\r\n\
+a=end-of-candidates\r\n\
+a=rtcp-mux\r\n\
+a=rtpmap:109 opus/48000/2\r\n\
+a=rtpmap:101 telephone-event/8000\r\n\
+a=setup:active\r\n";
+
+    #[test]
+    fn round_trips() {
+        for sdp in [CHROME_OFFER, FIREFOX_ANSWER] {
+            let parsed: SessionDescription = sdp.parse().unwrap();
+            assert_eq!(parsed.to_string(), sdp);
+            assert_eq!(parsed.to_string().parse::<SessionDescription>().unwrap(), parsed);
+        }
+    }
+
+    #[test]
+    fn models_webrtc_attributes() {
+        let parsed: SessionDescription = FIREFOX_ANSWER.parse().unwrap();
+        assert_eq!(parsed.origin.session_version, 0);
+        let audio = parsed.audio().next().unwrap();
+        assert_eq!(audio.port, 9);
+        assert!(audio.attributes.contains(&Attribute::Direction(Direction::RecvOnly)));
+        assert!(audio.attributes.contains(&Attribute::IceUfrag("5e3b1f2c".into())));
+        assert!(audio.attributes.contains(&Attribute::Setup("active".into())));
+        assert!(audio.attributes.contains(&Attribute::EndOfCandidates));
+    }
+
+    #[test]
+    fn finds_negotiated_codec() {
+        let parsed: SessionDescription = FIREFOX_ANSWER.parse().unwrap();
+        let codec = parsed.audio().next().unwrap().negotiated_codec().unwrap();
+        assert_eq!(codec.to_string(), "opus/48000/2");
+        assert_eq!(
+            codec.parameters.as_deref(),
+            Some("maxplaybackrate=48000;stereo=1;useinbandfec=1")
+        );
+
+        let parsed: SessionDescription = CHROME_OFFER.parse().unwrap();
+        let codecs = parsed.audio().next().unwrap().codecs();
+        assert_eq!(codecs.len(), 8);
+        assert_eq!(codecs[3].encoding, "PCMU");
+        assert_eq!(codecs[3].channels, None);
+    }
+
+    #[test]
+    fn accepts_bare_newlines() {
+        let sdp = CHROME_OFFER.replace("\r\n", "\n");
+        assert_eq!(sdp.parse::<SessionDescription>().unwrap().to_string(), CHROME_OFFER);
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        assert_eq!(
+            "v=0\r\ns=-\r\nt=0 0\r\n".parse::<SessionDescription>(),
+            Err(SdpError::MissingLine('o'))
+        );
+        assert_eq!(
+            CHROME_OFFER.replace("t=0 0\r\n", "").parse::<SessionDescription>(),
+            Err(SdpError::MissingLine('t'))
+        );
+        assert!(matches!(
+            CHROME_OFFER.replace("opus/48000/2", "opus").parse::<SessionDescription>(),
+            Err(SdpError::Malformed { line: 21, .. })
+        ));
+        assert!(matches!(
+            "hello".parse::<SessionDescription>(),
+            Err(SdpError::Malformed { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_oversized() {
+        let huge = CHROME_OFFER.to_string() + &"a=x\r\n".repeat(MAX_SDP_LENGTH / 5);
+        assert!(matches!(
+            SessionDescription::validate(&huge),
+            Err(SdpError::TooLarge(_))
+        ));
+    }
+}
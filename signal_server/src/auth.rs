@@ -0,0 +1,140 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, StatusCode},
+};
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use tokio::sync::RwLock;
+use tracing::log::{log, Level};
+
+use crate::{database::musician_for_subject, ServerState};
+
+/// Same provider the frontend signs in with
+const DEFAULT_USERINFO: &str = "https://dev-qcuxgjrapycf5ib4.us.auth0.com/userinfo";
+
+/// How long a checked access token is trusted before asking the provider
+/// again
+const TOKEN_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// What the identity provider tells about the owner of an access token
+#[derive(Deserialize, Debug)]
+struct UserInfo {
+    sub: String,
+    name: Option<String>,
+    nickname: Option<String>,
+}
+
+/// Checks access tokens by asking the identity provider who they belong
+/// to, each subject is a musician of its own
+#[derive(Clone)]
+pub struct Authenticator {
+    userinfo: String,
+    client: reqwest::Client,
+    /// Musicians of recently checked tokens
+    known: Arc<RwLock<HashMap<String, (i32, Instant)>>>,
+}
+
+impl Authenticator {
+    pub fn new() -> Self {
+        Self {
+            userinfo: std::env::var("AUTH_USERINFO_ENDPOINT")
+                .unwrap_or_else(|_| DEFAULT_USERINFO.to_string()),
+            client: reqwest::Client::new(),
+            known: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// The musician a token belongs to, registered the first time they show
+    /// up. `None` when the provider doesn't accept the token.
+    pub async fn musician(&self, token: &str, db: &Pool<Sqlite>) -> Result<Option<i32>, StatusCode> {
+        if let Some((id, checked)) = self.known.read().await.get(token) {
+            if checked.elapsed() < TOKEN_TTL {
+                return Ok(Some(*id));
+            }
+        }
+
+        let response = self
+            .client
+            .get(&self.userinfo)
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|e| {
+                log!(Level::Error, "Failed to reach the identity provider: {e}");
+                StatusCode::BAD_GATEWAY
+            })?;
+        if response.status().is_client_error() {
+            return Ok(None);
+        }
+        let info: UserInfo = response
+            .error_for_status()
+            .map_err(|e| {
+                log!(Level::Error, "Identity provider failed: {e}");
+                StatusCode::BAD_GATEWAY
+            })?
+            .json()
+            .await
+            .map_err(|e| {
+                log!(Level::Error, "Unexpected answer from the identity provider: {e}");
+                StatusCode::BAD_GATEWAY
+            })?;
+
+        let id = musician_for_subject(&info.sub, info.name.or(info.nickname), db)
+            .await
+            .map_err(|e| {
+                log!(Level::Error, "Failed to look up musician of {}: {e}", info.sub);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        let mut known = self.known.write().await;
+        known.retain(|_, (_, checked)| checked.elapsed() < TOKEN_TTL);
+        known.insert(token.to_string(), (id, Instant::now()));
+        Ok(Some(id))
+    }
+}
+
+/// The signed in musician making a request, from its
+/// `Authorization: Bearer` header
+pub struct AuthMusician(pub i32);
+
+#[async_trait]
+impl FromRequestParts<ServerState> for AuthMusician {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &ServerState) -> Result<Self, Self::Rejection> {
+        match MaybeMusician::from_request_parts(parts, state).await? {
+            MaybeMusician(Some(id)) => Ok(AuthMusician(id)),
+            MaybeMusician(None) => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+}
+
+/// Like [`AuthMusician`] for requests anyone may make, `None` when no token
+/// was sent. A token that is sent has to be valid.
+pub struct MaybeMusician(pub Option<i32>);
+
+#[async_trait]
+impl FromRequestParts<ServerState> for MaybeMusician {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &ServerState) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(header::AUTHORIZATION) else {
+            return Ok(MaybeMusician(None));
+        };
+        let token = value
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        match state.auth.musician(token, &state.db).await? {
+            Some(id) => Ok(MaybeMusician(Some(id))),
+            None => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+}
@@ -1,18 +1,33 @@
 /// Inspired by this blogpost:
 /// https://tms-dev-blog.com/rust-sqlx-basics-with-sqlite/#Creating_an_SQLite_database
 
-use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool, Pool};
+use sqlx::{migrate::MigrateDatabase, Row, Sqlite, SqlitePool, Pool};
 use tracing::{log::{log, Level}, instrument};
 use anyhow::{Result, bail};
-use protocol::{Musician, Band};
+use protocol::{Musician, Band, BandSummary, MemberProfile, Invite, Diagnostic, QualitySample, mixer::MixerScene};
+use uuid::Uuid;
+
+use crate::diagnostics::StoredDiagnostic;
 
 const DB_URL: &str = "sqlite://sqlite.db";
 
+/// Tables are only created when missing, so columns added later have to be
+/// added to databases created before them
+const ADDED_COLUMNS: [(&str, &str, &str); 6] = [
+    ("musicians", "instrument", "text"),
+    ("musicians", "avatar", "text"),
+    ("musicians", "subject", "text"),
+    ("diagnostics", "instance", "text"),
+    ("diagnostics", "musician", "integer"),
+    ("invites", "instance", "text"),
+];
+
 #[instrument]
 pub async fn setup_database() -> Result<Pool<Sqlite>>{
     let db = create_database().await?;
     add_tables(&db).await;
-    add_musician(Musician { id: 1, name: Some("Alex".into()) }, &db).await;
+    add_missing_columns(&db).await?;
+    add_musician(Musician { id: 1, name: Some("Alex".into()), instrument: None, avatar: None }, &db).await;
     Ok(db)
 }
 
@@ -36,18 +51,93 @@ async fn add_tables(db: &Pool<Sqlite>) -> Result<()> {
     Ok(())
 }
 
+async fn add_missing_columns(db: &Pool<Sqlite>) -> Result<()> {
+    for (table, column, kind) in ADDED_COLUMNS {
+        let columns = sqlx::query(&format!("pragma table_info({table})")).fetch_all(db).await?;
+        if !columns.iter().any(|row| row.get::<String, _>("name") == column) {
+            log!(Level::Info, "Adding column {column} to {table}");
+            sqlx::query(&format!("alter table {table} add column {column} {kind}")).execute(db).await?;
+        }
+    }
+    sqlx::query("create unique index if not exists musicians_subject on musicians (subject)")
+        .execute(db).await?;
+    sqlx::query("create index if not exists diagnostics_instance on diagnostics (instance, recorded_at)")
+        .execute(db).await?;
+    Ok(())
+}
+
 async fn add_musician(musician: Musician, db: &Pool<Sqlite>) -> Result<()> {
     let result = sqlx::query!("
-        insert or replace into musicians
+        insert or replace into musicians (id, name, instrument, avatar)
+        values ($1,$2,$3,$4)
+    ", musician.id, musician.name, musician.instrument, musician.avatar).execute(db).await?;
+    log!(Level::Info, "{:?}", result);
+    Ok(())
+}
+
+pub async fn get_musician(id: i32, db: &Pool<Sqlite>) -> Result<Option<Musician>> {
+    let musician = sqlx::query_as!(Musician, r#"
+        select id as "id: i32", name, instrument, avatar
+        from musicians
+        where id = $1
+    "#, id).fetch_optional(db).await?;
+    Ok(musician)
+}
+
+/// The musician signed in as `subject`, registered under `name` the first
+/// time
+pub async fn musician_for_subject(subject: &str, name: Option<String>, db: &Pool<Sqlite>) -> Result<i32> {
+    let known = sqlx::query!(r#"
+        select id as "id: i32"
+        from musicians
+        where subject = $1
+    "#, subject).fetch_optional(db).await?;
+    if let Some(musician) = known {
+        return Ok(musician.id);
+    }
+
+    let added = sqlx::query!(r#"
+        insert into musicians (name, subject)
         values ($1,$2)
-    ", musician.id, musician.name).execute(db).await?;
+        returning id as "id: i32"
+    "#, name, subject).fetch_one(db).await?;
+    Ok(added.id)
+}
+
+pub async fn update_musician_profile(id: i32, profile: &MemberProfile, db: &Pool<Sqlite>) -> Result<()> {
+    let result = sqlx::query!("
+        update musicians
+        set name = $2, instrument = $3, avatar = $4
+        where id = $1
+    ", id, profile.name, profile.instrument, profile.avatar).execute(db).await?;
     log!(Level::Info, "{:?}", result);
     Ok(())
 }
 
+/// The bands a musician plays in
+pub async fn get_musician_bands(musician: i32, db: &Pool<Sqlite>) -> Result<Vec<i32>> {
+    let rows = sqlx::query!(r#"
+        select id as "id: i32"
+        from band
+        where member = $1
+    "#, musician).fetch_all(db).await?;
+    Ok(rows.into_iter().map(|row| row.id).collect())
+}
+
+/// The bands a musician plays in, with their names
+pub async fn get_band_summaries(musician: i32, db: &Pool<Sqlite>) -> Result<Vec<BandSummary>> {
+    let bands = sqlx::query_as!(BandSummary, r#"
+        select id as "id: i32", name
+        from band
+        where member = $1
+        order by name
+    "#, musician).fetch_all(db).await?;
+    Ok(bands)
+}
+
 async fn add_band(band: Band, db: &Pool<Sqlite>) -> Result<()> {
     let result = sqlx::query!("
-        insert or replace into musicians
+        insert or replace into musicians (id, name)
         values ($1,$2)
     ", band.id, band.name).execute(db).await?;
     log!(Level::Info, "{:?}", result);
@@ -56,11 +146,142 @@ async fn add_band(band: Band, db: &Pool<Sqlite>) -> Result<()> {
 
 async fn add_session(band: Band, db: &Pool<Sqlite>) -> Result<()> {
     let result = sqlx::query!("
-        insert or replace into musicians
+        insert or replace into musicians (id, name)
         values ($1,$2)
     ", band.id, band.name).execute(db).await?;
     log!(Level::Info, "{:?}", result);
     Ok(())
 }
 
+pub async fn add_invite(invite: &Invite, db: &Pool<Sqlite>) -> Result<()> {
+    let role = invite.role.as_str();
+    let instance = invite.instance.to_string();
+    let result = sqlx::query!("
+        insert into invites (token, session, instance, role, expires_at)
+        values ($1,$2,$3,$4,$5)
+    ", invite.token, invite.session, instance, role, invite.expires_at).execute(db).await?;
+    log!(Level::Info, "{:?}", result);
+    Ok(())
+}
+
+/// Only returns invites that haven't expired yet. Invites from before they
+/// were tied to a run of a session aren't any good either.
+pub async fn get_invite(token: &str, now: i64, db: &Pool<Sqlite>) -> Result<Option<Invite>> {
+    let row = sqlx::query!("
+        select token, session, instance, role, expires_at
+        from invites
+        where token = $1 and expires_at > $2 and instance is not null
+    ", token, now).fetch_optional(db).await?;
+
+    match row {
+        Some(row) => Ok(Some(Invite {
+            token: row.token,
+            session: row.session,
+            instance: Uuid::parse_str(row.instance.as_deref().unwrap_or_default())?,
+            role: row.role.parse().map_err(anyhow::Error::msg)?,
+            expires_at: row.expires_at,
+        })),
+        None => Ok(None),
+    }
+}
+
+pub async fn add_diagnostic(stored: &StoredDiagnostic, db: &Pool<Sqlite>) -> Result<()> {
+    let diagnostic = &stored.diagnostic;
+    let instance = stored.instance.to_string();
+    let participant = diagnostic.participant.to_string();
+    let peer = diagnostic.peer.to_string();
+    let sample = &diagnostic.sample;
+    sqlx::query!("
+        insert into diagnostics (
+            instance, session, musician, participant, name, peer, recorded_at,
+            round_trip_time, jitter, packet_loss, jitter_buffer_delay, bitrate_in, bitrate_out,
+            local_candidate, remote_candidate, codec
+        )
+        values ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16)
+    ", instance, stored.session, stored.musician, participant, diagnostic.name, peer, diagnostic.recorded_at,
+        sample.round_trip_time, sample.jitter, sample.packet_loss, sample.jitter_buffer_delay,
+        sample.bitrate_in, sample.bitrate_out,
+        sample.local_candidate, sample.remote_candidate, sample.codec
+    ).execute(db).await?;
+    Ok(())
+}
+
+/// Whether a musician sent samples from a run of a session
+pub async fn took_part(instance: Uuid, musician: i32, db: &Pool<Sqlite>) -> Result<bool> {
+    let instance = instance.to_string();
+    let row = sqlx::query!("
+        select 1 as took_part
+        from diagnostics
+        where instance = $1 and musician = $2
+        limit 1
+    ", instance, musician).fetch_optional(db).await?;
+    Ok(row.is_some())
+}
 
+/// All samples recorded in a run of a session, oldest first
+pub async fn get_diagnostics(instance: Uuid, db: &Pool<Sqlite>) -> Result<Vec<Diagnostic>> {
+    let instance = instance.to_string();
+    let rows = sqlx::query!("
+        select participant, name, peer, recorded_at,
+            round_trip_time, jitter, packet_loss, jitter_buffer_delay, bitrate_in, bitrate_out,
+            local_candidate, remote_candidate, codec
+        from diagnostics
+        where instance = $1
+        order by recorded_at, id
+    ", instance).fetch_all(db).await?;
+
+    rows.into_iter()
+        .map(|row| Ok(Diagnostic {
+            participant: Uuid::parse_str(&row.participant)?,
+            name: row.name,
+            peer: Uuid::parse_str(&row.peer)?,
+            recorded_at: row.recorded_at,
+            sample: QualitySample {
+                round_trip_time: row.round_trip_time,
+                jitter: row.jitter,
+                packet_loss: row.packet_loss,
+                jitter_buffer_delay: row.jitter_buffer_delay,
+                bitrate_in: row.bitrate_in,
+                bitrate_out: row.bitrate_out,
+                local_candidate: row.local_candidate,
+                remote_candidate: row.remote_candidate,
+                codec: row.codec,
+            },
+        }))
+        .collect()
+}
+
+/// Scenes a musician saved for a band, by name
+pub async fn get_scenes(musician: i32, band: i32, db: &Pool<Sqlite>) -> Result<Vec<MixerScene>> {
+    let rows = sqlx::query!("
+        select name, mixer
+        from scenes
+        where musician = $1 and band = $2
+        order by name
+    ", musician, band).fetch_all(db).await?;
+
+    rows.into_iter()
+        .map(|row| Ok(MixerScene {
+            name: row.name,
+            mixer: serde_json::from_str(&row.mixer)?,
+        }))
+        .collect()
+}
+
+/// Replaces any scene with the same name
+pub async fn put_scene(musician: i32, band: i32, scene: &MixerScene, db: &Pool<Sqlite>) -> Result<()> {
+    let mixer = serde_json::to_string(&scene.mixer)?;
+    sqlx::query!("
+        insert or replace into scenes
+        values ($1,$2,$3,$4)
+    ", musician, band, scene.name, mixer).execute(db).await?;
+    Ok(())
+}
+
+pub async fn delete_scene(musician: i32, band: i32, name: &str, db: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query!("
+        delete from scenes
+        where musician = $1 and band = $2 and name = $3
+    ", musician, band, name).execute(db).await?;
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use protocol::{Diagnostic, DIAGNOSTICS_INTERVAL};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use sqlx::{Pool, Sqlite};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::log::{log, Level};
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthMusician,
+    database::{add_diagnostic, get_diagnostics, took_part},
+    ServerState,
+};
+
+/// Samples waiting to be written before new ones are dropped
+const BACKLOG: usize = 256;
+
+/// Shortest time between two samples about the same peer, members upload
+/// every `DIAGNOSTICS_INTERVAL` and timers may fire a little early
+pub const MIN_SAMPLE_GAP: Duration = DIAGNOSTICS_INTERVAL.saturating_sub(Duration::from_secs(1));
+
+/// A quality sample together with the run of the session it was taken in
+/// and the signed in musician who sent it
+pub struct StoredDiagnostic {
+    pub instance: Uuid,
+    pub session: String,
+    pub musician: Option<i32>,
+    pub diagnostic: Diagnostic,
+}
+
+/// Writes samples to the database one at a time, so that a slow write
+/// doesn't hold up the signaling of the session that sent them
+pub fn spawn_writer(db: Pool<Sqlite>) -> mpsc::Sender<StoredDiagnostic> {
+    let (tx, mut rx) = mpsc::channel::<StoredDiagnostic>(BACKLOG);
+    tokio::spawn(async move {
+        while let Some(stored) = rx.recv().await {
+            if let Err(e) = add_diagnostic(&stored, &db).await {
+                log!(Level::Error, "Failed to store diagnostics: {e}");
+            }
+        }
+    });
+    tx
+}
+
+/// Quality samples uploaded by the members of a run of a session, for
+/// looking into what went wrong afterwards. Only those who took part in it
+/// get to see them.
+pub async fn session_timeline(
+    State(server_state): State<ServerState>,
+    AuthMusician(musician): AuthMusician,
+    Path(instance): Path<Uuid>,
+) -> Result<Json<Vec<Diagnostic>>, StatusCode> {
+    let fail = |e: anyhow::Error| {
+        log!(Level::Error, "Failed to read diagnostics for {instance}: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    if !took_part(instance, musician, &server_state.db).await.map_err(fail)? {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    get_diagnostics(instance, &server_state.db)
+        .await
+        .map(Json)
+        .map_err(fail)
+}
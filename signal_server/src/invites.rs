@@ -0,0 +1,78 @@
+use protocol::{Invite, InviteRequest};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::log::{log, Level};
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthMusician,
+    database::{add_invite, get_invite},
+    lobby::JoinError,
+    ServerState,
+};
+
+/// Invites can't be valid for longer than a week
+const MAX_VALID_FOR: i64 = 7 * 24 * 60 * 60;
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Clock is before unix epoch")
+        .as_secs() as i64
+}
+
+/// Only the creator of a running session and those who performed in it may
+/// invite others
+pub async fn create_invite(
+    State(server_state): State<ServerState>,
+    AuthMusician(musician): AuthMusician,
+    Json(request): Json<InviteRequest>,
+) -> Result<Json<Invite>, StatusCode> {
+    if request.session.is_empty() || request.valid_for <= 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let instance = match server_state.invite_to(&request.session, musician).await {
+        Ok(instance) => instance,
+        Err(JoinError::Forbidden) => return Err(StatusCode::FORBIDDEN),
+        Err(JoinError::NotRunning) => return Err(StatusCode::NOT_FOUND),
+    };
+
+    let invite = Invite {
+        token: Uuid::new_v4().simple().to_string(),
+        session: request.session,
+        instance,
+        role: request.role,
+        expires_at: now() + request.valid_for.min(MAX_VALID_FOR),
+    };
+    add_invite(&invite, &server_state.db).await.map_err(|e| {
+        log!(Level::Error, "Failed to store invite: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(invite))
+}
+
+pub async fn show_invite(
+    State(server_state): State<ServerState>,
+    Path(token): Path<String>,
+) -> Result<Json<Invite>, StatusCode> {
+    match valid_invite(&token, &server_state).await? {
+        Some(invite) => Ok(Json(invite)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Looks up an invite that can still be used
+pub async fn valid_invite(
+    token: &str,
+    server_state: &ServerState,
+) -> Result<Option<Invite>, StatusCode> {
+    get_invite(token, now(), &server_state.db).await.map_err(|e| {
+        log!(Level::Error, "Failed to look up invite: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
@@ -0,0 +1,386 @@
+use protocol::{BandSummary, Invite, LobbyCommand, QualityPreset, Role, SessionInfo, SessionVisibility};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::collections::HashSet;
+use tokio::sync::broadcast::{channel, error::RecvError, Sender};
+use uuid::Uuid;
+use tracing::log::{log, Level};
+
+use crate::{
+    auth::{AuthMusician, MaybeMusician},
+    database::{get_band_summaries, get_musician_bands},
+    messages::BroadcastCommand,
+    ServerState,
+};
+
+/// The session a client asked to join, created on demand with these settings
+#[derive(Clone, Debug)]
+pub struct SessionRequest {
+    pub name: String,
+    pub visibility: SessionVisibility,
+    pub band: Option<i32>,
+    pub quality: QualityPreset,
+    /// Invites only get into the run of the session they were handed out in
+    pub invite: Option<Invite>,
+}
+
+/// Why a client was turned away
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JoinError {
+    /// Invites don't start sessions
+    NotRunning,
+    Forbidden,
+}
+
+/// What a client is told about the session it got into
+pub struct Joined {
+    pub tx: Sender<BroadcastCommand>,
+    pub quality: QualityPreset,
+    pub role: Role,
+    pub instance: Uuid,
+}
+
+/// A running session and the channel its members talk over
+#[derive(Clone, Debug)]
+pub struct SessionHandle {
+    pub tx: Sender<BroadcastCommand>,
+    /// Names get reused, this tells one run of a session from the next
+    pub id: Uuid,
+    pub info: SessionInfo,
+    /// The signed in musician who started the session
+    pub creator: Option<i32>,
+    /// Signed in musicians who have performed in the session
+    pub members: HashSet<i32>,
+}
+
+impl SessionHandle {
+    pub fn new(request: &SessionRequest, creator: Option<i32>) -> Self {
+        let (tx, _) = channel(10);
+        Self {
+            tx,
+            id: Uuid::new_v4(),
+            info: SessionInfo {
+                name: request.name.clone(),
+                visibility: request.visibility,
+                band: request.band,
+                quality: request.quality,
+                participants: 0,
+            },
+            creator,
+            members: HashSet::new(),
+        }
+    }
+
+    /// The creator and those who performed may bring others in
+    pub fn may_invite(&self, musician: i32) -> bool {
+        self.creator == Some(musician) || self.members.contains(&musician)
+    }
+
+    /// The creator, those who performed and the session's band perform
+    /// whichever way they come in
+    pub fn performs(&self, musician: Option<i32>, bands: &[i32]) -> bool {
+        musician.is_some_and(|m| self.may_invite(m))
+            || self.info.band.is_some_and(|band| bands.contains(&band))
+    }
+}
+
+/// Decides whether a client gets in and in what role, from the session and
+/// who the client is rather than from how it asked. `bands` are the bands
+/// the signed in musician plays in, `now` is in unix seconds.
+pub fn admission(
+    session: Option<&SessionHandle>,
+    request: &SessionRequest,
+    musician: Option<i32>,
+    bands: &[i32],
+    now: i64,
+) -> Result<Role, JoinError> {
+    let Some(session) = session else {
+        // Whoever starts a session performs in it, but somebody has to be
+        // able to invite others into a closed one
+        return match (&request.invite, request.visibility) {
+            (Some(_), _) => Err(JoinError::NotRunning),
+            (None, SessionVisibility::Public) => Ok(Role::Performer),
+            (None, SessionVisibility::Band) if request.band.is_some_and(|b| bands.contains(&b)) => {
+                Ok(Role::Performer)
+            }
+            (None, SessionVisibility::Private) if musician.is_some() => Ok(Role::Performer),
+            (None, _) => Err(JoinError::Forbidden),
+        };
+    };
+
+    if session.performs(musician, bands) {
+        return Ok(Role::Performer);
+    }
+    match (&request.invite, session.info.visibility) {
+        (Some(invite), _) if invite.instance != session.id || invite.expires_at <= now => {
+            Err(JoinError::Forbidden)
+        }
+        (Some(invite), _) => Ok(invite.role),
+        // Anyone may listen in on a public session, performing takes an invite
+        (None, SessionVisibility::Public) => Ok(Role::Listener),
+        (None, _) => Err(JoinError::Forbidden),
+    }
+}
+
+/// The bands a caller plays in, none for guests
+pub async fn bands_of(musician: Option<i32>, server_state: &ServerState) -> Result<Vec<i32>, StatusCode> {
+    let Some(id) = musician else {
+        return Ok(vec![]);
+    };
+    get_musician_bands(id, &server_state.db).await.map_err(|e| {
+        log!(Level::Error, "Failed to look up the bands of {id}: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// The bands the signed in musician can open band sessions for
+pub async fn list_bands(
+    State(server_state): State<ServerState>,
+    AuthMusician(musician): AuthMusician,
+) -> Result<Json<Vec<BandSummary>>, StatusCode> {
+    get_band_summaries(musician, &server_state.db)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            log!(Level::Error, "Failed to list the bands of {musician}: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Lists the sessions that are currently running and visible to the caller
+pub async fn list_sessions(
+    State(server_state): State<ServerState>,
+    MaybeMusician(musician): MaybeMusician,
+) -> Result<Json<Vec<SessionInfo>>, StatusCode> {
+    let bands = bands_of(musician, &server_state).await?;
+    Ok(Json(server_state.list_sessions(&bands).await))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LobbyParams {
+    /// Access token from the identity provider, browsers can't set headers
+    /// on websockets
+    token: Option<String>,
+}
+
+/// Pushes lobby changes to the client until it disconnects. Band sessions
+/// are only shown to signed in members of the band.
+pub async fn lobby_handler(
+    State(server_state): State<ServerState>,
+    Query(params): Query<LobbyParams>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let musician = match &params.token {
+        Some(token) => match server_state.auth.musician(token, &server_state.db).await {
+            Ok(Some(id)) => Some(id),
+            Ok(None) => return (StatusCode::UNAUTHORIZED, "Invalid access token").into_response(),
+            Err(status) => return status.into_response(),
+        },
+        None => None,
+    };
+    let bands = match bands_of(musician, &server_state).await {
+        Ok(bands) => bands,
+        Err(status) => return status.into_response(),
+    };
+    ws.on_upgrade(move |socket| handle_lobby(server_state, socket, bands))
+        .into_response()
+}
+
+async fn handle_lobby(server_state: ServerState, mut socket: WebSocket, bands: Vec<i32>) {
+    // Subscribe before listing so no change falls in between
+    let mut rx_lobby = server_state.lobby.subscribe();
+    let sessions = LobbyCommand::Sessions(server_state.list_sessions(&bands).await);
+    if socket
+        .send(Message::Text(serde_json::to_string(&sessions).unwrap()))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            command = socket.recv() => {
+                match command {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    _ => {}
+                }
+            }
+
+            command = rx_lobby.recv() => {
+                let command = match command {
+                    Ok(LobbyCommand::SessionChanged(info)) if !info.visible_to(&bands) => {
+                        // Tell the client in case the session used to be visible
+                        LobbyCommand::SessionClosed(info.name)
+                    }
+                    Ok(command) => command,
+                    Err(RecvError::Closed) => break,
+                    Err(e) => {
+                        log!(Level::Warn, "Lobby subscriber lagging: {e}");
+                        LobbyCommand::Sessions(server_state.list_sessions(&bands).await)
+                    }
+                };
+                if socket.send(Message::Text(serde_json::to_string(&command).unwrap())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    log!(Level::Info, "Lobby subscriber left");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn request(visibility: SessionVisibility, band: Option<i32>) -> SessionRequest {
+        SessionRequest {
+            name: String::from("rehearsal"),
+            visibility,
+            band,
+            quality: QualityPreset::default(),
+            invite: None,
+        }
+    }
+
+    fn invite(session: &SessionHandle, role: Role, expires_at: i64) -> SessionRequest {
+        SessionRequest {
+            invite: Some(Invite {
+                token: String::from("token"),
+                session: session.info.name.clone(),
+                instance: session.id,
+                role,
+                expires_at,
+            }),
+            ..request(SessionVisibility::Private, None)
+        }
+    }
+
+    /// A session started by musician 1 in `band`
+    fn running(visibility: SessionVisibility, band: Option<i32>) -> SessionHandle {
+        SessionHandle::new(&request(visibility, band), Some(1))
+    }
+
+    fn info(visibility: SessionVisibility, band: Option<i32>) -> SessionInfo {
+        running(visibility, band).info
+    }
+
+    #[test]
+    fn public_sessions_are_listed_for_everyone() {
+        assert!(info(SessionVisibility::Public, None).visible_to(&[]));
+        assert!(info(SessionVisibility::Public, Some(7)).visible_to(&[3]));
+    }
+
+    #[test]
+    fn band_sessions_are_listed_for_members_only() {
+        let session = info(SessionVisibility::Band, Some(7));
+        assert!(session.visible_to(&[3, 7]));
+        assert!(!session.visible_to(&[3]));
+        assert!(!session.visible_to(&[]));
+    }
+
+    #[test]
+    fn band_sessions_without_a_band_are_listed_for_nobody() {
+        let session = info(SessionVisibility::Band, None);
+        assert!(!session.visible_to(&[]));
+        assert!(!session.visible_to(&[7]));
+    }
+
+    #[test]
+    fn private_sessions_are_never_listed() {
+        assert!(!info(SessionVisibility::Private, None).visible_to(&[]));
+        assert!(!info(SessionVisibility::Private, Some(7)).visible_to(&[7]));
+    }
+
+    #[test]
+    fn anyone_starts_a_public_session() {
+        let public = request(SessionVisibility::Public, None);
+        assert_eq!(admission(None, &public, None, &[], NOW), Ok(Role::Performer));
+    }
+
+    #[test]
+    fn private_sessions_are_started_signed_in_and_joined_by_invite() {
+        let private = request(SessionVisibility::Private, None);
+        assert_eq!(admission(None, &private, None, &[], NOW), Err(JoinError::Forbidden));
+        assert_eq!(admission(None, &private, Some(1), &[], NOW), Ok(Role::Performer));
+
+        let session = running(SessionVisibility::Private, None);
+        assert_eq!(admission(Some(&session), &private, Some(1), &[], NOW), Ok(Role::Performer));
+        assert_eq!(admission(Some(&session), &private, Some(2), &[], NOW), Err(JoinError::Forbidden));
+        assert_eq!(admission(Some(&session), &private, None, &[], NOW), Err(JoinError::Forbidden));
+    }
+
+    #[test]
+    fn band_sessions_take_the_band() {
+        let band = request(SessionVisibility::Band, Some(7));
+        assert_eq!(admission(None, &band, Some(2), &[7], NOW), Ok(Role::Performer));
+        assert_eq!(admission(None, &band, Some(2), &[3], NOW), Err(JoinError::Forbidden));
+        assert_eq!(admission(None, &band, None, &[], NOW), Err(JoinError::Forbidden));
+
+        let session = running(SessionVisibility::Band, Some(7));
+        assert_eq!(admission(Some(&session), &band, Some(2), &[7], NOW), Ok(Role::Performer));
+        assert_eq!(admission(Some(&session), &band, Some(2), &[3], NOW), Err(JoinError::Forbidden));
+    }
+
+    #[test]
+    fn band_sessions_without_a_band_take_nobody() {
+        let band = request(SessionVisibility::Band, None);
+        assert_eq!(admission(None, &band, Some(2), &[], NOW), Err(JoinError::Forbidden));
+        assert_eq!(admission(None, &band, Some(2), &[7], NOW), Err(JoinError::Forbidden));
+
+        let session = running(SessionVisibility::Band, None);
+        assert_eq!(admission(Some(&session), &band, Some(2), &[], NOW), Err(JoinError::Forbidden));
+    }
+
+    #[test]
+    fn others_listen_in_on_public_sessions() {
+        let public = request(SessionVisibility::Public, None);
+        let mut session = running(SessionVisibility::Public, None);
+        assert_eq!(admission(Some(&session), &public, None, &[], NOW), Ok(Role::Listener));
+        assert_eq!(admission(Some(&session), &public, Some(2), &[], NOW), Ok(Role::Listener));
+
+        // Once performed, always performing
+        session.members.insert(2);
+        assert_eq!(admission(Some(&session), &public, Some(2), &[], NOW), Ok(Role::Performer));
+    }
+
+    #[test]
+    fn invites_grant_their_role() {
+        let session = running(SessionVisibility::Private, None);
+        let performer = invite(&session, Role::Performer, NOW + 60);
+        let listener = invite(&session, Role::Listener, NOW + 60);
+        assert_eq!(admission(Some(&session), &performer, None, &[], NOW), Ok(Role::Performer));
+        assert_eq!(admission(Some(&session), &listener, None, &[], NOW), Ok(Role::Listener));
+        // The creator performs whatever the invite says
+        assert_eq!(admission(Some(&session), &listener, Some(1), &[], NOW), Ok(Role::Performer));
+    }
+
+    #[test]
+    fn expired_invites_are_refused() {
+        let session = running(SessionVisibility::Private, None);
+        let expired = invite(&session, Role::Performer, NOW);
+        assert_eq!(admission(Some(&session), &expired, None, &[], NOW), Err(JoinError::Forbidden));
+    }
+
+    #[test]
+    fn invites_only_get_into_their_run() {
+        let ended = running(SessionVisibility::Private, None);
+        let stale = invite(&ended, Role::Performer, NOW + 60);
+        assert_eq!(admission(None, &stale, None, &[], NOW), Err(JoinError::NotRunning));
+
+        // A new session under the same name
+        let session = running(SessionVisibility::Private, None);
+        assert_eq!(admission(Some(&session), &stale, None, &[], NOW), Err(JoinError::Forbidden));
+    }
+}
@@ -1,55 +1,129 @@
+mod auth;
 mod database;
+mod diagnostics;
+mod invites;
+mod lobby;
 mod server;
 mod messages;
+mod scenes;
 
 use axum::{
-    routing::get,
+    routing::{get, post, put},
     Router,
 };
+use lobby::{admission, JoinError, Joined, SessionHandle, SessionRequest};
 use messages::BroadcastCommand;
+use protocol::{LobbyCommand, Role, SessionInfo};
 use sqlx::{Sqlite, Pool};
 use std::{net::SocketAddr, sync::Arc, collections::HashMap};
 use tower_http::{
+    cors::CorsLayer,
     trace::{DefaultMakeSpan, TraceLayer},
 };
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use tracing::log::{log, Level};
 use tokio::sync::{mpsc, RwLock};
 use tokio::sync::broadcast::{Sender, channel};
+use uuid::Uuid;
 
+use crate::auth::Authenticator;
 use crate::database::setup_database;
+use crate::diagnostics::{session_timeline, spawn_writer, StoredDiagnostic};
+use crate::invites::{create_invite, now, show_invite};
+use crate::lobby::{list_bands, list_sessions, lobby_handler};
 use crate::server::ws_handler;
 use crate::messages::DirectCommand;
+use crate::scenes::{list_scenes, remove_scene, save_scene};
 
 
 
 
-type SessionMap = Arc<RwLock<HashMap<String, Sender<BroadcastCommand>>>>;
+type SessionMap = Arc<RwLock<HashMap<String, SessionHandle>>>;
 
 #[derive(Clone)]
 pub struct ServerState {
     sessions: SessionMap,
-    db: Pool<Sqlite>
+    lobby: Sender<LobbyCommand>,
+    db: Pool<Sqlite>,
+    auth: Authenticator,
+    /// Stores quality samples without holding up signaling
+    diagnostics: mpsc::Sender<StoredDiagnostic>,
 }
 
 impl ServerState {
     fn new(db: Pool<Sqlite>) -> Self {
         let sessions = Arc::new(RwLock::new(HashMap::new()));
+        let (lobby, _) = channel(32);
         Self{
             sessions,
-            db
+            lobby,
+            diagnostics: spawn_writer(db.clone()),
+            db,
+            auth: Authenticator::new(),
         }
     }
 
-    async fn join_session(&mut self, name: String) -> Sender<BroadcastCommand> {
-        if self.sessions.read().await.contains_key(&name) {
-            self.sessions.read().await.get(&name).expect("Missing session sender").clone() 
+    /// Whether a join would be let in, checked before upgrading the socket
+    async fn check_join(&self, request: &SessionRequest, musician: Option<i32>, bands: &[i32]) -> Result<Role, JoinError> {
+        let sessions = self.sessions.read().await;
+        admission(sessions.get(&request.name), request, musician, bands, now())
+    }
+
+    /// Returns the session channel, the quality the session runs at, which
+    /// was decided by whoever created it, and the role to join in
+    async fn join_session(&mut self, request: &SessionRequest, musician: Option<i32>, bands: &[i32]) -> Result<Joined, JoinError> {
+        let mut sessions = self.sessions.write().await;
+        let role = admission(sessions.get(&request.name), request, musician, bands, now())?;
+        let session = sessions
+            .entry(request.name.clone())
+            .or_insert_with(|| SessionHandle::new(request, musician));
+        session.info.participants += 1;
+        if let (Some(musician), Role::Performer) = (musician, role) {
+            session.members.insert(musician);
+        }
+
+        // Nobody might be watching the lobby, that is fine
+        let _ = self.lobby.send(LobbyCommand::SessionChanged(session.info.clone()));
+        Ok(Joined {
+            tx: session.tx.clone(),
+            quality: session.info.quality,
+            role,
+            instance: session.id,
+        })
+    }
+
+    /// The run of a session a musician may hand out invites to
+    async fn invite_to(&self, session: &str, musician: i32) -> Result<Uuid, JoinError> {
+        let sessions = self.sessions.read().await;
+        match sessions.get(session) {
+            Some(session) if session.may_invite(musician) => Ok(session.id),
+            Some(_) => Err(JoinError::Forbidden),
+            None => Err(JoinError::NotRunning),
+        }
+    }
+
+    async fn leave_session(&mut self, name: &str) {
+        let mut sessions = self.sessions.write().await;
+        let Some(session) = sessions.get_mut(name) else {
+            return;
+        };
+        session.info.participants = session.info.participants.saturating_sub(1);
+        if session.info.participants == 0 {
+            sessions.remove(name);
+            let _ = self.lobby.send(LobbyCommand::SessionClosed(name.to_string()));
         } else {
-            let (sender, _) = channel(10);
-            self.sessions.write().await.insert(name, sender.clone());
-            sender
+            let _ = self.lobby.send(LobbyCommand::SessionChanged(session.info.clone()));
         }
+    }
 
+    async fn list_sessions(&self, bands: &[i32]) -> Vec<SessionInfo> {
+        self.sessions
+            .read()
+            .await
+            .values()
+            .map(|session| session.info.clone())
+            .filter(|info| info.visible_to(bands))
+            .collect()
     }
 }
 
@@ -67,6 +141,15 @@ async fn main() {
     let db = setup_database().await.unwrap();
     let app = Router::new()
         .route("/ws", get(ws_handler))
+        .route("/lobby", get(lobby_handler))
+        .route("/sessions", get(list_sessions))
+        .route("/bands", get(list_bands))
+        .route("/sessions/:instance/diagnostics", get(session_timeline))
+        .route("/bands/:band/scenes", get(list_scenes))
+        .route("/bands/:band/scenes/:name", put(save_scene).delete(remove_scene))
+        .route("/invites", post(create_invite))
+        .route("/invites/:token", get(show_invite))
+        .layer(CorsLayer::permissive())
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::default().include_headers(true)),
@@ -1,4 +1,4 @@
-use protocol::{ClientCommand, ServerCommand};
+use protocol::{ClientCommand, IceCandidate, MemberProfile, Role, ServerCommand};
 
 use axum::extract::ws::Message;
 use tokio::sync::mpsc;
@@ -14,6 +14,12 @@ pub enum BroadcastCommand {
     HelloFrom {
         uuid: uuid::Uuid,
         tx: mpsc::Sender<DirectCommand>,
+        profile: MemberProfile,
+        role: Role,
+    },
+    ProfileFrom {
+        uuid: uuid::Uuid,
+        profile: MemberProfile,
     },
     GoodbyFrom {
         uuid: uuid::Uuid,
@@ -25,10 +31,17 @@ pub enum DirectCommand {
     WelcomeFrom {
         uuid: uuid::Uuid,
         tx: mpsc::Sender<DirectCommand>,
+        profile: MemberProfile,
+        role: Role,
     },
 
     CreateOfferFor {
         uuid: uuid::Uuid,
+        ice_restart: bool,
+    },
+
+    ResetConnectionFrom {
+        uuid: uuid::Uuid,
     },
 
     CreateAnswerFor {
@@ -43,7 +56,7 @@ pub enum DirectCommand {
 
     GetIceFrom {
         uuid: uuid::Uuid,
-        ice: String,
+        ice: Option<IceCandidate>,
     },
 }
 
@@ -0,0 +1,63 @@
+use protocol::mixer::{MixerScene, MixerState};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use tracing::log::{log, Level};
+
+use crate::{
+    auth::AuthMusician,
+    database::{delete_scene, get_scenes, put_scene},
+    ServerState,
+};
+
+/// Mixer scenes the signed in musician saved for a band, so they follow
+/// them between machines
+pub async fn list_scenes(
+    State(server_state): State<ServerState>,
+    AuthMusician(musician): AuthMusician,
+    Path(band): Path<i32>,
+) -> Result<Json<Vec<MixerScene>>, StatusCode> {
+    get_scenes(musician, band, &server_state.db)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            log!(Level::Error, "Failed to read scenes of {musician} in {band}: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+pub async fn save_scene(
+    State(server_state): State<ServerState>,
+    AuthMusician(musician): AuthMusician,
+    Path((band, name)): Path<(i32, String)>,
+    Json(mixer): Json<MixerState>,
+) -> Result<StatusCode, StatusCode> {
+    if name.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let scene = MixerScene { name, mixer };
+    put_scene(musician, band, &scene, &server_state.db)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(|e| {
+            log!(Level::Error, "Failed to store scene {}: {e}", scene.name);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+pub async fn remove_scene(
+    State(server_state): State<ServerState>,
+    AuthMusician(musician): AuthMusician,
+    Path((band, name)): Path<(i32, String)>,
+) -> Result<StatusCode, StatusCode> {
+    delete_scene(musician, band, &name, &server_state.db)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(|e| {
+            log!(Level::Error, "Failed to delete scene {name}: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
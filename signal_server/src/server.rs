@@ -1,56 +1,208 @@
 /// See https://github.com/tokio-rs/axum/blob/main/examples/websockets/src/main.rs for original
 ///
-use protocol::{ClientCommand, ServerCommand};
+use protocol::{
+    sdp::SessionDescription, ClientCommand, Diagnostic, MemberProfile, QualityPreset, Role, ServerCommand,
+    SessionVisibility,
+};
 
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
+        Query, State,
     },
     headers,
-    response::IntoResponse,
+    http::StatusCode,
+    response::{IntoResponse, Response},
     TypedHeader,
 };
-use tokio::sync::mpsc::{channel, Sender};
+use serde::Deserialize;
+use tokio::sync::{
+    broadcast,
+    mpsc::{channel, Sender},
+};
 use tracing::log::{log, Level};
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::ops::ControlFlow;
+use std::time::Instant;
 use uuid::Uuid;
 
 //allows to extract the IP of connecting user
 use axum::extract::connect_info::ConnectInfo;
 
 use crate::{
+    database::{get_musician, update_musician_profile},
+    diagnostics::{StoredDiagnostic, MIN_SAMPLE_GAP},
+    invites::{now, valid_invite},
+    lobby::{bands_of, JoinError, Joined, SessionRequest},
     messages::{process_message, BroadcastCommand, DirectCommand},
     ServerState,
 };
 
+/// Who is connecting, either a signed in musician or a named guest. Guests
+/// holding an invite get the session and role from it instead.
+#[derive(Deserialize, Debug)]
+pub struct JoinParams {
+    invite: Option<String>,
+    /// Access token from the identity provider, browsers can't set headers
+    /// on websockets
+    token: Option<String>,
+    name: Option<String>,
+    session: Option<String>,
+    visibility: Option<SessionVisibility>,
+    band: Option<i32>,
+    quality: Option<QualityPreset>,
+}
+
 pub async fn ws_handler(
     State(server_state): State<ServerState>,
+    Query(params): Query<JoinParams>,
     ws: WebSocketUpgrade,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
+) -> Response {
     let user_agent = if let Some(TypedHeader(user_agent)) = user_agent {
         user_agent.to_string()
     } else {
         String::from("Unknown browser")
     };
     log!(Level::Info, "`{}` at {} connected.", user_agent, addr);
+
+    // Only a musician proven by the identity provider gets their stored
+    // profile, and may change it
+    let musician = match &params.token {
+        Some(token) => match server_state.auth.musician(token, &server_state.db).await {
+            Ok(Some(id)) => Some(id),
+            Ok(None) => return (StatusCode::UNAUTHORIZED, "Invalid access token").into_response(),
+            Err(status) => return status.into_response(),
+        },
+        None => None,
+    };
+
+    // Look up the profile that will be presented to the other session members
+    let musician = match musician {
+        Some(id) => get_musician(id, &server_state.db).await.unwrap_or_else(|e| {
+            log!(Level::Error, "Failed to look up musician {id}: {e}");
+            None
+        }),
+        None => None,
+    };
+    let musician_id = musician.as_ref().map(|m| m.id);
+    let profile = match musician {
+        Some(musician) => MemberProfile::from(musician),
+        None => MemberProfile::guest(params.name.filter(|name| !name.trim().is_empty())),
+    };
+
+    // Guests with an invite get into the invited session in the role it
+    // grants, everyone else asks for a session by name
+    let session = match params.invite {
+        Some(token) => {
+            let invite = match valid_invite(&token, &server_state).await {
+                Ok(Some(invite)) => invite,
+                Ok(None) => {
+                    log!(Level::Warn, "{addr} tried to use an invalid invite");
+                    return (StatusCode::FORBIDDEN, "Invalid or expired invite").into_response();
+                }
+                Err(status) => return status.into_response(),
+            };
+            SessionRequest {
+                name: invite.session.clone(),
+                visibility: SessionVisibility::Private,
+                band: None,
+                quality: QualityPreset::default(),
+                invite: Some(invite),
+            }
+        }
+        None => SessionRequest {
+            name: params.session.unwrap_or_else(|| String::from("hej")),
+            visibility: params.visibility.unwrap_or_default(),
+            band: params.band,
+            quality: params.quality.unwrap_or_default(),
+            invite: None,
+        },
+    };
+    let bands = match bands_of(musician_id, &server_state).await {
+        Ok(bands) => bands,
+        Err(status) => return status.into_response(),
+    };
+    match server_state.check_join(&session, musician_id, &bands).await {
+        Ok(_) => {}
+        Err(JoinError::NotRunning) => {
+            return (StatusCode::NOT_FOUND, "The session isn't running").into_response()
+        }
+        Err(JoinError::Forbidden) => {
+            log!(Level::Warn, "{addr} may not join {}", session.name);
+            return (StatusCode::FORBIDDEN, "Not allowed to join this session").into_response();
+        }
+    }
+    let identity = Identity {
+        musician: musician_id,
+        profile,
+        bands,
+    };
+
     // finalize the upgrade process by returning upgrade callback.
     // we can customize the callback by sending additional info such as address.
-    ws.on_upgrade(move |socket| handle_socket(server_state, socket, addr))
+    ws.on_upgrade(move |socket| handle_socket(server_state, socket, addr, session, identity))
+        .into_response()
+}
+
+/// How a connected client is known for the lifetime of the socket
+struct Identity {
+    musician: Option<i32>,
+    profile: MemberProfile,
+    bands: Vec<i32>,
+}
+
+/// Perfect negotiation needs exactly one polite peer per pair. Both ends
+/// compare the same two ids, so they always agree on who it is.
+fn is_polite(me: Uuid, peer: Uuid) -> bool {
+    me < peer
+}
+
+/// Offers and answers are only passed on if they are well formed SDP of a
+/// sensible size
+fn valid_description(who: &SocketAddr, sdp: &str) -> bool {
+    match SessionDescription::validate(sdp) {
+        Ok(_) => true,
+        Err(e) => {
+            log!(Level::Warn, "Dropping session description from {who}: {e}");
+            false
+        }
+    }
 }
 
 /// Actual websocket statemachine (one will be spawned per connection)
-async fn handle_socket(mut server_state: ServerState, mut socket: WebSocket, who: SocketAddr) {
+async fn handle_socket(
+    mut server_state: ServerState,
+    mut socket: WebSocket,
+    who: SocketAddr,
+    session: SessionRequest,
+    identity: Identity,
+) {
     let my_uuid = Uuid::new_v4();
     let db = server_state.db.clone();
+    let musician_id = identity.musician;
+    let mut my_profile = identity.profile;
 
-    // Client must specify what session it wants to join
-    let tx_session = server_state.join_session(String::from("hej")).await;
+    // Client must specify what session it wants to join, things may have
+    // changed since the check before upgrading
+    let joined = server_state.join_session(&session, musician_id, &identity.bands).await;
+    let Joined { tx: tx_session, quality, role: my_role, instance } = match joined {
+        Ok(joined) => joined,
+        Err(e) => {
+            log!(Level::Warn, "{who} can't join {} after all: {e:?}", session.name);
+            return;
+        }
+    };
+    log!(Level::Info, "{who} joins {} as {}", session.name, my_role.as_str());
+    let _departure = Departure {
+        server_state: server_state.clone(),
+        tx: tx_session.clone(),
+        session: session.name.clone(),
+        uuid: my_uuid,
+    };
 
     // If allowed client will introduce itself to the memebers of the session, allowing them direct
     // serverside communication.
@@ -58,18 +210,19 @@ async fn handle_socket(mut server_state: ServerState, mut socket: WebSocket, who
     let hello = BroadcastCommand::HelloFrom {
         uuid: my_uuid.clone(),
         tx: tx_direct.clone(),
+        profile: my_profile.clone(),
+        role: my_role,
     };
     if tx_session.send(hello).is_err() {
         log!(Level::Info, "{who} is first to join");
     }
+    socket.send(Message::Text(serde_json::to_string(&ServerCommand::Joined(my_uuid, my_role, quality, instance)).unwrap())).await;
 
     // Start listening for session and socket updates
     let mut rx_session = tx_session.subscribe();
-    let introduction = DirectCommand::WelcomeFrom {
-        uuid: my_uuid.clone(),
-        tx: tx_direct.clone(),
-    };
     let mut participants = HashMap::<Uuid, Sender<DirectCommand>>::new();
+    // When the client last sent a sample about each peer
+    let mut last_samples = HashMap::<Uuid, Instant>::new();
     loop {
         tokio::select! {
             // Deal with incoming messages from the client
@@ -81,6 +234,10 @@ async fn handle_socket(mut server_state: ServerState, mut socket: WebSocket, who
                             ControlFlow::Break(()) => break,
                             ControlFlow::Continue(command) => {
                                 match command {
+                                    ClientCommand::Offer(_, sdp) | ClientCommand::Answer(_, sdp) if !valid_description(&who, &sdp) => {},
+                                    ClientCommand::Offer(uuid, _) if my_role == Role::Listener => {
+                                        log!(Level::Warn, "Listener {who} tried to send an offer to {uuid}");
+                                    },
                                     ClientCommand::Offer(uuid, offer) => {
                                         if let Some(tx) = participants.get(&uuid) {
                                             let tx = tx.clone();
@@ -93,16 +250,93 @@ async fn handle_socket(mut server_state: ServerState, mut socket: WebSocket, who
 
                                     },
                                     ClientCommand::Answer(uuid, answer) => {
-                                        let tx = participants.get(&uuid).expect(&format!("Missing session participant {uuid}")).clone();
-                                        tokio::spawn( async move {
-                                            tx.send(DirectCommand::GetAnswerFrom { uuid: my_uuid, answer }).await;
-                                        });
+                                        // The peer may have left while this was under way
+                                        if let Some(tx) = participants.get(&uuid) {
+                                            let tx = tx.clone();
+                                            tokio::spawn( async move {
+                                                tx.send(DirectCommand::GetAnswerFrom { uuid: my_uuid, answer }).await;
+                                            });
+                                        } else {
+                                            log!(Level::Warn, "Missing participant {uuid}");
+                                        }
                                     }
                                     ClientCommand::IceCandidate(uuid, ice) => {
-                                        let tx = participants.get(&uuid).expect(&format!("Missing session participant {uuid}")).clone();
-                                        tokio::spawn( async move {
-                                            tx.send(DirectCommand::GetIceFrom { uuid: my_uuid, ice}).await;
-                                        });
+                                        // The peer may have left while this was under way
+                                        if let Some(tx) = participants.get(&uuid) {
+                                            let tx = tx.clone();
+                                            tokio::spawn( async move {
+                                                tx.send(DirectCommand::GetIceFrom { uuid: my_uuid, ice}).await;
+                                            });
+                                        } else {
+                                            log!(Level::Warn, "Missing participant {uuid}");
+                                        }
+                                    }
+                                    ClientCommand::RenegotiationNeeded(uuid) | ClientCommand::RestartIce(uuid) => {
+                                        let ice_restart = matches!(command, ClientCommand::RestartIce(_));
+                                        // Listeners can't offer, so they ask the performer to do it
+                                        if let Some(tx) = participants.get(&uuid) {
+                                            if my_role == Role::Performer {
+                                                let command = if ice_restart {
+                                                    ServerCommand::RestartIce(uuid)
+                                                } else {
+                                                    ServerCommand::CreateOffer(uuid)
+                                                };
+                                                socket.send(Message::Text(serde_json::to_string(&command).unwrap())).await;
+                                            } else {
+                                                let tx = tx.clone();
+                                                tokio::spawn( async move {
+                                                    tx.send(DirectCommand::CreateOfferFor { uuid: my_uuid, ice_restart }).await;
+                                                });
+                                            }
+                                        } else {
+                                            log!(Level::Warn, "Missing participant");
+                                        }
+                                    }
+                                    ClientCommand::ResetConnection(uuid) => {
+                                        // Not spawned, the reset has to arrive before any new offer
+                                        if let Some(tx) = participants.get(&uuid) {
+                                            tx.send(DirectCommand::ResetConnectionFrom { uuid: my_uuid }).await;
+                                        } else {
+                                            log!(Level::Warn, "Missing participant");
+                                        }
+                                    }
+                                    ClientCommand::UpdateProfile(profile) if profile.name.trim().is_empty() => {
+                                        log!(Level::Warn, "Ignoring profile without a name from {who}");
+                                    }
+                                    ClientCommand::UpdateProfile(profile) => {
+                                        if let Some(id) = musician_id {
+                                            if let Err(e) = update_musician_profile(id, &profile, &db).await {
+                                                log!(Level::Error, "Failed to store profile: {e}");
+                                            }
+                                        }
+                                        my_profile = profile.clone();
+                                        tx_session.send(BroadcastCommand::ProfileFrom { uuid: my_uuid, profile });
+                                    }
+                                    ClientCommand::Diagnostics(peer, _) if !participants.contains_key(&peer) => {
+                                        log!(Level::Warn, "Dropped diagnostics from {who} about unknown peer {peer}");
+                                    }
+                                    ClientCommand::Diagnostics(peer, _) if last_samples.get(&peer).is_some_and(|at| at.elapsed() < MIN_SAMPLE_GAP) => {
+                                        log!(Level::Warn, "Dropped diagnostics from {who} about {peer}, too soon after the last");
+                                    }
+                                    ClientCommand::Diagnostics(peer, sample) => {
+                                        last_samples.insert(peer, Instant::now());
+                                        let diagnostic = Diagnostic {
+                                            participant: my_uuid,
+                                            name: my_profile.name.clone(),
+                                            peer,
+                                            recorded_at: now(),
+                                            sample,
+                                        };
+                                        let stored = StoredDiagnostic {
+                                            instance,
+                                            session: session.name.clone(),
+                                            musician: musician_id,
+                                            diagnostic,
+                                        };
+                                        // Samples are only worth so much, signaling comes first
+                                        if let Err(e) = server_state.diagnostics.try_send(stored) {
+                                            log!(Level::Warn, "Dropped diagnostics from {who}: {e}");
+                                        }
                                     }
                                 }
                             }
@@ -118,16 +352,38 @@ async fn handle_socket(mut server_state: ServerState, mut socket: WebSocket, who
             // Keep track of session members
             Ok(command) = rx_session.recv() => {
                 match command {
-                    BroadcastCommand::HelloFrom{uuid ,tx} => {
-                        tx.send(introduction.clone()).await;
+                    // Listeners don't send anything, so two of them have nothing to exchange.
+                    // They don't get to know each other and never set up a connection.
+                    BroadcastCommand::HelloFrom{ role: Role::Listener, .. } if my_role == Role::Listener => {}
+                    BroadcastCommand::HelloFrom{uuid ,tx, profile, role} => {
+                        let introduction = DirectCommand::WelcomeFrom {
+                            uuid: my_uuid.clone(),
+                            tx: tx_direct.clone(),
+                            profile: my_profile.clone(),
+                            role: my_role,
+                        };
+                        tx.send(introduction).await;
+                        let polite = is_polite(my_uuid, uuid);
+                        socket.send(Message::Text(serde_json::to_string(&ServerCommand::AddMember(uuid.clone(), polite, profile, role)).unwrap())).await;
+
+                        // Only performers send audio, so they are the ones making offers
+                        if my_role == Role::Performer {
+                            socket.send(Message::Text(serde_json::to_string(&ServerCommand::CreateOffer(uuid.clone())).unwrap())).await;
+                        } else if role == Role::Performer {
+                            tx.send(DirectCommand::CreateOfferFor { uuid: my_uuid, ice_restart: false }).await;
+                        }
                         participants.insert(uuid, tx);
-                        let polite = true;
-                        socket.send(Message::Text(serde_json::to_string(&ServerCommand::AddMember(uuid.clone(), polite)).unwrap())).await;
-                        socket.send(Message::Text(serde_json::to_string(&ServerCommand::CreateOffer(uuid.clone())).unwrap())).await;
+                    }
+                    BroadcastCommand::ProfileFrom{uuid, profile} => {
+                        if uuid != my_uuid {
+                            socket.send(Message::Text(serde_json::to_string(&ServerCommand::UpdateMember(uuid, profile)).unwrap())).await;
+                        }
                     }
                     BroadcastCommand::GoodbyFrom{uuid} => {
-                        socket.send(Message::Text(serde_json::to_string(&ServerCommand::DropMember(uuid)).unwrap())).await;
-                        participants.remove(&uuid);
+                        last_samples.remove(&uuid);
+                        if participants.remove(&uuid).is_some() {
+                            socket.send(Message::Text(serde_json::to_string(&ServerCommand::DropMember(uuid)).unwrap())).await;
+                        }
                     }
                 }
             }
@@ -135,13 +391,21 @@ async fn handle_socket(mut server_state: ServerState, mut socket: WebSocket, who
             // Let others trigger outgoing traffic to client
             Some(command) = rx_direct.recv() => {
                 match command {
-                    DirectCommand::WelcomeFrom { uuid, tx} => {
+                    DirectCommand::WelcomeFrom { uuid, tx, profile, role} => {
                         participants.insert(uuid, tx);
-                        let polite = false;
-                        socket.send(Message::Text(serde_json::to_string(&ServerCommand::AddMember(uuid.clone(), polite)).unwrap())).await;
+                        let polite = is_polite(my_uuid, uuid);
+                        socket.send(Message::Text(serde_json::to_string(&ServerCommand::AddMember(uuid.clone(), polite, profile, role)).unwrap())).await;
                     },
-                    DirectCommand::CreateOfferFor { uuid } => {
-                        let command = ServerCommand::CreateOffer(uuid);
+                    DirectCommand::CreateOfferFor { uuid, ice_restart } => {
+                        let command = if ice_restart {
+                            ServerCommand::RestartIce(uuid)
+                        } else {
+                            ServerCommand::CreateOffer(uuid)
+                        };
+                        socket.send(Message::Text(serde_json::to_string(&command).unwrap())).await;
+                    },
+                    DirectCommand::ResetConnectionFrom { uuid } => {
+                        let command = ServerCommand::ResetConnection(uuid);
                         socket.send(Message::Text(serde_json::to_string(&command).unwrap())).await;
                     },
                     DirectCommand::CreateAnswerFor { uuid, offer} => {
@@ -163,6 +427,25 @@ async fn handle_socket(mut server_state: ServerState, mut socket: WebSocket, who
 
         }
     }
-    tx_session.send(BroadcastCommand::GoodbyFrom { uuid: my_uuid });
     log!(Level::Info, "Websocket context {who} destroyed");
 }
+
+/// Says goodbye to the other members and leaves the session however the
+/// socket task ends, panics included, so the session doesn't outlive its
+/// last member
+struct Departure {
+    server_state: ServerState,
+    tx: broadcast::Sender<BroadcastCommand>,
+    session: String,
+    uuid: Uuid,
+}
+
+impl Drop for Departure {
+    fn drop(&mut self) {
+        // Nobody else might be left to hear it
+        let _ = self.tx.send(BroadcastCommand::GoodbyFrom { uuid: self.uuid });
+        let mut server_state = self.server_state.clone();
+        let session = std::mem::take(&mut self.session);
+        tokio::spawn(async move { server_state.leave_session(&session).await });
+    }
+}
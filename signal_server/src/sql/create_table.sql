@@ -1,7 +1,11 @@
 create table if not exists
 musicians (
   id integer primary key not null,
-  name text
+  name text,
+  instrument text,
+  avatar text,
+  -- Who the identity provider says the musician is
+  subject text
 );
 
 create table if not exists
@@ -11,5 +15,44 @@ band (
   member integer,
   primary key (id),
   foreign key (member)
-    refrences musicians (id)
+    references musicians (id)
+);
+
+create table if not exists
+invites (
+  token text primary key not null,
+  session text not null,
+  instance text,
+  role text not null,
+  expires_at integer not null
+);
+
+create table if not exists
+diagnostics (
+  id integer primary key autoincrement,
+  instance text,
+  session text not null,
+  musician integer,
+  participant text not null,
+  name text not null,
+  peer text not null,
+  recorded_at integer not null,
+  round_trip_time real,
+  jitter real,
+  packet_loss real,
+  jitter_buffer_delay real,
+  bitrate_in real,
+  bitrate_out real,
+  local_candidate text,
+  remote_candidate text,
+  codec text
+);
+
+create table if not exists
+scenes (
+  musician integer not null,
+  band integer not null,
+  name text not null,
+  mixer text not null,
+  primary key (musician, band, name)
 );